@@ -1,70 +1,381 @@
 use anyhow::{Context, Result};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 use walkdir::WalkDir;
 
-use crate::config;
+use crate::config::{self, AppDir};
+use crate::journal;
+use crate::library::{self, ByteSize, Filter, Library, Wallpaper};
+use crate::prompt::{self, Exit};
+use crate::query::Query;
 
-/// Remove all downloaded wallpapers with confirmation
-pub fn execute() -> Result<()> {
-    let wallpaper_dir = config::get_wallpaper_dir()?;
+/// Which wallpapers `clean` removes; without filters it removes all of them
+#[derive(Debug, Default, Clone)]
+pub struct CleanOptions {
+    /// Only wallpapers in this category
+    pub category: Option<String>,
+    /// Only wallpapers downloaded longer ago than this
+    pub older_than: Option<Age>,
+    /// Only files larger than this
+    pub larger_than: Option<ByteSize>,
+    /// Keep favorites
+    pub not_favorite: bool,
+    /// Only banned wallpapers
+    pub banned: bool,
+    /// Only wallpapers matching this query
+    pub query: Option<Query>,
+    /// Only list what would be removed
+    pub dry_run: bool,
+    /// Delete for good instead of moving to the trash
+    pub permanent: bool,
+    /// Go ahead without asking
+    pub yes: bool,
+}
 
-    if !wallpaper_dir.exists() {
-        println!(
-            "No wallpaper directory found at {}",
-            wallpaper_dir.display()
-        );
+impl CleanOptions {
+    /// Whether anything narrows down the library
+    fn is_filtered(&self) -> bool {
+        self.category.is_some()
+            || self.older_than.is_some()
+            || self.larger_than.is_some()
+            || self.not_favorite
+            || self.banned
+            || self.query.is_some()
+    }
+
+    /// The filters that can be checked against the library, as one query
+    fn combined_query(&self) -> Option<Query> {
+        let mut terms = Vec::new();
+        if let Some(category) = &self.category {
+            terms.push(Query::Category(category.clone()));
+        }
+        if self.not_favorite {
+            terms.push(Query::Not(Box::new(Query::Favorite)));
+        }
+        if self.banned {
+            terms.push(Query::Banned);
+        }
+        if let Some(query) = &self.query {
+            terms.push(query.clone());
+        }
+        terms
+            .into_iter()
+            .reduce(|all, term| Query::And(Box::new(all), Box::new(term)))
+    }
+}
+
+/// A span of time written like "90d", "12h" or "2w"
+#[derive(Debug, Clone, Copy)]
+pub struct Age(pub Duration);
+
+impl FromStr for Age {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid age '{}' (expected e.g. 12h, 90d or 2w)", s))?;
+
+        let seconds = match unit.trim() {
+            "s" => 1,
+            "m" | "min" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            "y" => 365 * 24 * 60 * 60,
+            "" => anyhow::bail!("Missing unit in age '{}' (expected s, m, h, d, w or y)", s),
+            other => anyhow::bail!("Unknown age unit '{}' (expected s, m, h, d, w or y)", other),
+        };
+        Ok(Age(Duration::from_secs(number.saturating_mul(seconds))))
+    }
+}
+
+/// Remove downloaded wallpapers with confirmation
+///
+/// Without filters the whole wallpaper directory goes, after which wcapp
+/// offers to remove the state, data and cache it keeps about them. Files
+/// go to the trash unless `permanent` is set, and `restore` can bring all of
+/// them back. With `yes` nothing is asked, and only wallpapers go.
+pub fn execute(options: CleanOptions) -> Result<()> {
+    if options.is_filtered() || options.dry_run {
+        return remove_selected(&options);
+    }
+
+    let mut removed_at = remove_wallpapers(options.permanent, options.yes)?;
+
+    // Settings stay; `uninstall` removes those
+    if !options.yes {
+        for dir in config::app_dirs()? {
+            if dir.kinds.contains(&"config") {
+                continue;
+            }
+            let now = chrono::Utc::now().timestamp();
+            // The journal stays too, or there would be nothing to restore from
+            let journal = dir.path.join(journal::JOURNAL_FILE);
+            let removed = offer_removal(&dir, options.permanent, false, &[journal])?;
+            let Some(removed) = removed.filter(|removed| !removed.is_empty()) else {
+                continue;
+            };
+            match removed_at {
+                Some(removed_at) => journal::extend(removed_at, removed, options.permanent)?,
+                None => {
+                    journal::record(removed, options.permanent, now)?;
+                    removed_at = Some(now);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove only the wallpapers matching the filters, or list them for a dry run
+fn remove_selected(options: &CleanOptions) -> Result<()> {
+    let mut library = Library::open()?;
+    if !library.dir.exists() {
+        println!("No wallpaper directory found at {}", library.dir.display());
+        return Ok(());
+    }
+
+    let candidates = library.select(&Filter {
+        query: options.combined_query(),
+        ..Filter::default()
+    })?;
+    library.save_index()?;
+
+    let now = SystemTime::now();
+    let mut selected: Vec<(Wallpaper, u64)> = Vec::new();
+    for wallpaper in candidates {
+        let metadata = fs::metadata(&wallpaper.path)
+            .with_context(|| format!("Failed to read {}", wallpaper.path.display()))?;
+        if let Some(ByteSize(limit)) = options.larger_than
+            && metadata.len() <= limit
+        {
+            continue;
+        }
+        if let Some(Age(age)) = options.older_than {
+            // Files are written when they are fetched, so this is their age in the library
+            let modified = metadata.modified().unwrap_or(now);
+            if now.duration_since(modified).unwrap_or_default() <= age {
+                continue;
+            }
+        }
+        selected.push((wallpaper, metadata.len()));
+    }
+
+    if selected.is_empty() {
+        if options.is_filtered() {
+            println!("No wallpapers match the given filters");
+        } else {
+            println!("No wallpapers found in {}", library.dir.display());
+        }
         return Ok(());
     }
 
-    let image_extensions = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
-    let mut count = 0;
+    let total: u64 = selected.iter().map(|(_, size)| size).sum();
+    for (wallpaper, size) in &selected {
+        println!("  {}  ({})", wallpaper.name, library::format_size(*size));
+    }
+    println!();
+    println!(
+        "{} wallpapers, {} ({} bytes)",
+        selected.len(),
+        library::format_size(total),
+        total
+    );
 
-    for entry in WalkDir::new(&wallpaper_dir)
-        .follow_links(true)
+    if options.dry_run {
+        println!("Dry run: nothing was removed");
+        return Ok(());
+    }
+
+    let question = if options.permanent {
+        "Delete them permanently?"
+    } else {
+        "Move them to the trash?"
+    };
+    if !options.yes && !prompt::confirm(question)? {
+        return Err(Exit::Cancelled.into());
+    }
+
+    let paths: Vec<PathBuf> = selected
+        .into_iter()
+        .map(|(wallpaper, _)| wallpaper.path)
+        .collect();
+    let removed_at = chrono::Utc::now().timestamp();
+    let removed = journal::remove(&paths, options.permanent)?;
+    journal::record(removed, options.permanent, removed_at)?;
+    let emptied = remove_empty_dirs(&library.dir);
+
+    if options.permanent {
+        println!(
+            "Deleted {} wallpapers ({}) from {}",
+            paths.len(),
+            library::format_size(total),
+            library.dir.display()
+        );
+    } else {
+        println!(
+            "Moved {} wallpapers ({}) from {} to the trash",
+            paths.len(),
+            library::format_size(total),
+            library.dir.display()
+        );
+    }
+    if emptied > 0 {
+        println!("Removed {} empty folders", emptied);
+    }
+    if !options.permanent {
+        println!("Run 'wcapp restore' to bring them back");
+    }
+    Ok(())
+}
+
+/// Remove folders under `dir` left without any files, returning how many
+pub fn remove_empty_dirs(dir: &Path) -> usize {
+    let mut removed = 0;
+    // Children come first, so a folder holding only empty folders goes too
+    for entry in WalkDir::new(dir)
+        .min_depth(1)
+        .contents_first(true)
         .into_iter()
         .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
     {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if image_extensions.contains(&ext.to_str().unwrap_or("").to_lowercase().as_str()) {
-                    count += 1;
-                }
-            }
+        let is_empty = fs::read_dir(entry.path()).is_ok_and(|mut e| e.next().is_none());
+        if is_empty && fs::remove_dir(entry.path()).is_ok() {
+            removed += 1;
         }
     }
+    removed
+}
+
+/// Remove the wallpaper directory, asking first unless `yes` is set, and
+/// return when it was removed as recorded in the journal
+fn remove_wallpapers(permanent: bool, yes: bool) -> Result<Option<i64>> {
+    let wallpaper_dir = config::get_wallpaper_dir()?;
+
+    if !wallpaper_dir.exists() {
+        println!(
+            "No wallpaper directory found at {}",
+            wallpaper_dir.display()
+        );
+        return Ok(None);
+    }
+
+    let count = library::scan(&wallpaper_dir).len();
 
     if count == 0 {
         println!("No wallpapers found in {}", wallpaper_dir.display());
-        return Ok(());
+        return Ok(None);
     }
 
-    println!(
-        "This will delete {} wallpapers from {}",
-        count,
-        wallpaper_dir.display()
-    );
-    println!("Are you sure? (y/N): ");
+    if permanent {
+        println!(
+            "This will permanently delete {} wallpapers from {}",
+            count,
+            wallpaper_dir.display()
+        );
+    } else {
+        println!(
+            "This will move {} wallpapers from {} to the trash",
+            count,
+            wallpaper_dir.display()
+        );
+    }
+    if !yes && !prompt::confirm("Are you sure?")? {
+        return Err(Exit::Cancelled.into());
+    }
+
+    let removed_at = chrono::Utc::now().timestamp();
+    let removed = journal::remove(std::slice::from_ref(&wallpaper_dir), permanent)?;
+    journal::record(removed, permanent, removed_at)?;
 
-    let mut input = String::new();
-    std::io::stdin()
-        .read_line(&mut input)
-        .context("Failed to read user input")?;
+    if permanent {
+        println!(
+            "Deleted {} wallpapers from {}",
+            count,
+            wallpaper_dir.display()
+        );
+    } else {
+        println!(
+            "Moved {} wallpapers from {} to the trash. Run 'wcapp restore' to bring them back",
+            count,
+            wallpaper_dir.display()
+        );
+    }
 
-    let input = input.trim().to_lowercase();
+    Ok(Some(removed_at))
+}
 
-    if input != "y" && input != "yes" {
-        println!("Operation cancelled");
-        return Ok(());
+/// Ask whether to remove one of wcapp's own directories, and remove it if so;
+/// with `yes` it goes without asking
+///
+/// Paths in `keep` directly inside the directory are left in place, along
+/// with the directory itself. Returns the absolute paths of what was removed,
+/// or `None` if the user said no.
+pub fn offer_removal(
+    dir: &AppDir,
+    permanent: bool,
+    yes: bool,
+    keep: &[PathBuf],
+) -> Result<Option<Vec<PathBuf>>> {
+    if !dir.path.exists() {
+        return Ok(Some(Vec::new()));
     }
 
-    fs::remove_dir_all(&wallpaper_dir).context("Failed to remove wallpaper directory")?;
+    let size: u64 = WalkDir::new(&dir.path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !keep.iter().any(|k| e.path().starts_with(k)))
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum();
 
+    println!();
     println!(
-        "Deleted {} wallpapers from {}",
-        count,
-        wallpaper_dir.display()
+        "{} directory: {}",
+        capitalize(&dir.kinds.join(" and ")),
+        dir.path.display()
     );
+    println!("Holds {} ({})", dir.contents, library::format_size(size));
+    let question = if permanent {
+        "Delete it permanently?"
+    } else {
+        "Move it to the trash?"
+    };
+    if !yes && !prompt::confirm(question)? {
+        println!("✓ Kept {}", dir.path.display());
+        return Ok(None);
+    }
 
-    Ok(())
+    let targets = if keep.iter().any(|k| k.exists()) {
+        fs::read_dir(&dir.path)
+            .with_context(|| format!("Failed to read {}", dir.path.display()))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| !keep.contains(path))
+            .collect()
+    } else {
+        vec![dir.path.clone()]
+    };
+    if targets.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    let removed = journal::remove(&targets, permanent)?;
+    println!("✓ Removed {}", dir.path.display());
+    Ok(Some(removed))
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
 }
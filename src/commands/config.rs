@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use toml::{Table, Value};
+
+use crate::config::{self, Config, KEYS, LISTS, Layer, Origin};
+
+/// Print the effective value of a setting
+pub fn get(key: &str) -> Result<()> {
+    config::check_key(key)?;
+    let effective = effective()?;
+
+    let Some(value) = config::lookup(&effective, key) else {
+        anyhow::bail!("'{}' is not set", key);
+    };
+
+    match value {
+        Value::String(s) => println!("{}", s),
+        Value::Array(_) | Value::Table(_) => {
+            let mut wrapper = Table::new();
+            wrapper.insert(key.to_string(), value.clone());
+            print!("{}", toml::to_string(&wrapper)?);
+        }
+        other => println!("{}", other),
+    }
+    Ok(())
+}
+
+/// Change a setting in the user's config file, checking the value against the schema
+pub fn set(key: &str, raw: &str) -> Result<()> {
+    config::check_key(key)?;
+    if key == "version" {
+        anyhow::bail!("'version' is kept up to date by wcapp when it upgrades the config file");
+    }
+    if !config::is_scalar(key) && !key.contains('.') {
+        anyhow::bail!(
+            "'{}' holds several values. Set them one at a time, or use 'wcapp config edit'",
+            key
+        );
+    }
+
+    let value = config::typed_value(key, raw)?;
+    config::update(|table| config::assign(table, key, value))?;
+    println!("✓ {} = {}", key, raw);
+
+    let layers = config::layers()?;
+    if let Some(layer) = winner(&layers, key)
+        && layer.origin > Origin::User
+    {
+        println!("Note: {} overrides this setting", layer.source);
+    }
+    Ok(())
+}
+
+/// Remove a setting from the user's config file, so that other sources apply again
+pub fn unset(key: &str) -> Result<()> {
+    config::check_key(key)?;
+    let config_path = config::get_config_path()?;
+    let is_set = config::read_table()?.is_some_and(|table| config::lookup(&table, key).is_some());
+    if !is_set {
+        anyhow::bail!("'{}' is not set in {}", key, config_path.display());
+    }
+
+    config::update(|table| {
+        config::remove_key(table, key);
+    })?;
+    println!("✓ Unset {}", key);
+
+    let layers = config::layers()?;
+    if let Some(layer) = winner(&layers, key)
+        && layer.origin != Origin::Default
+    {
+        println!("Note: {} still sets it", layer.source);
+    }
+    Ok(())
+}
+
+/// List every setting with its effective value and the kind of source it
+/// comes from, or with `origin` the exact file, variable or flag
+pub fn list(origin: bool) -> Result<()> {
+    let layers = config::layers()?;
+    let effective = effective()?;
+
+    let mut rows: Vec<(String, String, String)> = Vec::new();
+    let mut row = |key: String, value: String| {
+        let source = match winner(&layers, &key) {
+            Some(layer) if origin => layer.source.clone(),
+            Some(layer) => layer.origin.to_string(),
+            None if origin => "built in".to_string(),
+            None => Origin::Default.to_string(),
+        };
+        rows.push((key, value, source));
+    };
+
+    for &key in KEYS {
+        match effective.get(key) {
+            None if LISTS.contains(&key) => row(key.to_string(), "(none)".to_string()),
+            None => row(key.to_string(), "(not set)".to_string()),
+            Some(Value::Table(entries)) if !entries.is_empty() => {
+                for (name, value) in entries {
+                    row(format!("{}.{}", key, name), show(value));
+                }
+            }
+            Some(value) => row(key.to_string(), show(value)),
+        }
+    }
+
+    let key_width = rows.iter().map(|(k, _, _)| k.len()).max().unwrap_or(0);
+    let value_width = rows.iter().map(|(_, v, _)| v.len()).max().unwrap_or(0);
+    for (key, value, source) in rows {
+        println!(
+            "{:<key_width$}  {:<value_width$}  {}",
+            key,
+            value,
+            source,
+            key_width = key_width,
+            value_width = value_width
+        );
+    }
+    Ok(())
+}
+
+/// Open the config file in $VISUAL or $EDITOR, saving it only once it is valid
+pub fn edit() -> Result<()> {
+    let config_path = config::get_config_path()?;
+    if !config_path.exists() {
+        config::update(|_| {})?;
+    }
+
+    // Edit a copy, so that a half-finished file is never picked up
+    let draft = config_path.with_file_name("config.edit.toml");
+    fs::copy(&config_path, &draft).context("Failed to copy config file")?;
+
+    loop {
+        open_editor(&draft)?;
+
+        let content = fs::read_to_string(&draft).context("Failed to read edited config")?;
+        // Judged as the file will be read, so an old version is upgraded first
+        match config::upgrade(&draft, &content) {
+            Ok((table, _)) => {
+                fs::rename(&draft, &config_path).context("Failed to save config file")?;
+                println!("✓ Saved {}", config_path.display());
+                for key in config::unknown_keys(&table) {
+                    println!("✗ {}", config::unknown_message(key, "the file"));
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                println!("✗ {:#}", e);
+                print!("Edit again? (Y/n): ");
+                io::stdout().flush()?;
+
+                // Without an answer there is nobody to edit again
+                let mut input = String::new();
+                let read = io::stdin().read_line(&mut input)?;
+                if read == 0 || input.trim().eq_ignore_ascii_case("n") {
+                    fs::remove_file(&draft).context("Failed to remove edited copy")?;
+                    println!("Changes discarded");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Check every config file and override, failing when wcapp would refuse or
+/// ignore any of it
+pub fn check() -> Result<()> {
+    let layers = config::layers()?;
+    let _: Config = config::merge(&layers)
+        .try_into()
+        .context("Failed to combine config files")?;
+
+    let mut unknown = 0;
+    for layer in &layers {
+        for key in config::unknown_keys(&layer.table) {
+            println!("✗ {}", config::unknown_message(key, &layer.source));
+            unknown += 1;
+        }
+    }
+    if unknown > 0 {
+        anyhow::bail!("Found {} unknown setting(s)", unknown);
+    }
+
+    if layers.len() == 1 {
+        println!("No config files found. Defaults are used.");
+    }
+    for layer in layers.iter().skip(1) {
+        println!("✓ {} ({})", layer.source, layer.origin);
+    }
+    Ok(())
+}
+
+/// Print the path of the user's config file
+pub fn path() -> Result<()> {
+    println!("{}", config::get_config_path()?.display());
+    Ok(())
+}
+
+/// The combined config with defaults filled in, as a TOML table
+fn effective() -> Result<Table> {
+    let config = config::load_config()?.unwrap_or_default();
+    Ok(Table::try_from(&config)?)
+}
+
+/// The layer the effective value of `key` comes from
+fn winner<'a>(layers: &'a [Layer], key: &str) -> Option<&'a Layer> {
+    layers
+        .iter()
+        .rev()
+        .find(|layer| config::lookup(&layer.table, key).is_some())
+}
+
+/// A value on one line: strings unquoted, lists as their length
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(entries) if entries.len() == 1 => "1 entry".to_string(),
+        Value::Array(entries) => format!("{} entries", entries.len()),
+        Value::Table(entries) if entries.is_empty() => "(none)".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Run the user's editor on `path` and wait for it to close
+fn open_editor(path: &Path) -> Result<()> {
+    let fallback = if cfg!(target_os = "windows") {
+        "notepad"
+    } else {
+        "vi"
+    };
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| fallback.to_string());
+
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().context("No editor configured")?;
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to start editor '{}'", editor))?;
+
+    if !status.success() {
+        anyhow::bail!("Editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
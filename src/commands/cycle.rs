@@ -1,44 +1,95 @@
-use anyhow::{Context, Result};
-use rand::seq::SliceRandom;
-use std::fs;
-use std::path::PathBuf;
+use anyhow::Result;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::thread;
 use std::time::Duration;
-use walkdir::WalkDir;
 
-use crate::config::{self, Config};
+use crate::config;
+use crate::control::{self, Message};
+use crate::display::{self, Target};
+use crate::effects::Effects;
+use crate::history::{self, History};
+use crate::hooks::{self, Event};
+use crate::library::{Library, Wallpaper};
+use crate::playlist;
+use crate::render::Color;
+use crate::selection::{PickOptions, Selector};
+use crate::setter::{self, Appearance, Mode};
 
-pub fn execute(interval: Option<u64>, set_default: bool) -> Result<()> {
-    let wallpaper_dir = config::get_wallpaper_dir()?;
+/// What to cycle through and how, kept across profile switches
+struct Request {
+    interval: Option<u64>,
+    options: PickOptions,
+    mode: Option<Mode>,
+    letterbox: Option<Color>,
+    effects: Option<Effects>,
+    target: Target,
+}
+
+pub fn execute(
+    interval: Option<u64>,
+    set_default: bool,
+    options: PickOptions,
+    mode: Option<Mode>,
+    letterbox: Option<Color>,
+    effects: Option<Effects>,
+    target: Target,
+) -> Result<()> {
+    if let Some(interval) = interval
+        && set_default
+    {
+        let value = config::typed_value("cycle_interval", &interval.to_string())?;
+        config::update(|table| config::assign(table, "cycle_interval", value))?;
+        println!("✓ Default cycle interval set to {} seconds", interval);
+    }
+
+    // `wcapp profile use` tells a running cycle to switch over
+    let messages = match control::listen() {
+        Ok(messages) => Some(messages),
+        Err(e) => {
+            eprintln!("{:#}. Switching profiles will need a restart.", e);
+            None
+        }
+    };
+
+    let request = Request {
+        interval,
+        options,
+        mode,
+        letterbox,
+        effects,
+        target,
+    };
+    while let Some(profile) = run(&request, messages.as_ref())? {
+        config::switch_profile(Some(&profile));
+        if profile == config::NO_PROFILE {
+            println!("Stopped using profiles");
+        } else {
+            println!("Switched to profile '{}'", profile);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Cycle with the current settings until told to switch to another
+/// profile, whose name is returned
+fn run(request: &Request, messages: Option<&Receiver<Message>>) -> Result<Option<String>> {
+    let options = &request.options;
+    let mut library = Library::open()?;
+    let wallpaper_dir = library.dir.clone();
 
     if !wallpaper_dir.exists() {
         println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
-        return Ok(());
+        return Ok(None);
     }
 
-    let mut config_data = config::load_config();
-    let cycle_interval = if let Some(interval) = interval {
-        if set_default {
-            if let Some(ref mut cfg) = config_data {
-                cfg.cycle_interval = interval;
-                config::save_config(cfg)?;
-                println!("✓ Default cycle interval set to {} seconds", interval);
-            } else {
-                let cfg = Config {
-                    wallpaper_dir: wallpaper_dir.clone(),
-                    cycle_interval: interval,
-                };
-                config::save_config(&cfg)?;
-                println!("✓ Default cycle interval set to {} seconds", interval);
-            }
-        }
-        interval
-    } else {
+    let config_data = config::load_config()?;
+    let cycle_interval = request.interval.unwrap_or_else(|| {
         config_data
             .as_ref()
             .map(|c| c.cycle_interval)
             .unwrap_or(300)
-    };
+    });
 
     println!("Wallpaper Cycle Mode");
     println!("Interval: {} seconds ({} minutes)", cycle_interval, cycle_interval / 60);
@@ -47,79 +98,187 @@ pub fn execute(interval: Option<u64>, set_default: bool) -> Result<()> {
     println!("Press Ctrl+C to stop");
     println!();
 
-    let image_extensions = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
-    let mut wallpapers = Vec::new();
-
-    for entry in WalkDir::new(&wallpaper_dir)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if image_extensions.contains(&ext.to_str().unwrap_or("").to_lowercase().as_str()) {
-                    wallpapers.push(path.to_path_buf());
-                }
-            }
-        }
-    }
+    let playlist = options
+        .playlist
+        .as_deref()
+        .map(playlist::load_playlist)
+        .transpose()?;
+    let wallpapers = options.candidates(&mut library, playlist.as_ref(), config_data.as_ref())?;
+    library.save_index()?;
 
     if wallpapers.is_empty() {
-        println!("No wallpapers found in {}", wallpaper_dir.display());
-        return Ok(());
+        if options.is_filtered() {
+            println!(
+                "No wallpapers match the given filters in {}",
+                wallpaper_dir.display()
+            );
+        } else {
+            println!("No wallpapers found in {}", wallpaper_dir.display());
+        }
+        return Ok(None);
     }
 
     println!("Found {} wallpapers", wallpapers.len());
+    if let Some(name) = &options.playlist {
+        println!("Playlist: {}", name);
+    }
     println!();
 
-    let mut rng = rand::thread_rng();
+    // Static and query playlists play in order unless they are set to shuffle
+    let in_order = playlist.as_ref().is_some_and(|p| !p.shuffle);
+
+    let effects = match (request.effects, &playlist) {
+        (None, Some(playlist)) => playlist.parsed_effects()?,
+        (effects, _) => effects,
+    };
+    let appearance = Appearance::resolve(
+        request.mode,
+        request.letterbox,
+        effects,
+        config_data.as_ref(),
+    );
+
+    // Monitors to set on every tick, or none to set the whole desktop
+    let monitors = display::layout(config_data.as_ref());
+    let slots: Option<Vec<usize>> = match &request.target {
+        Target::All => None,
+        Target::One(monitor) => Some(vec![monitor.resolve(&monitors)?]),
+        Target::Each => Some((0..monitors.len()).collect()),
+    };
+    let count = slots.as_ref().map_or(1, Vec::len);
+    let mut selector = Selector::from_config(config_data.as_ref(), options.seed, options.weighted);
+    let mut history = history::load_history()?;
     let mut cycle_count = 0;
+    let mut position = 0;
 
     loop {
         cycle_count += 1;
         
-        let chosen = wallpapers
-            .choose(&mut rng)
-            .context("Failed to choose random wallpaper")?;
-
-        match set_wallpaper_internal(chosen) {
-            Ok(_) => {
-                let filename = chosen
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown");
-                
-                let category = chosen
-                    .parent()
-                    .and_then(|p| p.file_name())
-                    .and_then(|n| n.to_str());
-                
-                let display_name = if let Some(cat) = category {
-                    format!("{}/{}", cat, filename)
-                } else {
-                    filename.to_string()
-                };
-                
-                let now = chrono::Local::now();
-                println!("[{}] Cycle #{}: {}", 
-                    now.format("%H:%M:%S"), 
-                    cycle_count, 
-                    display_name
-                );
+        let chosen = if in_order {
+            let chosen: Vec<Wallpaper> = (0..count)
+                .map(|i| wallpapers[(position + i) % wallpapers.len()].clone())
+                .collect();
+            position += count;
+            chosen
+        } else {
+            selector.pick_many(&wallpapers, count, &mut library, &history)?
+        };
+        if chosen.is_empty() {
+            anyhow::bail!("Failed to choose random wallpaper");
+        }
+
+        let previous = display::load_screens().ok().and_then(|screens| {
+            let shown = match &slots {
+                Some(slots) => screens.on(&monitors[slots[0]]).cloned(),
+                None => screens.all,
+            };
+            shown.map(|shown| shown.path)
+        });
+
+        match set(&mut library, &chosen, slots.as_deref(), appearance) {
+            Ok(hashes) => {
+                for (i, (chosen, hash)) in chosen.iter().zip(&hashes).enumerate() {
+                    if let Err(e) = record(&mut library, &mut history, hash, chosen) {
+                        eprintln!("Failed to update history: {}", e);
+                    }
+
+                    let filename = chosen
+                        .path
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("unknown");
+
+                    let category = chosen
+                        .path
+                        .parent()
+                        .and_then(|p| p.file_name())
+                        .and_then(|n| n.to_str());
+
+                    let mut display_name = if let Some(cat) = category {
+                        format!("{}/{}", cat, filename)
+                    } else {
+                        filename.to_string()
+                    };
+                    if let Some(slots) = &slots {
+                        display_name = format!("{}: {}", monitors[slots[i]].name, display_name);
+                    }
+
+                    let now = chrono::Local::now();
+                    println!("[{}] Cycle #{}: {}",
+                        now.format("%H:%M:%S"),
+                        cycle_count,
+                        display_name
+                    );
+                }
+
+                let mut env = hooks::wallpaper_env(&chosen[0], previous.as_deref());
+                env.push(("WCAPP_CYCLE", cycle_count.to_string()));
+                hooks::run(config_data.as_ref(), Event::CycleTick, &env);
             }
             Err(e) => {
                 eprintln!("Failed to set wallpaper: {}", e);
             }
         }
 
-        thread::sleep(Duration::from_secs(cycle_interval));
+        if let Some(profile) = wait(messages, Duration::from_secs(cycle_interval)) {
+            return Ok(Some(profile));
+        }
+    }
+}
+
+/// Sleep for `interval`, waking up early when asked to switch profiles
+fn wait(messages: Option<&Receiver<Message>>, interval: Duration) -> Option<String> {
+    let Some(messages) = messages else {
+        thread::sleep(interval);
+        return None;
+    };
+
+    match messages.recv_timeout(interval) {
+        Ok(Message::Profile(name)) => Some(name),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => {
+            thread::sleep(interval);
+            None
+        }
     }
 }
 
-fn set_wallpaper_internal(path: &PathBuf) -> Result<()> {
-    let absolute_path = fs::canonicalize(path).context("Failed to get absolute path")?;
-    wallpaper::set_from_path(absolute_path.to_str().unwrap())
-        .map_err(|e| anyhow::anyhow!("Failed to set wallpaper: {}", e))?;
-    Ok(())
+/// Set wallpapers on the given monitors, or one on the whole desktop, and
+/// return their content hashes
+fn set(
+    library: &mut Library,
+    wallpapers: &[Wallpaper],
+    slots: Option<&[usize]>,
+    appearance: Appearance,
+) -> Result<Vec<String>> {
+    let mut hashes = Vec::with_capacity(wallpapers.len());
+    for wallpaper in wallpapers {
+        hashes.push(library.hash(wallpaper)?);
+    }
+
+    match slots {
+        Some(slots) => {
+            let changes: Vec<(usize, &Wallpaper, &str)> = slots
+                .iter()
+                .zip(wallpapers)
+                .zip(&hashes)
+                .map(|((&index, wallpaper), hash)| (index, wallpaper, hash.as_str()))
+                .collect();
+            setter::set_on_monitors(&changes, appearance)?;
+        }
+        None => setter::set_wallpaper(&wallpapers[0], &hashes[0], appearance)?,
+    }
+    Ok(hashes)
+}
+
+/// Add a wallpaper that was just set to the history
+fn record(
+    library: &mut Library,
+    history: &mut History,
+    hash: &str,
+    wallpaper: &Wallpaper,
+) -> Result<()> {
+    history.record(hash, &wallpaper.name);
+    history::save_history(history)?;
+    library.save_index()
 }
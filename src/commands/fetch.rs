@@ -1,48 +1,62 @@
 use anyhow::{Context, Result};
+use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use walkdir::WalkDir;
 
-use crate::config::{self, Config, WALLPAPER_REPO};
+use super::clean;
+use crate::config::{self, WALLPAPER_REPO};
+use crate::history;
+use crate::hooks::{self, Event};
+use crate::journal;
+use crate::library::{self, ByteSize, Library};
+use crate::quota::{self, Evicted, Plan};
 
 /// Fetch wallpapers from a git repository and move images to destination
 pub fn execute(destination: Option<PathBuf>) -> Result<()> {
     println!("Fetching wallpapers from: {}", WALLPAPER_REPO);
 
-    let dest_dir = destination.unwrap_or_else(|| {
-        config::get_wallpaper_dir().expect("Could not determine wallpaper directory")
-    });
+    let dest_dir = match destination {
+        Some(destination) => destination,
+        None => config::get_wallpaper_dir()?,
+    };
 
     println!("Destination: {}", dest_dir.display());
 
-    let temp_dir = std::env::temp_dir().join("wcapp_temp");
-    if temp_dir.exists() {
-        fs::remove_dir_all(&temp_dir)?;
-    }
-    fs::create_dir_all(&temp_dir)?;
+    // The checkout is kept in the cache, so that later fetches only pull changes
+    let checkout_dir = config::get_cache_dir()?.join("repository");
+    if !pull(&checkout_dir)? {
+        if checkout_dir.exists() {
+            fs::remove_dir_all(&checkout_dir).context("Failed to remove old checkout")?;
+        }
 
-    println!("Cloning repository...");
-    let status = Command::new("git")
-        .args(["clone", "--depth", "1", WALLPAPER_REPO])
-        .arg(&temp_dir)
-        .status()
-        .context("Failed to execute git. Make sure git is installed and in PATH")?;
+        println!("Cloning repository...");
+        let status = Command::new("git")
+            .args(["clone", "--depth", "1", WALLPAPER_REPO])
+            .arg(&checkout_dir)
+            .status()
+            .context("Failed to execute git. Make sure git is installed and in PATH")?;
 
-    if !status.success() {
-        anyhow::bail!("Git clone failed");
+        if !status.success() {
+            anyhow::bail!("Git clone failed");
+        }
     }
 
     println!("Copying images with folder structure...");
-    let image_extensions = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
     let mut copied_count = 0;
+    let mut unchanged_count = 0;
 
-    let classified_dir = temp_dir.join("classified");
+    let classified_dir = checkout_dir.join("classified");
 
     if !classified_dir.exists() {
         anyhow::bail!("classified/ folder not found in repository");
     }
 
+    // Files already in the library are only copied again when they changed,
+    // so that their age stays the time they were first fetched
+    let mut incoming = Vec::new();
+    let mut available = HashSet::new();
     for entry in WalkDir::new(&classified_dir)
         .follow_links(true)
         .into_iter()
@@ -50,42 +64,166 @@ pub fn execute(destination: Option<PathBuf>) -> Result<()> {
     {
         let path = entry.path();
 
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if image_extensions.contains(&ext.to_str().unwrap_or("").to_lowercase().as_str()) {
-                    if let Ok(relative_path) = path.strip_prefix(&classified_dir) {
-                        let dest_path = dest_dir.join(relative_path);
-
-                        if let Some(parent) = dest_path.parent() {
-                            fs::create_dir_all(parent)
-                                .context("Failed to create category directory")?;
-                        }
-
-                        fs::copy(path, &dest_path)
-                            .context(format!("Failed to copy {}", relative_path.display()))?;
-                        copied_count += 1;
-                    }
+        if path.is_file()
+            && library::is_image(path)
+            && let Ok(relative_path) = path.strip_prefix(&classified_dir)
+        {
+            let dest_path = dest_dir.join(relative_path);
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            available.insert(relative_path.display().to_string());
+
+            match fs::metadata(&dest_path) {
+                Ok(existing) if existing.len() == size => unchanged_count += 1,
+                Ok(_) => {
+                    copy(path, &dest_path, relative_path)?;
+                    copied_count += 1;
                 }
+                Err(_) => incoming.push((relative_path.to_path_buf(), size)),
             }
         }
     }
 
-    fs::remove_dir_all(&temp_dir)?;
+    let config = config::load_config()?.unwrap_or_default();
+    let plan = match config.max_library_size {
+        Some(limit) => make_room(&dest_dir, &incoming, &available, limit)?,
+        None => {
+            // Without a limit everything evicted earlier comes back
+            if !quota::load_evicted()?.names.is_empty() {
+                quota::save_evicted(&Evicted::default())?;
+            }
+            Plan {
+                fetch: (0..incoming.len()).collect(),
+                ..Plan::default()
+            }
+        }
+    };
+
+    for &index in &plan.fetch {
+        let relative_path = &incoming[index].0;
+        copy(
+            &classified_dir.join(relative_path),
+            &dest_dir.join(relative_path),
+            relative_path,
+        )?;
+        copied_count += 1;
+    }
 
     println!(
         "Successfully copied {} wallpapers to {}",
         copied_count,
         dest_dir.display()
     );
+    if unchanged_count > 0 {
+        println!("{} wallpapers were already up to date", unchanged_count);
+    }
+    if let Some(limit) = config.max_library_size {
+        let skipped = incoming.len() - plan.fetch.len();
+        if skipped > 0 {
+            println!(
+                "Skipped {} wallpapers that do not fit in max_library_size",
+                skipped
+            );
+        }
+        println!("Library: {} of {}", library::format_size(plan.total), limit);
+    }
 
-    let existing_config = config::load_config();
-    let config = Config {
-        wallpaper_dir: dest_dir.clone(),
-        cycle_interval: existing_config.map(|c| c.cycle_interval).unwrap_or(300),
-    };
-    config::save_config(&config)?;
+    let wallpaper_dir = dest_dir.display().to_string();
+    config::update(|table| {
+        table.insert("wallpaper_dir".to_string(), wallpaper_dir.into());
+    })?;
 
     println!("Wallpaper directory saved to config");
 
+    hooks::run(
+        config::load_config()?.as_ref(),
+        Event::FetchComplete,
+        &[
+            ("WCAPP_DIR", dest_dir.display().to_string()),
+            ("WCAPP_COUNT", copied_count.to_string()),
+        ],
+    );
+
     Ok(())
 }
+
+/// Copy one image into the library, creating its category folder
+fn copy(source: &Path, dest_path: &Path, relative_path: &Path) -> Result<()> {
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create category directory")?;
+    }
+
+    fs::copy(source, dest_path)
+        .with_context(|| format!("Failed to copy {}", relative_path.display()))?;
+    Ok(())
+}
+
+/// Evict wallpapers so that what is worth fetching fits in `limit`,
+/// returning which of the `incoming` files to fetch
+fn make_room(
+    dest_dir: &Path,
+    incoming: &[(PathBuf, u64)],
+    available: &HashSet<String>,
+    limit: ByteSize,
+) -> Result<Plan> {
+    let mut library = Library::open_at(dest_dir.to_path_buf())?;
+    let named: Vec<(String, u64)> = incoming
+        .iter()
+        .map(|(path, size)| (path.display().to_string(), *size))
+        .collect();
+    let history = history::load_history()?;
+    let mut evicted = quota::load_evicted()?;
+    let plan = quota::plan(&mut library, &named, available, limit.0, &history, &evicted)?;
+    library.save_index()?;
+
+    let evicted_size: u64 = plan
+        .evict
+        .iter()
+        .filter_map(|w| fs::metadata(&w.path).ok())
+        .map(|m| m.len())
+        .sum();
+    if !plan.evict.is_empty() {
+        // Deleted rather than trashed, since the point is to free the space;
+        // `fetch` brings them back once there is room
+        let paths: Vec<PathBuf> = plan.evict.iter().map(|w| w.path.clone()).collect();
+        let removed_at = chrono::Utc::now().timestamp();
+        let removed = journal::remove(&paths, true)?;
+        journal::record(removed, true, removed_at)?;
+        clean::remove_empty_dirs(dest_dir);
+        println!(
+            "Evicted {} wallpapers ({}) to stay under max_library_size",
+            plan.evict.len(),
+            library::format_size(evicted_size)
+        );
+    }
+
+    // Remember what is missing, so that it only comes back when there is room
+    evicted
+        .names
+        .extend(plan.evict.iter().map(|w| w.name.clone()));
+    evicted
+        .names
+        .extend(named.iter().map(|(name, _)| name.clone()));
+    for &index in &plan.fetch {
+        evicted.names.remove(&named[index].0);
+    }
+    evicted.names.retain(|name| available.contains(name));
+    quota::save_evicted(&evicted)?;
+
+    Ok(plan)
+}
+
+/// Bring an earlier checkout up to date, returning whether there was one to update
+fn pull(checkout_dir: &Path) -> Result<bool> {
+    if !checkout_dir.join(".git").exists() {
+        return Ok(false);
+    }
+
+    println!("Updating repository...");
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(checkout_dir)
+        .args(["pull", "--ff-only", "--depth", "1"])
+        .status()
+        .context("Failed to execute git. Make sure git is installed and in PATH")?;
+    Ok(status.success())
+}
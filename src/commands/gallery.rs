@@ -0,0 +1,588 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::library::{self, Library, Wallpaper};
+use crate::state::WallpaperState;
+use crate::thumbnail;
+
+/// Folder inside the output directory holding the thumbnails
+const THUMBS_DIR: &str = "thumbs";
+/// File in the output directory listing the pages the last run wrote
+const MANIFEST: &str = ".wcapp-pages";
+
+const STYLE: &str = r#"* { box-sizing: border-box; }
+body { margin: 0; font-family: system-ui, sans-serif; background: #16161a; color: #e4e4e7; }
+header { padding: 1.5rem 2rem 1rem; border-bottom: 1px solid #2e2e35; }
+h1 { margin: 0 0 0.75rem; font-size: 1.5rem; }
+nav { display: flex; flex-wrap: wrap; gap: 0.5rem; }
+nav a { color: #a1a1aa; text-decoration: none; padding: 0.2rem 0.6rem; border-radius: 999px; background: #24242a; }
+nav a.current, nav a:hover { color: #16161a; background: #e4e4e7; }
+main { display: grid; grid-template-columns: repeat(auto-fill, minmax(260px, 1fr)); gap: 1.25rem; padding: 2rem; }
+.card { margin: 0; background: #202026; border-radius: 8px; overflow: hidden; }
+.card.banned { opacity: 0.5; }
+.card img, .card .missing { display: block; width: 100%; aspect-ratio: 16 / 10; object-fit: cover; background: #2e2e35; }
+.card .missing { display: flex; align-items: center; justify-content: center; color: #71717a; }
+.card a { color: inherit; text-decoration: none; }
+figcaption { padding: 0.75rem; display: flex; flex-direction: column; gap: 0.4rem; font-size: 0.85rem; }
+.name { font-weight: 600; word-break: break-all; }
+.favorite { color: #facc15; }
+.meta { color: #a1a1aa; }
+.tags { display: flex; flex-wrap: wrap; gap: 0.3rem; }
+.tag { padding: 0.05rem 0.45rem; border-radius: 999px; background: #312e81; color: #c7d2fe; }
+.command { display: flex; gap: 0.4rem; align-items: center; }
+.command code { flex: 1; overflow-x: auto; white-space: nowrap; padding: 0.3rem 0.4rem; border-radius: 4px; background: #16161a; }
+.command button { border: 0; border-radius: 4px; padding: 0.3rem 0.6rem; background: #3f3f46; color: inherit; cursor: pointer; }
+.command button:hover { background: #52525b; }
+.count { color: #a1a1aa; font-weight: normal; }
+"#;
+
+const SCRIPT: &str = r#"document.addEventListener('click', function (event) {
+  var button = event.target.closest('button[data-command]');
+  if (!button) return;
+  var done = function () {
+    button.textContent = 'Copied';
+    setTimeout(function () { button.textContent = 'Copy'; }, 1500);
+  };
+  if (navigator.clipboard) {
+    navigator.clipboard.writeText(button.dataset.command).then(done);
+  } else {
+    var selection = window.getSelection();
+    selection.selectAllChildren(button.previousElementSibling);
+    document.execCommand('copy');
+    selection.removeAllRanges();
+    done();
+  }
+});
+"#;
+
+/// A wallpaper and everything shown about it on a card
+struct Card {
+    wallpaper: Wallpaper,
+    /// Thumbnail file name in the thumbs folder, if one could be made
+    thumbnail: Option<String>,
+    dimensions: Option<(u32, u32)>,
+    size: Option<u64>,
+    state: WallpaperState,
+}
+
+/// A generated page and the wallpapers on it
+struct Page {
+    title: String,
+    file: String,
+    cards: Vec<usize>,
+}
+
+/// Generate a static HTML gallery of the library in `out`
+///
+/// Thumbnails are named after the content hash of their wallpaper, so running
+/// this again only creates thumbnails for new or changed wallpapers.
+pub fn execute(out: &Path) -> Result<()> {
+    let mut library = Library::open()?;
+
+    if library.wallpapers.is_empty() {
+        println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
+        return Ok(());
+    }
+
+    let thumbs_dir = out.join(THUMBS_DIR);
+    fs::create_dir_all(&thumbs_dir)
+        .with_context(|| format!("Failed to create {}", thumbs_dir.display()))?;
+
+    println!("Generating gallery in {}...", out.display());
+
+    let mut created = 0;
+    let mut failed = 0;
+    let mut cards = Vec::new();
+    for wallpaper in library.wallpapers.clone() {
+        let hash = library.hash(&wallpaper)?;
+        let file_name = format!("{}.jpg", hash);
+
+        let thumbnail = match thumbnail::ensure(&wallpaper.path, &thumbs_dir.join(&file_name)) {
+            Ok(new) => {
+                created += usize::from(new);
+                Some(file_name)
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to create a thumbnail for {}: {:#}",
+                    wallpaper.name, e
+                );
+                failed += 1;
+                None
+            }
+        };
+
+        cards.push(Card {
+            dimensions: library.dimensions(&wallpaper)?,
+            size: fs::metadata(&wallpaper.path).map(|m| m.len()).ok(),
+            state: library.state.get(&hash).cloned().unwrap_or_default(),
+            thumbnail,
+            wallpaper,
+        });
+    }
+    library.save_index()?;
+
+    let removed = remove_stale_thumbnails(&thumbs_dir, &cards)?;
+    let pages = pages(&cards);
+
+    write_if_changed(&out.join("style.css"), STYLE)?;
+    write_if_changed(&out.join("index.html"), &render_index(&pages, &cards))?;
+    for page in &pages {
+        write_if_changed(&out.join(&page.file), &render_page(page, &pages, &cards))?;
+    }
+    remove_stale_pages(out, &pages)?;
+
+    println!(
+        "✓ Gallery written to {} ({} pages)",
+        out.join("index.html").display(),
+        pages.len() + 1
+    );
+    println!(
+        "Thumbnails: {} new, {} reused, {} removed",
+        created,
+        cards.len() - created - failed,
+        removed
+    );
+    if failed > 0 {
+        println!(
+            "{} wallpapers could not be read and have no thumbnail",
+            failed
+        );
+    }
+
+    Ok(())
+}
+
+/// One page per category, then uncategorized wallpapers and favorites
+fn pages(cards: &[Card]) -> Vec<Page> {
+    let mut categories: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    let mut uncategorized = Vec::new();
+    for (i, card) in cards.iter().enumerate() {
+        match &card.wallpaper.category {
+            Some(category) => categories.entry(category).or_default().push(i),
+            None => uncategorized.push(i),
+        }
+    }
+
+    let mut used = HashSet::new();
+    let mut pages: Vec<Page> = categories
+        .into_iter()
+        .map(|(category, cards)| {
+            let mut file = format!("category-{}.html", slug(category));
+            let mut n = 2;
+            while !used.insert(file.clone()) {
+                file = format!("category-{}-{}.html", slug(category), n);
+                n += 1;
+            }
+            Page {
+                title: category.to_string(),
+                file,
+                cards,
+            }
+        })
+        .collect();
+
+    if !uncategorized.is_empty() {
+        pages.push(Page {
+            title: "Uncategorized".to_string(),
+            file: "uncategorized.html".to_string(),
+            cards: uncategorized,
+        });
+    }
+
+    let favorites: Vec<usize> = (0..cards.len())
+        .filter(|&i| cards[i].state.favorite)
+        .collect();
+    if !favorites.is_empty() {
+        pages.push(Page {
+            title: "★ Favorites".to_string(),
+            file: "favorites.html".to_string(),
+            cards: favorites,
+        });
+    }
+
+    pages
+}
+
+fn render_index(pages: &[Page], cards: &[Card]) -> String {
+    let mut body = String::new();
+    for page in pages {
+        let cover = page
+            .cards
+            .iter()
+            .find_map(|&i| cards[i].thumbnail.as_deref());
+        let _ = write!(
+            body,
+            r#"<figure class="card"><a href="{}">"#,
+            escape(&page.file)
+        );
+        match cover {
+            Some(thumbnail) => {
+                let _ = write!(
+                    body,
+                    r#"<img src="{}/{}" alt="" loading="lazy">"#,
+                    THUMBS_DIR,
+                    escape(thumbnail)
+                );
+            }
+            None => body.push_str(r#"<div class="missing">No preview</div>"#),
+        }
+        let _ = write!(
+            body,
+            r#"<figcaption><div class="name">{} <span class="count">({})</span></div></figcaption></a></figure>"#,
+            escape(&page.title),
+            page.cards.len()
+        );
+        body.push('\n');
+    }
+
+    layout("Wallpapers", cards.len(), "index.html", pages, &body)
+}
+
+fn render_page(page: &Page, pages: &[Page], cards: &[Card]) -> String {
+    let mut body = String::new();
+    for &i in &page.cards {
+        render_card(&mut body, &cards[i]);
+    }
+
+    layout(&page.title, page.cards.len(), &page.file, pages, &body)
+}
+
+fn render_card(out: &mut String, card: &Card) {
+    let wallpaper = &card.wallpaper;
+    let class = if card.state.banned {
+        "card banned"
+    } else {
+        "card"
+    };
+    let _ = write!(
+        out,
+        r#"<figure class="{}"><a href="{}">"#,
+        class,
+        escape(&file_url(&wallpaper.path))
+    );
+    match &card.thumbnail {
+        Some(thumbnail) => {
+            let _ = write!(
+                out,
+                r#"<img src="{}/{}" alt="{}" loading="lazy">"#,
+                THUMBS_DIR,
+                escape(thumbnail),
+                escape(&wallpaper.name)
+            );
+        }
+        None => out.push_str(r#"<div class="missing">No preview</div>"#),
+    }
+    out.push_str("</a><figcaption>");
+
+    let favorite = if card.state.favorite {
+        r#" <span class="favorite" title="Favorite">★</span>"#
+    } else {
+        ""
+    };
+    let _ = write!(
+        out,
+        r#"<div class="name">{}{}</div>"#,
+        escape(&wallpaper.name),
+        favorite
+    );
+
+    let mut meta = Vec::new();
+    if let Some((width, height)) = card.dimensions {
+        meta.push(format!("{}×{}", width, height));
+    }
+    if let Some(size) = card.size {
+        meta.push(library::format_size(size));
+    }
+    if let Some(rating) = card.state.rating {
+        meta.push(format!(
+            "{}{}",
+            "★".repeat(rating as usize),
+            "☆".repeat(5 - rating as usize)
+        ));
+    }
+    if card.state.banned {
+        meta.push("banned".to_string());
+    }
+    let _ = write!(
+        out,
+        r#"<div class="meta">{}</div>"#,
+        escape(&meta.join(" · "))
+    );
+
+    if !card.state.tags.is_empty() {
+        out.push_str(r#"<div class="tags">"#);
+        for tag in &card.state.tags {
+            let _ = write!(out, r#"<span class="tag">{}</span>"#, escape(tag));
+        }
+        out.push_str("</div>");
+    }
+
+    let command = format!("wcapp set --name {}", shell_quote(&wallpaper.name));
+    let _ = write!(
+        out,
+        r#"<div class="command"><code>{}</code><button type="button" data-command="{}">Copy</button></div>"#,
+        escape(&command),
+        escape(&command)
+    );
+    out.push_str("</figcaption></figure>\n");
+}
+
+/// Wrap a page body with the shared header and navigation
+fn layout(title: &str, count: usize, current: &str, pages: &[Page], body: &str) -> String {
+    let mut nav = String::new();
+    for (file, label) in std::iter::once(("index.html", "All categories"))
+        .chain(pages.iter().map(|p| (p.file.as_str(), p.title.as_str())))
+    {
+        let class = if file == current {
+            r#" class="current""#
+        } else {
+            ""
+        };
+        let _ = write!(
+            nav,
+            r#"<a href="{}"{}>{}</a>"#,
+            escape(file),
+            class,
+            escape(label)
+        );
+    }
+
+    let title = escape(title);
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{} · wcapp gallery</title>
+<link rel="stylesheet" href="style.css">
+</head>
+<body>
+<header>
+<h1>{} <span class="count">({})</span></h1>
+<nav>{}</nav>
+</header>
+<main>
+{}</main>
+<script>
+{}</script>
+</body>
+</html>
+"#,
+        title, title, count, nav, body, SCRIPT
+    )
+}
+
+/// Delete thumbnails of wallpapers that are gone or have changed
+///
+/// Only files named like a thumbnail are touched, so anything else kept in
+/// the folder stays.
+fn remove_stale_thumbnails(thumbs_dir: &Path, cards: &[Card]) -> Result<usize> {
+    let wanted: HashSet<&str> = cards
+        .iter()
+        .filter_map(|c| c.thumbnail.as_deref())
+        .collect();
+
+    let mut removed = 0;
+    for entry in fs::read_dir(thumbs_dir).context("Failed to read thumbnail directory")? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if path.is_file() && is_thumbnail(name) && !wanted.contains(name) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether `name` is what thumbnails are called: a SHA-256 hash and `.jpg`
+fn is_thumbnail(name: &str) -> bool {
+    name.strip_suffix(".jpg").is_some_and(|hash| {
+        hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    })
+}
+
+/// Delete pages an earlier run wrote for categories that no longer exist
+///
+/// The pages written are listed in a manifest in `out`, and only pages it
+/// lists are deleted, so other files in the folder stay.
+fn remove_stale_pages(out: &Path, pages: &[Page]) -> Result<()> {
+    let manifest_path = out.join(MANIFEST);
+    let previous = fs::read_to_string(&manifest_path).unwrap_or_default();
+
+    for name in stale_pages(&previous, pages) {
+        let path = out.join(name);
+        if path.is_file() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+
+    let manifest: String = pages.iter().map(|p| format!("{}\n", p.file)).collect();
+    write_if_changed(&manifest_path, &manifest)
+}
+
+/// Pages in the `previous` manifest that are no longer among `pages`
+fn stale_pages<'a>(previous: &'a str, pages: &[Page]) -> Vec<&'a str> {
+    let wanted: HashSet<&str> = pages.iter().map(|p| p.file.as_str()).collect();
+    previous
+        .lines()
+        .map(str::trim)
+        // A manifest edited by hand must not reach outside the folder
+        .filter(|name| name.ends_with(".html") && !name.contains(['/', '\\']))
+        .filter(|name| !wanted.contains(name))
+        .collect()
+}
+
+/// Write a file only when its content changed, keeping timestamps stable
+fn write_if_changed(path: &Path, content: &str) -> Result<()> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Lowercase ASCII letters and digits separated by dashes, for file names
+fn slug(text: &str) -> String {
+    let slug: String = text
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let slug = slug
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if slug.is_empty() {
+        "category".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Quote a name for a POSIX shell, only when needed
+fn shell_quote(name: &str) -> String {
+    let safe = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-".contains(c));
+    if safe {
+        return name.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    for c in name.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `file://` URL of a local file
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut url = String::from(if path.starts_with('/') {
+        "file://"
+    } else {
+        "file:///"
+    });
+
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~:".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            let _ = write!(url, "%{:02X}", byte);
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn page(file: &str) -> Page {
+        Page {
+            title: file.to_string(),
+            file: file.to_string(),
+            cards: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_hash_names_count_as_thumbnails() {
+        assert!(is_thumbnail(&format!("{HASH}.jpg")));
+        assert!(!is_thumbnail(&format!("{HASH}.png")));
+        assert!(!is_thumbnail(&format!("{}.jpg", HASH.to_uppercase())));
+        assert!(!is_thumbnail(&format!("{}.jpg", &HASH[1..])));
+        assert!(!is_thumbnail("holiday.jpg"));
+    }
+
+    #[test]
+    fn only_pages_from_the_manifest_go() {
+        let previous = "category-nature.html\ncategory-city.html\nfavorites.html\n";
+        let pages = [page("category-nature.html"), page("uncategorized.html")];
+
+        assert_eq!(
+            stale_pages(previous, &pages),
+            ["category-city.html", "favorites.html"]
+        );
+        // Without a manifest nothing is deleted, whatever the folder holds
+        assert!(stale_pages("", &pages).is_empty());
+    }
+
+    #[test]
+    fn manifest_entries_cannot_leave_the_folder() {
+        let previous = "../notes.html\nsub/page.html\nC:\\page.html\nnotes.txt\n";
+        assert!(stale_pages(previous, &[]).is_empty());
+    }
+
+    #[test]
+    fn unrelated_files_next_to_thumbnails_stay() {
+        let dir = std::env::temp_dir().join(format!("wcapp-thumbs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stale = format!("{}.jpg", "0".repeat(64));
+        for name in [stale.as_str(), "notes.txt", "holiday.jpg"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let removed = remove_stale_thumbnails(&dir, &[]).unwrap();
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(removed, 1);
+        assert_eq!(left, ["holiday.jpg", "notes.txt"]);
+    }
+}
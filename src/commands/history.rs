@@ -0,0 +1,42 @@
+use anyhow::Result;
+
+use crate::history;
+use crate::library::Library;
+use crate::preview;
+
+/// Show the most recently set wallpapers, newest first
+pub fn execute(limit: usize, preview: bool) -> Result<()> {
+    let history = history::load_history()?;
+
+    if history.entries.is_empty() {
+        println!("No wallpapers have been set yet.");
+        return Ok(());
+    }
+
+    let library = Library::open()?;
+
+    println!("Recently set wallpapers:");
+    println!();
+
+    for entry in history.entries.iter().rev().take(limit) {
+        let shown_at = chrono::DateTime::from_timestamp(entry.shown_at, 0)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+
+        match library.find(&entry.name) {
+            Some(wallpaper) => {
+                println!("  {}  {}", shown_at, entry.name);
+                if preview {
+                    preview::print_thumbnail(&wallpaper.path);
+                }
+            }
+            None => println!("  {}  {} (no longer in the library)", shown_at, entry.name),
+        }
+    }
+
+    Ok(())
+}
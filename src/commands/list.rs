@@ -1,76 +1,79 @@
-use anyhow::{Context, Result};
-use std::fs;
-use walkdir::WalkDir;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
-use crate::config;
+use crate::library::{Filter, Library};
+use crate::preview;
+use crate::query::Query;
 
-/// List all available wallpapers
-pub fn execute() -> Result<()> {
-    let wallpaper_dir = config::get_wallpaper_dir()?;
+/// List all available wallpapers, with a thumbnail under each one if `preview` is set
+pub fn execute(favorites: bool, query: Option<Query>, preview: bool) -> Result<()> {
+    let mut library = Library::open()?;
 
-    if !wallpaper_dir.exists() {
+    if !library.dir.exists() {
         println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
         return Ok(());
     }
 
-    println!("Available wallpapers in {}:", wallpaper_dir.display());
+    println!("Available wallpapers in {}:", library.dir.display());
     println!();
 
-    let image_extensions = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
-    let mut total_count = 0;
+    let filtered = favorites || query.is_some();
+    let wallpapers = library.select(&Filter {
+        favorites,
+        skip_banned: false,
+        query,
+    })?;
+
+    let mut categories: BTreeMap<String, Vec<(String, PathBuf)>> = BTreeMap::new();
+    for wallpaper in &wallpapers {
+        let Some(category) = &wallpaper.category else {
+            continue;
+        };
 
-    let mut categories = Vec::new();
-    for entry in fs::read_dir(&wallpaper_dir).context("Failed to read wallpaper directory")? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            categories.push(path);
+        let mut line = wallpaper.name.clone();
+        if let Some(entry) = library.state_of(wallpaper)? {
+            if entry.favorite {
+                line.push_str(" ★");
+            }
+            if let Some(rating) = entry.rating {
+                line.push_str(&format!(" ({}/5)", rating));
+            }
+            if entry.banned {
+                line.push_str(" [banned]");
+            }
+            for tag in &entry.tags {
+                line.push_str(&format!(" #{}", tag));
+            }
         }
+
+        categories
+            .entry(category.clone())
+            .or_default()
+            .push((line, wallpaper.path.clone()));
     }
+    library.save_index()?;
 
     if categories.is_empty() {
-        println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
+        if filtered {
+            println!("No wallpapers match the given filters.");
+        } else {
+            println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
+        }
         return Ok(());
     }
 
-    categories.sort();
-
-    for category_path in categories {
-        let category_name = category_path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy();
-
-        let mut wallpapers = Vec::new();
-
-        for entry in WalkDir::new(&category_path)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if image_extensions
-                        .contains(&ext.to_str().unwrap_or("").to_lowercase().as_str())
-                    {
-                        if let Ok(relative) = path.strip_prefix(&wallpaper_dir) {
-                            wallpapers.push(relative.display().to_string());
-                        }
-                    }
-                }
-            }
-        }
-
-        if !wallpapers.is_empty() {
-            println!("[{}]", category_name);
-            wallpapers.sort();
-            for wallpaper in &wallpapers {
-                println!("  - {}", wallpaper);
+    let mut total_count = 0;
+    for (category_name, wallpapers) in &categories {
+        println!("[{}]", category_name);
+        for (line, path) in wallpapers {
+            println!("  - {}", line);
+            if preview {
+                preview::print_thumbnail(path);
             }
-            println!();
-            total_count += wallpapers.len();
         }
+        println!();
+        total_count += wallpapers.len();
     }
 
     println!("Total: {} wallpapers", total_count);
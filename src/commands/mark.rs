@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use crate::library::Library;
+use crate::state::WallpaperState;
+
+/// Mark a wallpaper as a favorite
+pub fn favorite(name: &str) -> Result<()> {
+    update(name, |entry| entry.favorite = true)?;
+    println!("★ Added {} to favorites", name);
+    Ok(())
+}
+
+/// Remove a wallpaper from the favorites
+pub fn unfavorite(name: &str) -> Result<()> {
+    update(name, |entry| entry.favorite = false)?;
+    println!("Removed {} from favorites", name);
+    Ok(())
+}
+
+/// Rate a wallpaper from 1 to 5
+pub fn rate(name: &str, rating: u8) -> Result<()> {
+    update(name, |entry| entry.rating = Some(rating))?;
+    println!("Rated {} {}/5", name, rating);
+    Ok(())
+}
+
+/// Hide a wallpaper from random selection and cycling
+pub fn ban(name: &str) -> Result<()> {
+    update(name, |entry| entry.banned = true)?;
+    println!("Banned {}", name);
+    Ok(())
+}
+
+/// Allow a banned wallpaper to be picked again
+pub fn unban(name: &str) -> Result<()> {
+    update(name, |entry| entry.banned = false)?;
+    println!("Unbanned {}", name);
+    Ok(())
+}
+
+fn update(name: &str, f: impl FnOnce(&mut WallpaperState)) -> Result<()> {
+    let mut library = Library::open()?;
+    library.update(name, f)?;
+    library.save()
+}
@@ -1,7 +1,20 @@
 pub mod clean;
+pub mod config;
 pub mod cycle;
 pub mod fetch;
+pub mod gallery;
+pub mod history;
 pub mod list;
+pub mod mark;
+pub mod monitors;
+pub mod palette;
+pub mod playlist;
+pub mod profile;
+pub mod restore;
+pub mod serve;
 pub mod set;
+pub mod stats;
+pub mod tag;
+pub mod tui;
 pub mod uninstall;
 pub mod update;
@@ -0,0 +1,34 @@
+use anyhow::Result;
+
+use crate::config;
+use crate::display;
+
+/// List the monitors wallpapers can be set on, and what each one shows
+pub fn execute() -> Result<()> {
+    let config = config::load_config()?;
+    let monitors = display::layout(config.as_ref());
+    let screens = display::load_screens()?;
+    let from_config = config.is_some_and(|c| !c.monitors.is_empty());
+
+    println!("Monitors:");
+    println!();
+
+    for (i, monitor) in monitors.iter().enumerate() {
+        let primary = if monitor.primary { " (primary)" } else { "" };
+        println!(
+            "  {}: {}  {}x{}+{}+{}{}",
+            i, monitor.name, monitor.width, monitor.height, monitor.x, monitor.y, primary
+        );
+        if let Some(shown) = screens.on(monitor) {
+            println!("     {}", shown.name);
+        }
+    }
+
+    println!();
+    if from_config {
+        println!("Layout from the config file");
+    } else {
+        println!("Layout detected from the system. Set [[monitors]] in the config to override it.");
+    }
+    Ok(())
+}
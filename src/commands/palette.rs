@@ -0,0 +1,102 @@
+use anyhow::Result;
+use std::io::{self, IsTerminal};
+
+use crate::config;
+use crate::history;
+use crate::library::{Library, Wallpaper};
+use crate::matching::Resolved;
+use crate::palette::{Format, Palette};
+use crate::render::Color;
+
+/// Show the colour palette of a wallpaper, the current one by default
+///
+/// With `format`, the palette is printed in that format instead. With
+/// `write`, the theme files from the config are written as well.
+pub fn execute(name: Option<&str>, format: Option<Format>, write: bool) -> Result<()> {
+    let library = Library::open()?;
+    let wallpaper = match name {
+        Some(name) => find(&library, name)?,
+        None => current(&library)?,
+    };
+
+    let palette = Palette::extract(&wallpaper.path)?;
+
+    if let Some(format) = format {
+        print!("{}", palette.format(format, &wallpaper.path));
+    } else {
+        print_swatches(&wallpaper, &palette);
+    }
+
+    if write {
+        let config = config::load_config()?;
+        let themes = config
+            .as_ref()
+            .map(|c| c.themes.as_slice())
+            .unwrap_or_default();
+        if themes.is_empty() {
+            println!("No theme files configured. Add [[themes]] to the config file.");
+        }
+        for theme in themes {
+            let path = theme.write(&palette, &wallpaper.path)?;
+            println!("✓ Wrote {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Look up a wallpaper by name, failing when it is ambiguous or missing
+fn find(library: &Library, name: &str) -> Result<Wallpaper> {
+    match library.resolve(name) {
+        Resolved::Found(wallpaper) => Ok(wallpaper.clone()),
+        Resolved::Ambiguous(candidates) => {
+            let list: Vec<String> = candidates
+                .iter()
+                .map(|c| format!("  - {}", c.name))
+                .collect();
+            anyhow::bail!(
+                "'{}' matches several wallpapers:\n{}\nPlease use a more specific name",
+                name,
+                list.join("\n")
+            );
+        }
+        Resolved::NotFound(_) => {
+            anyhow::bail!(
+                "Wallpaper '{}' not found in {}",
+                name,
+                library.dir.display()
+            )
+        }
+    }
+}
+
+/// The most recently set wallpaper that is still in the library
+fn current(library: &Library) -> Result<Wallpaper> {
+    let history = history::load_history()?;
+    history
+        .entries
+        .iter()
+        .rev()
+        .find_map(|entry| library.find(&entry.name).cloned())
+        .ok_or_else(|| anyhow::anyhow!("No wallpaper has been set yet. Pass a name instead."))
+}
+
+fn print_swatches(wallpaper: &Wallpaper, palette: &Palette) {
+    let color = io::stdout().is_terminal();
+    let swatch = |c: &Color| {
+        if color {
+            let [r, g, b] = c.0;
+            format!("\x1b[48;2;{};{};{}m    \x1b[0m {}", r, g, b, c)
+        } else {
+            c.to_string()
+        }
+    };
+
+    println!("Palette of {}", wallpaper.name);
+    println!();
+    println!("  background  {}", swatch(&palette.background));
+    println!("  foreground  {}", swatch(&palette.foreground));
+    println!();
+    for (i, c) in palette.colors.iter().enumerate() {
+        println!("  color{:<5} {}", i, swatch(c));
+    }
+}
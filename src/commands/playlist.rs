@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::effects::Effects;
+use crate::library::Library;
+use crate::playlist::{self, Playlist, PlaylistEntry};
+use crate::query::Query;
+
+/// Create an empty playlist, or a saved query when `query` is given
+pub fn create(
+    name: &str,
+    query: Option<String>,
+    shuffle: bool,
+    effects: Option<Effects>,
+) -> Result<()> {
+    let path = playlist::get_playlist_path(name)?;
+    if path.exists() {
+        anyhow::bail!("Playlist '{}' already exists", name);
+    }
+
+    if let Some(query) = &query {
+        Query::from_str(query)?;
+    }
+
+    let is_query = query.is_some();
+    playlist::save_playlist(
+        name,
+        &Playlist {
+            query,
+            shuffle,
+            effects: effects.map(|e| e.to_string()),
+            wallpapers: Vec::new(),
+        },
+    )?;
+
+    if is_query {
+        println!("✓ Created query playlist '{}'", name);
+    } else {
+        println!("✓ Created playlist '{}'", name);
+        println!("Add wallpapers with: wcapp playlist add {} <name>...", name);
+    }
+    Ok(())
+}
+
+/// Append wallpapers to a static playlist
+pub fn add(name: &str, wallpapers: &[String]) -> Result<()> {
+    let mut playlist = load_static(name)?;
+    let mut library = Library::open()?;
+
+    for wallpaper_name in wallpapers {
+        let wallpaper = library.find(wallpaper_name).cloned().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Wallpaper '{}' not found in {}",
+                wallpaper_name,
+                library.dir.display()
+            )
+        })?;
+        let hash = library.hash(&wallpaper)?;
+
+        if playlist.wallpapers.iter().any(|e| e.hash == hash) {
+            println!("{} is already in '{}'", wallpaper.name, name);
+            continue;
+        }
+
+        playlist.wallpapers.push(PlaylistEntry {
+            hash,
+            name: wallpaper.name.clone(),
+        });
+        println!("✓ Added {} to '{}'", wallpaper.name, name);
+    }
+
+    library.save_index()?;
+    playlist::save_playlist(name, &playlist)
+}
+
+/// Remove wallpapers from a static playlist
+pub fn remove(name: &str, wallpapers: &[String]) -> Result<()> {
+    let mut playlist = load_static(name)?;
+    let mut library = Library::open()?;
+
+    for wallpaper_name in wallpapers {
+        let hash = match library.find(wallpaper_name).cloned() {
+            Some(wallpaper) => Some(library.hash(&wallpaper)?),
+            None => None,
+        };
+
+        let before = playlist.wallpapers.len();
+        playlist
+            .wallpapers
+            .retain(|e| e.name != *wallpaper_name && Some(&e.hash) != hash.as_ref());
+
+        if playlist.wallpapers.len() < before {
+            println!("✓ Removed {} from '{}'", wallpaper_name, name);
+        } else {
+            println!("{} is not in '{}'", wallpaper_name, name);
+        }
+    }
+
+    library.save_index()?;
+    playlist::save_playlist(name, &playlist)
+}
+
+/// Show the wallpapers in a playlist, or list all playlists
+pub fn show(name: Option<&str>) -> Result<()> {
+    let Some(name) = name else {
+        let names = playlist::list_playlists()?;
+        if names.is_empty() {
+            println!("No playlists yet. Use 'playlist create <name>' to create one.");
+            return Ok(());
+        }
+
+        for name in names {
+            match playlist::load_playlist(&name) {
+                Ok(playlist) => match &playlist.query {
+                    Some(query) => println!("{} (query: {})", name, query),
+                    None => println!("{} ({} wallpapers)", name, playlist.wallpapers.len()),
+                },
+                Err(e) => println!("{} (invalid: {:#})", name, e),
+            }
+        }
+        return Ok(());
+    };
+
+    let playlist = playlist::load_playlist(name)?;
+    let mut library = Library::open()?;
+    let wallpapers = playlist.resolve(&mut library)?;
+    library.save_index()?;
+
+    println!("Playlist '{}'", name);
+    if let Some(query) = &playlist.query {
+        println!("Query: {}", query);
+    }
+    let order = if playlist.shuffle {
+        "shuffled"
+    } else {
+        "in order"
+    };
+    println!("Order: {}", order);
+    if let Some(effects) = &playlist.effects {
+        println!("Effects: {}", effects);
+    }
+    println!();
+
+    for (i, wallpaper) in wallpapers.iter().enumerate() {
+        println!("  {}. {}", i + 1, wallpaper.name);
+    }
+
+    let missing = if playlist.query.is_none() {
+        playlist.wallpapers.len().saturating_sub(wallpapers.len())
+    } else {
+        0
+    };
+
+    println!();
+    println!("Total: {} wallpapers", wallpapers.len());
+    if missing > 0 {
+        println!("({} entries are missing or banned)", missing);
+    }
+    Ok(())
+}
+
+/// Delete a playlist
+pub fn delete(name: &str) -> Result<()> {
+    let path = playlist::get_playlist_path(name)?;
+    if !path.exists() {
+        anyhow::bail!("Playlist '{}' not found", name);
+    }
+
+    fs::remove_file(&path).context("Failed to remove playlist file")?;
+    println!("✓ Deleted playlist '{}'", name);
+    Ok(())
+}
+
+/// Write a playlist to a file, or to stdout
+pub fn export(name: &str, output: Option<&Path>) -> Result<()> {
+    let playlist = playlist::load_playlist(name)?;
+    let content = toml::to_string(&playlist).context("Failed to serialize playlist")?;
+
+    match output {
+        Some(path) => {
+            fs::write(path, content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("✓ Exported '{}' to {}", name, path.display());
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+/// Import a playlist file, named after the file unless `name` is given
+pub fn import(file: &Path, name: Option<&str>, force: bool) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .context("Could not derive a playlist name from the file name")?,
+    };
+
+    let content =
+        fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let playlist = playlist::parse_playlist(&content)
+        .with_context(|| format!("Invalid playlist {}", file.display()))?;
+
+    let path = playlist::get_playlist_path(&name)?;
+    if path.exists() && !force {
+        anyhow::bail!(
+            "Playlist '{}' already exists. Use --force to overwrite it or --name to pick another name.",
+            name
+        );
+    }
+
+    playlist::save_playlist(&name, &playlist)?;
+    println!("✓ Imported playlist '{}'", name);
+    Ok(())
+}
+
+fn load_static(name: &str) -> Result<Playlist> {
+    let playlist = playlist::load_playlist(name)?;
+    if playlist.query.is_some() {
+        anyhow::bail!(
+            "Playlist '{}' is a saved query; its wallpapers cannot be edited by hand",
+            name
+        );
+    }
+    Ok(playlist)
+}
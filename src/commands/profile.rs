@@ -0,0 +1,101 @@
+use anyhow::Result;
+use toml::{Table, Value};
+
+use crate::config::{self, NO_PROFILE, Origin};
+use crate::control::{self, Message};
+
+/// Make `name` the active profile, and switch a running `cycle` over to it
+pub fn use_profile(name: &str) -> Result<()> {
+    // Check against the files alone, so that a profile that went missing
+    // can still be replaced
+    config::switch_profile(Some(NO_PROFILE));
+    if name != NO_PROFILE {
+        config::find_profile(&config::merge(&config::layers()?), name)?;
+    }
+
+    config::update(|table| {
+        if name == NO_PROFILE {
+            table.remove("profile");
+        } else {
+            table.insert("profile".to_string(), Value::String(name.to_string()));
+        }
+    })?;
+    if name == NO_PROFILE {
+        println!("✓ Stopped using profiles");
+    } else {
+        println!("✓ Using profile '{}'", name);
+    }
+
+    let layers = config::layers()?;
+    if let Some(layer) = layers
+        .iter()
+        .rev()
+        .find(|layer| layer.origin != Origin::Profile && layer.table.contains_key("profile"))
+        && layer.origin > Origin::User
+    {
+        println!("Note: {} overrides this setting", layer.source);
+    }
+
+    if control::send(&Message::Profile(name.to_string()))? {
+        println!("✓ Switched the running cycle over");
+    }
+    Ok(())
+}
+
+/// List the profiles in the config, marking the active one
+pub fn list() -> Result<()> {
+    let profiles = defined()?;
+    if profiles.is_empty() {
+        println!(
+            "No profiles defined. Add a [profiles.<name>] table to {}",
+            config::get_config_path()?.display()
+        );
+        return Ok(());
+    }
+
+    let active = active()?;
+    for name in profiles.keys() {
+        let marker = if active.as_deref() == Some(name.as_str()) {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {}", marker, name);
+    }
+    Ok(())
+}
+
+/// Print the settings of profile `name`, or of the active profile
+pub fn show(name: Option<&str>) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => active()?.ok_or_else(|| {
+            anyhow::anyhow!("No profile is in use. Name one, e.g. 'wcapp profile show work'")
+        })?,
+    };
+
+    let merged = config::merge(&config::layers()?);
+    let profile = config::find_profile(&merged, &name)?;
+    if profile.is_empty() {
+        println!("Profile '{}' changes no settings", name);
+    } else {
+        print!("{}", toml::to_string(profile)?);
+    }
+    Ok(())
+}
+
+/// Every profile defined by the config files
+fn defined() -> Result<Table> {
+    let mut merged = config::merge(&config::layers()?);
+    match merged.remove("profiles") {
+        Some(Value::Table(profiles)) => Ok(profiles),
+        _ => Ok(Table::new()),
+    }
+}
+
+/// Name of the profile in use, if any
+fn active() -> Result<Option<String>> {
+    Ok(config::load_config()?
+        .and_then(|config| config.profile)
+        .filter(|name| name != NO_PROFILE))
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::journal;
+
+/// Bring back the files the last `clean` moved to the trash
+pub fn execute() -> Result<()> {
+    let mut journal = journal::load_journal()?;
+    if let Some(deleted) = journal.last_deleted() {
+        println!(
+            "The {} files removed on {} were deleted permanently, so they cannot be restored",
+            deleted.paths.len(),
+            format_time(deleted.removed_at)
+        );
+    }
+    let Some(entry) = journal.last_mut() else {
+        println!("Nothing to restore");
+        return Ok(());
+    };
+
+    println!(
+        "Restoring what was removed on {}",
+        format_time(entry.removed_at)
+    );
+    let outcome = restore_from_trash(&entry.paths, entry.removed_at)?;
+    entry.restored = true;
+    let total = entry.paths.len();
+    journal::save_journal(&journal)?;
+
+    for path in &outcome.restored {
+        println!("✓ {}", path.display());
+    }
+    for path in &outcome.taken {
+        println!(
+            "✗ {} exists again, so its copy was left in the trash",
+            path.display()
+        );
+    }
+    for path in &outcome.missing {
+        println!("✗ {} is no longer in the trash", path.display());
+    }
+    println!("Restored {} of {}", outcome.restored.len(), total);
+    Ok(())
+}
+
+fn format_time(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// What happened to each path of a removal
+#[derive(Default)]
+struct Outcome {
+    restored: Vec<PathBuf>,
+    /// Something new lives at the original path
+    taken: Vec<PathBuf>,
+    /// The trash was emptied since
+    missing: Vec<PathBuf>,
+}
+
+#[cfg(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+))]
+fn restore_from_trash(paths: &[PathBuf], removed_at: i64) -> Result<Outcome> {
+    use anyhow::Context;
+    use std::fs;
+
+    let items = trash::os_limited::list()
+        .map_err(|e| anyhow::anyhow!("Failed to read the trash: {}", e))?;
+
+    let mut outcome = Outcome::default();
+    let mut chosen = Vec::new();
+    for path in paths {
+        if path.exists() {
+            outcome.taken.push(path.clone());
+            continue;
+        }
+
+        // The newest copy trashed since the removal, allowing for clock rounding
+        let item = items
+            .iter()
+            .filter(|item| item.original_path() == *path && item.time_deleted >= removed_at - 60)
+            .max_by_key(|item| item.time_deleted);
+        match item {
+            Some(item) => {
+                chosen.push(item.clone());
+                outcome.restored.push(path.clone());
+            }
+            None => outcome.missing.push(path.clone()),
+        }
+    }
+
+    // Empty category folders were removed along with the files
+    for path in &outcome.restored {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+    }
+    trash::os_limited::restore_all(chosen)
+        .map_err(|e| anyhow::anyhow!("Failed to restore from the trash: {}", e))?;
+    Ok(outcome)
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+)))]
+fn restore_from_trash(_paths: &[PathBuf], _removed_at: i64) -> Result<Outcome> {
+    anyhow::bail!(
+        "Restoring from the trash is not supported on this system. Use Put Back in the Trash instead"
+    )
+}
@@ -0,0 +1,1062 @@
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tiny_http::{Header, Server};
+
+use crate::config::{self, Config};
+use crate::effects::Effects;
+use crate::history::{self, History};
+use crate::library::{Filter, Library, Wallpaper};
+use crate::matching::{self, Resolved};
+use crate::playlist::{self, Playlist};
+use crate::query::Query;
+use crate::selection::{PickOptions, Selector};
+use crate::setter::{self, Appearance};
+use crate::thumbnail;
+
+/// Routes served by the API, listed by `GET /api`
+const ENDPOINTS: [&str; 9] = [
+    "GET /api/wallpapers?query=&favorites=",
+    "GET /api/current",
+    "POST /api/set?name=",
+    "POST /api/random?query=&favorites=&playlist=&weighted=&seed=",
+    "POST /api/next",
+    "POST /api/prev",
+    "POST /api/favorite?name=",
+    "DELETE /api/favorite?name=",
+    "GET /api/thumbnail?name=",
+];
+
+/// Environment variable the token can be passed in, out of sight of `ps`
+const TOKEN_VAR: &str = "WCAPP_API_TOKEN";
+
+/// Serve the JSON API on `bind` until interrupted
+///
+/// Every request needs the token, taken from `--token`, `--token-file` or
+/// `WCAPP_API_TOKEN`, or made up for this run when none is given.
+pub fn execute(bind: &str, token: Option<String>, token_file: Option<PathBuf>) -> Result<()> {
+    let (token, generated) = match find_token(token, token_file.as_deref())? {
+        Some(token) => (token, false),
+        None => (
+            format!(
+                "{:016x}{:016x}",
+                rand::random::<u64>(),
+                rand::random::<u64>()
+            ),
+            true,
+        ),
+    };
+
+    let server =
+        Server::http(bind).map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", bind, e))?;
+
+    println!("wcapp API listening on http://{}/api", bind);
+    println!("Requests must send 'Authorization: Bearer <token>' or '?token=<token>'");
+    if generated {
+        println!("Token for this run: {}", token);
+    }
+    println!();
+    println!("Press Ctrl+C to stop");
+    println!();
+
+    let api = Api {
+        token,
+        backend: Box::new(System),
+    };
+    for request in server.incoming_requests() {
+        answer(&api, request);
+    }
+
+    Ok(())
+}
+
+/// Handle one HTTP request and send the response
+fn answer(api: &Api, request: tiny_http::Request) {
+    let api_request = ApiRequest::from_http(&request);
+    let response = api.handle(&api_request);
+
+    let now = chrono::Local::now();
+    println!(
+        "[{}] {} {} -> {}",
+        now.format("%H:%M:%S"),
+        api_request.method,
+        api_request.path,
+        response.status
+    );
+
+    // Any page may call the API, since none can without the token
+    let http_response = tiny_http::Response::from_data(response.body)
+        .with_status_code(response.status)
+        .with_header(header("Content-Type", response.content_type))
+        .with_header(header("Access-Control-Allow-Origin", "*"))
+        .with_header(header("Access-Control-Allow-Headers", "Authorization"))
+        .with_header(header("Access-Control-Allow-Methods", "GET, POST, DELETE"));
+    if let Err(e) = request.respond(http_response) {
+        eprintln!("Failed to send response: {}", e);
+    }
+}
+
+/// The token from the command line, a file or the environment, in that order
+fn find_token(token: Option<String>, token_file: Option<&Path>) -> Result<Option<String>> {
+    let (token, source) = match (token, token_file) {
+        (Some(token), _) => (token, "--token".to_string()),
+        (None, Some(path)) => (
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read token file {}", path.display()))?,
+            path.display().to_string(),
+        ),
+        (None, None) => match env::var(TOKEN_VAR) {
+            Ok(token) => (token, TOKEN_VAR.to_string()),
+            Err(_) => return Ok(None),
+        },
+    };
+
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("The token from {} is empty", source);
+    }
+    Ok(Some(token.to_string()))
+}
+
+/// Whether `given` is the token, taking the same time wherever they differ
+fn same_token(given: &str, token: &str) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("static header is valid")
+}
+
+/// An HTTP request reduced to what the API looks at, so routing does not
+/// depend on the HTTP server
+struct ApiRequest {
+    method: String,
+    path: String,
+    params: HashMap<String, String>,
+    /// Bearer token from the Authorization header
+    bearer: Option<String>,
+}
+
+impl ApiRequest {
+    fn from_http(request: &tiny_http::Request) -> Self {
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+
+        let params = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect();
+
+        let bearer = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+
+        Self {
+            method: request.method().as_str().to_uppercase(),
+            path: path.trim_end_matches('/').to_string(),
+            params,
+            bearer,
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .get(name)
+            .map(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// A flag is set when present without a value, or set to a true-ish value
+    fn flag(&self, name: &str) -> bool {
+        self.params
+            .get(name)
+            .is_some_and(|v| matches!(v.as_str(), "" | "1" | "true" | "yes"))
+    }
+}
+
+#[derive(Debug)]
+struct ApiResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl ApiResponse {
+    fn json(status: u16, value: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    fn ok(value: Value) -> Self {
+        Self::json(200, value)
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, json!({ "error": message.into() }))
+    }
+}
+
+/// Where the API finds the library, its state and history, and how it sets
+/// wallpapers, so tests can run it against a library of their own
+trait Backend {
+    /// The library, with the state of its wallpapers
+    fn library(&self) -> Result<Library>;
+    /// Keep the state of `library`, and any hashes it computed
+    fn save(&self, library: &Library) -> Result<()>;
+    /// Keep the hashes `library` computed, leaving the state alone
+    fn save_index(&self, library: &Library) -> Result<()>;
+    fn history(&self) -> Result<History>;
+    fn config(&self) -> Result<Option<Config>>;
+    fn playlist(&self, name: &str) -> Result<Playlist>;
+    fn thumbnails_dir(&self) -> Result<PathBuf>;
+    /// Set `wallpaper` on the desktop and add it to the history
+    fn apply(
+        &self,
+        library: &mut Library,
+        wallpaper: &Wallpaper,
+        effects: Option<Effects>,
+    ) -> Result<()>;
+}
+
+/// The user's own library and desktop
+struct System;
+
+impl Backend for System {
+    fn library(&self) -> Result<Library> {
+        Library::open()
+    }
+
+    fn save(&self, library: &Library) -> Result<()> {
+        library.save()
+    }
+
+    fn save_index(&self, library: &Library) -> Result<()> {
+        library.save_index()
+    }
+
+    fn history(&self) -> Result<History> {
+        history::load_history()
+    }
+
+    fn config(&self) -> Result<Option<Config>> {
+        config::load_config()
+    }
+
+    fn playlist(&self, name: &str) -> Result<Playlist> {
+        playlist::load_playlist(name)
+    }
+
+    fn thumbnails_dir(&self) -> Result<PathBuf> {
+        thumbnail::get_thumbnails_dir()
+    }
+
+    fn apply(
+        &self,
+        library: &mut Library,
+        wallpaper: &Wallpaper,
+        effects: Option<Effects>,
+    ) -> Result<()> {
+        let appearance = Appearance::resolve(None, None, effects, self.config()?.as_ref());
+        setter::apply(library, wallpaper, appearance)
+    }
+}
+
+struct Api {
+    token: String,
+    backend: Box<dyn Backend>,
+}
+
+impl Api {
+    fn handle(&self, request: &ApiRequest) -> ApiResponse {
+        if request.method == "OPTIONS" {
+            return ApiResponse {
+                status: 204,
+                content_type: "text/plain",
+                body: Vec::new(),
+            };
+        }
+
+        let given = request.bearer.as_deref().or(request.param("token"));
+        if !given.is_some_and(|given| same_token(given, &self.token)) {
+            return ApiResponse::error(401, "Missing or invalid token");
+        }
+
+        match self.route(request) {
+            Ok(response) => response,
+            Err(e) => ApiResponse::error(500, format!("{:#}", e)),
+        }
+    }
+
+    fn route(&self, request: &ApiRequest) -> Result<ApiResponse> {
+        let backend = self.backend.as_ref();
+        let method = request.method.as_str();
+        match (method, request.path.as_str()) {
+            ("GET", "/api") => Ok(ApiResponse::ok(json!({ "endpoints": ENDPOINTS }))),
+            ("GET", "/api/wallpapers") => list(backend, request),
+            ("GET", "/api/current") => current(backend),
+            ("POST", "/api/set") => set(backend, request),
+            ("POST", "/api/random") => random(backend, request),
+            ("POST", "/api/next") => step(backend, 1),
+            ("POST", "/api/prev") => step(backend, -1),
+            ("POST", "/api/favorite") => favorite(backend, request, true),
+            ("DELETE", "/api/favorite") => favorite(backend, request, false),
+            ("GET", "/api/thumbnail") => thumbnail(backend, request),
+            (_, path)
+                if ENDPOINTS
+                    .iter()
+                    .any(|e| e.split([' ', '?']).nth(1) == Some(path)) =>
+            {
+                Ok(ApiResponse::error(
+                    405,
+                    format!("{} is not allowed on {}", method, path),
+                ))
+            }
+            (_, path) => Ok(ApiResponse::error(404, format!("No endpoint {}", path))),
+        }
+    }
+}
+
+/// Wallpapers in the library, optionally filtered
+fn list(backend: &dyn Backend, request: &ApiRequest) -> Result<ApiResponse> {
+    let query = match request.param("query").map(Query::from_str).transpose() {
+        Ok(query) => query,
+        Err(e) => return Ok(ApiResponse::error(400, format!("{:#}", e))),
+    };
+
+    let mut library = backend.library()?;
+    let wallpapers = library.select(&Filter {
+        favorites: request.flag("favorites"),
+        skip_banned: false,
+        query,
+    })?;
+
+    let mut items = Vec::new();
+    for wallpaper in &wallpapers {
+        items.push(describe(&mut library, wallpaper)?);
+    }
+    backend.save_index(&library)?;
+
+    Ok(ApiResponse::ok(
+        json!({ "total": items.len(), "wallpapers": items }),
+    ))
+}
+
+/// The wallpaper that was set most recently
+fn current(backend: &dyn Backend) -> Result<ApiResponse> {
+    let mut library = backend.library()?;
+    let Some((wallpaper, shown_at)) = last_set(backend, &library)? else {
+        return Ok(ApiResponse::ok(json!({ "current": null })));
+    };
+
+    let mut description = describe(&mut library, &wallpaper)?;
+    description["shown_at"] =
+        json!(chrono::DateTime::from_timestamp(shown_at, 0).map(|t| t.to_rfc3339()));
+    if let Some((width, height)) = library.dimensions(&wallpaper)? {
+        description["width"] = json!(width);
+        description["height"] = json!(height);
+    }
+    backend.save_index(&library)?;
+
+    Ok(ApiResponse::ok(json!({ "current": description })))
+}
+
+/// Set a wallpaper by name, matched like `wcapp set --name`
+fn set(backend: &dyn Backend, request: &ApiRequest) -> Result<ApiResponse> {
+    let Some(name) = request.param("name") else {
+        return Ok(ApiResponse::error(400, "Missing 'name' parameter"));
+    };
+
+    let mut library = backend.library()?;
+    let wallpaper = match find(&library.wallpapers, name) {
+        Ok(wallpaper) => wallpaper.clone(),
+        Err(response) => return Ok(response),
+    };
+
+    apply(backend, &mut library, &wallpaper, None)
+}
+
+/// The wallpaper `name` stands for, matched like `wcapp set --name`, or the
+/// response explaining why there is none
+fn find<'a>(wallpapers: &'a [Wallpaper], name: &str) -> Result<&'a Wallpaper, ApiResponse> {
+    match matching::resolve(wallpapers, name) {
+        Resolved::Found(wallpaper) => Ok(wallpaper),
+        Resolved::Ambiguous(candidates) => {
+            let names: Vec<&str> = candidates.iter().map(|c| c.name.as_str()).collect();
+            Err(ApiResponse::json(
+                409,
+                json!({
+                    "error": format!("'{}' matches several wallpapers", name),
+                    "candidates": names,
+                }),
+            ))
+        }
+        Resolved::NotFound(suggestions) => {
+            let names: Vec<&str> = suggestions.iter().map(|c| c.name.as_str()).collect();
+            Err(ApiResponse::json(
+                404,
+                json!({
+                    "error": format!("Wallpaper '{}' not found", name),
+                    "suggestions": names,
+                }),
+            ))
+        }
+    }
+}
+
+/// Set a random wallpaper, with the same filters as `wcapp set --random`
+fn random(backend: &dyn Backend, request: &ApiRequest) -> Result<ApiResponse> {
+    let query = match request.param("query").map(Query::from_str).transpose() {
+        Ok(query) => query,
+        Err(e) => return Ok(ApiResponse::error(400, format!("{:#}", e))),
+    };
+    let seed = match request.param("seed").map(u64::from_str).transpose() {
+        Ok(seed) => seed,
+        Err(_) => return Ok(ApiResponse::error(400, "'seed' must be a number")),
+    };
+    let options = PickOptions {
+        favorites: request.flag("favorites"),
+        query,
+        playlist: request.param("playlist").map(str::to_string),
+        weighted: request.flag("weighted"),
+        seed,
+    };
+
+    let mut library = backend.library()?;
+    let playlist = options
+        .playlist
+        .as_deref()
+        .map(|name| backend.playlist(name))
+        .transpose()?;
+    let config = backend.config()?;
+    let wallpapers = options.candidates(&mut library, playlist.as_ref(), config.as_ref())?;
+
+    let mut selector = Selector::from_config(config.as_ref(), options.seed, options.weighted);
+    let history = backend.history()?;
+    let Some(chosen) = selector.pick(&wallpapers, &mut library, &history)? else {
+        return Ok(ApiResponse::error(
+            404,
+            "No wallpapers match the given filters",
+        ));
+    };
+    let chosen = chosen.clone();
+    let effects = match &playlist {
+        Some(playlist) => playlist.parsed_effects()?,
+        None => None,
+    };
+
+    apply(backend, &mut library, &chosen, effects)
+}
+
+/// Set the next or previous wallpaper in library order, skipping banned ones
+fn step(backend: &dyn Backend, by: isize) -> Result<ApiResponse> {
+    let mut library = backend.library()?;
+    let wallpapers = library.select(&Filter {
+        skip_banned: true,
+        ..Filter::default()
+    })?;
+    if wallpapers.is_empty() {
+        return Ok(ApiResponse::error(404, "No wallpapers found"));
+    }
+
+    let current = last_set(backend, &library)?
+        .and_then(|(current, _)| wallpapers.iter().position(|w| w.path == current.path));
+    let len = wallpapers.len() as isize;
+    let index = match current {
+        Some(index) => (index as isize + by).rem_euclid(len) as usize,
+        None if by > 0 => 0,
+        None => wallpapers.len() - 1,
+    };
+
+    apply(backend, &mut library, &wallpapers[index], None)
+}
+
+/// Mark a wallpaper, or the current one when no name is given, as a favorite
+fn favorite(backend: &dyn Backend, request: &ApiRequest, favorite: bool) -> Result<ApiResponse> {
+    let mut library = backend.library()?;
+    let wallpaper = match request.param("name") {
+        Some(name) => match find(&library.wallpapers, name) {
+            Ok(wallpaper) => wallpaper.clone(),
+            Err(response) => return Ok(response),
+        },
+        None => match last_set(backend, &library)? {
+            Some((wallpaper, _)) => wallpaper,
+            None => return Ok(ApiResponse::error(404, "No wallpaper has been set yet")),
+        },
+    };
+
+    library.update(&wallpaper.name, |entry| entry.favorite = favorite)?;
+    backend.save(&library)?;
+
+    Ok(ApiResponse::ok(describe(&mut library, &wallpaper)?))
+}
+
+/// A JPEG thumbnail of a wallpaper, cached by content hash
+fn thumbnail(backend: &dyn Backend, request: &ApiRequest) -> Result<ApiResponse> {
+    let Some(name) = request.param("name") else {
+        return Ok(ApiResponse::error(400, "Missing 'name' parameter"));
+    };
+
+    let mut library = backend.library()?;
+    let wallpaper = match find(&library.wallpapers, name) {
+        Ok(wallpaper) => wallpaper.clone(),
+        Err(response) => return Ok(response),
+    };
+
+    let hash = library.hash(&wallpaper)?;
+    backend.save_index(&library)?;
+    let path = backend.thumbnails_dir()?.join(format!("{}.jpg", hash));
+    if let Err(e) = thumbnail::ensure(&wallpaper.path, &path) {
+        return Ok(ApiResponse::error(422, format!("{:#}", e)));
+    }
+
+    Ok(ApiResponse {
+        status: 200,
+        content_type: "image/jpeg",
+        body: fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+    })
+}
+
+/// Set a wallpaper and describe it in the response
+fn apply(
+    backend: &dyn Backend,
+    library: &mut Library,
+    wallpaper: &Wallpaper,
+    effects: Option<Effects>,
+) -> Result<ApiResponse> {
+    backend.apply(library, wallpaper, effects)?;
+    Ok(ApiResponse::ok(describe(library, wallpaper)?))
+}
+
+/// The most recently set wallpaper that is still in the library, and when it was set
+fn last_set(backend: &dyn Backend, library: &Library) -> Result<Option<(Wallpaper, i64)>> {
+    let history = backend.history()?;
+    Ok(history.entries.iter().rev().find_map(|entry| {
+        library
+            .find(&entry.name)
+            .map(|wallpaper| (wallpaper.clone(), entry.shown_at))
+    }))
+}
+
+fn describe(library: &mut Library, wallpaper: &Wallpaper) -> Result<Value> {
+    let state = library.state_of(wallpaper)?.cloned().unwrap_or_default();
+    let size = fs::metadata(&wallpaper.path).map(|m| m.len()).ok();
+
+    Ok(json!({
+        "name": wallpaper.name,
+        "category": wallpaper.category,
+        "size": size,
+        "favorite": state.favorite,
+        "rating": state.rating,
+        "banned": state.banned,
+        "tags": state.tags,
+    }))
+}
+
+/// Decode `%XX` escapes and `+` in a query string component
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let escaped = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match escaped {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use image::{Rgb, RgbImage};
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    const TOKEN: &str = "s3cret";
+
+    /// A library in a temporary folder, with state and history kept in memory
+    struct Fake {
+        dir: PathBuf,
+        state: RefCell<State>,
+        history: RefCell<History>,
+    }
+
+    impl Fake {
+        /// A library of small images at `names`, each a different colour
+        fn new(names: &[&str]) -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = env::temp_dir().join(format!(
+                "wcapp-serve-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            for (i, name) in names.iter().enumerate() {
+                let path = dir.join("library").join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                RgbImage::from_pixel(4, 3, Rgb([i as u8 * 40, 100, 200]))
+                    .save(&path)
+                    .unwrap();
+            }
+            Self {
+                dir,
+                state: RefCell::new(State::default()),
+                history: RefCell::new(History::default()),
+            }
+        }
+    }
+
+    impl Drop for Fake {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    impl Backend for Fake {
+        fn library(&self) -> Result<Library> {
+            Ok(Library::with_state(
+                self.dir.join("library"),
+                self.state.borrow().clone(),
+            ))
+        }
+
+        fn save(&self, library: &Library) -> Result<()> {
+            *self.state.borrow_mut() = library.state.clone();
+            Ok(())
+        }
+
+        fn save_index(&self, _library: &Library) -> Result<()> {
+            Ok(())
+        }
+
+        fn history(&self) -> Result<History> {
+            Ok(self.history.borrow().clone())
+        }
+
+        fn config(&self) -> Result<Option<Config>> {
+            Ok(None)
+        }
+
+        fn playlist(&self, name: &str) -> Result<Playlist> {
+            anyhow::bail!("Playlist '{}' not found", name)
+        }
+
+        fn thumbnails_dir(&self) -> Result<PathBuf> {
+            let dir = self.dir.join("thumbnails");
+            fs::create_dir_all(&dir)?;
+            Ok(dir)
+        }
+
+        fn apply(
+            &self,
+            library: &mut Library,
+            wallpaper: &Wallpaper,
+            _effects: Option<Effects>,
+        ) -> Result<()> {
+            let hash = library.hash(wallpaper)?;
+            self.history.borrow_mut().record(&hash, &wallpaper.name);
+            Ok(())
+        }
+    }
+
+    const LIBRARY: &[&str] = &["City/night.png", "Nature/lake.png", "Nature/sunset.png"];
+
+    fn api() -> Api {
+        api_for(&[])
+    }
+
+    fn api_for(names: &[&str]) -> Api {
+        Api {
+            token: TOKEN.to_string(),
+            backend: Box::new(Fake::new(names)),
+        }
+    }
+
+    /// Handle a request and return its status and JSON body
+    fn call(api: &Api, method: &str, path: &str, params: &[(&str, &str)]) -> (u16, Value) {
+        let response = api.handle(&request(method, path, params));
+        (response.status, body(&response))
+    }
+
+    fn names(body: &Value) -> Vec<&str> {
+        body["wallpapers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| w["name"].as_str().unwrap())
+            .collect()
+    }
+
+    fn request(method: &str, path: &str, params: &[(&str, &str)]) -> ApiRequest {
+        ApiRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            params: params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            bearer: Some(TOKEN.to_string()),
+        }
+    }
+
+    fn body(response: &ApiResponse) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    fn wallpapers(names: &[&str]) -> Vec<Wallpaper> {
+        names
+            .iter()
+            .map(|name| Wallpaper {
+                path: PathBuf::from("/wallpapers").join(name),
+                name: name.to_string(),
+                category: name.split_once('/').map(|(c, _)| c.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn requests_without_the_token_are_refused() {
+        let mut missing = request("GET", "/api", &[]);
+        missing.bearer = None;
+        assert_eq!(api().handle(&missing).status, 401);
+
+        let mut wrong = request("GET", "/api", &[]);
+        wrong.bearer = Some("s3cres".to_string());
+        assert_eq!(api().handle(&wrong).status, 401);
+
+        let mut longer = request("GET", "/api", &[]);
+        longer.bearer = Some(format!("{}x", TOKEN));
+        assert_eq!(api().handle(&longer).status, 401);
+    }
+
+    #[test]
+    fn tokens_are_trimmed_and_must_not_be_empty() {
+        assert_eq!(
+            find_token(Some(" abc\n".to_string()), None).unwrap(),
+            Some("abc".to_string())
+        );
+        assert!(find_token(Some("  ".to_string()), None).is_err());
+    }
+
+    #[test]
+    fn the_token_is_accepted_as_header_or_parameter() {
+        assert_eq!(api().handle(&request("GET", "/api", &[])).status, 200);
+
+        let mut by_param = request("GET", "/api", &[("token", TOKEN)]);
+        by_param.bearer = None;
+        assert_eq!(api().handle(&by_param).status, 200);
+    }
+
+    #[test]
+    fn preflight_requests_need_no_token() {
+        let mut preflight = request("OPTIONS", "/api/set", &[]);
+        preflight.bearer = None;
+        assert_eq!(api().handle(&preflight).status, 204);
+    }
+
+    #[test]
+    fn index_lists_the_endpoints() {
+        let response = api().route(&request("GET", "/api", &[])).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            body(&response)["endpoints"].as_array().unwrap().len(),
+            ENDPOINTS.len()
+        );
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let response = api().route(&request("GET", "/api/nothing", &[])).unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(body(&response)["error"], "No endpoint /api/nothing");
+    }
+
+    #[test]
+    fn wrong_methods_are_not_allowed() {
+        for (method, path) in [
+            ("GET", "/api/set"),
+            ("POST", "/api/current"),
+            ("PUT", "/api/favorite"),
+        ] {
+            let response = api().route(&request(method, path, &[])).unwrap();
+            assert_eq!(response.status, 405, "{} {}", method, path);
+            assert_eq!(
+                body(&response)["error"],
+                format!("{} is not allowed on {}", method, path)
+            );
+        }
+    }
+
+    #[test]
+    fn names_are_required_where_expected() {
+        for (method, path) in [("POST", "/api/set"), ("GET", "/api/thumbnail")] {
+            let response = api()
+                .route(&request(method, path, &[("name", "")]))
+                .unwrap();
+            assert_eq!(response.status, 400, "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn names_are_matched_like_set() {
+        let wallpapers = wallpapers(&["Nature/sunset.jpg", "City/night.png"]);
+        assert_eq!(
+            find(&wallpapers, "sunset").unwrap().name,
+            "Nature/sunset.jpg"
+        );
+        assert_eq!(
+            find(&wallpapers, "city/NIGHT.png").unwrap().name,
+            "City/night.png"
+        );
+    }
+
+    #[test]
+    fn ambiguous_names_list_the_candidates() {
+        let wallpapers = wallpapers(&["Nature/sunset.jpg", "Ocean/sunset.jpg", "City/night.png"]);
+        let response = find(&wallpapers, "sunset").unwrap_err();
+
+        assert_eq!(response.status, 409);
+        assert_eq!(
+            body(&response)["candidates"],
+            json!(["Nature/sunset.jpg", "Ocean/sunset.jpg"])
+        );
+    }
+
+    #[test]
+    fn unknown_names_come_with_suggestions() {
+        let wallpapers = wallpapers(&["Nature/sunset.jpg", "City/night.png"]);
+        let response = find(&wallpapers, "sunsat").unwrap_err();
+
+        assert_eq!(response.status, 404);
+        assert_eq!(body(&response)["error"], "Wallpaper 'sunsat' not found");
+        assert_eq!(body(&response)["suggestions"], json!(["Nature/sunset.jpg"]));
+    }
+
+    #[test]
+    fn query_strings_are_decoded() {
+        assert_eq!(percent_decode("tag%3Adark+mode"), "tag:dark mode");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn wallpapers_are_listed_and_filtered() {
+        let api = api_for(LIBRARY);
+
+        let (status, all) = call(&api, "GET", "/api/wallpapers", &[]);
+        assert_eq!(status, 200);
+        assert_eq!(all["total"], 3);
+        assert_eq!(names(&all), LIBRARY);
+        assert_eq!(all["wallpapers"][1]["category"], "Nature");
+        assert_eq!(all["wallpapers"][1]["favorite"], false);
+
+        let (_, nature) = call(
+            &api,
+            "GET",
+            "/api/wallpapers",
+            &[("query", "category:nature")],
+        );
+        assert_eq!(names(&nature), ["Nature/lake.png", "Nature/sunset.png"]);
+
+        let (status, _) = call(&api, "GET", "/api/wallpapers", &[("query", "(name:x")]);
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn favorites_are_kept_and_listed() {
+        let api = api_for(LIBRARY);
+
+        let (status, lake) = call(&api, "POST", "/api/favorite", &[("name", "lake")]);
+        assert_eq!(status, 200);
+        assert_eq!(lake["name"], "Nature/lake.png");
+        assert_eq!(lake["favorite"], true);
+
+        let (_, favorites) = call(&api, "GET", "/api/wallpapers", &[("favorites", "")]);
+        assert_eq!(names(&favorites), ["Nature/lake.png"]);
+
+        let (_, lake) = call(&api, "DELETE", "/api/favorite", &[("name", "lake")]);
+        assert_eq!(lake["favorite"], false);
+        let (_, favorites) = call(&api, "GET", "/api/wallpapers", &[("favorites", "1")]);
+        assert_eq!(favorites["total"], 0);
+    }
+
+    #[test]
+    fn favorite_without_a_name_marks_the_current_wallpaper() {
+        let api = api_for(LIBRARY);
+
+        let (status, _) = call(&api, "POST", "/api/favorite", &[]);
+        assert_eq!(status, 404);
+
+        call(&api, "POST", "/api/set", &[("name", "night")]);
+        let (status, night) = call(&api, "POST", "/api/favorite", &[]);
+        assert_eq!(status, 200);
+        assert_eq!(night["name"], "City/night.png");
+        assert_eq!(night["favorite"], true);
+    }
+
+    #[test]
+    fn set_wallpapers_become_current() {
+        let api = api_for(LIBRARY);
+
+        let (status, current) = call(&api, "GET", "/api/current", &[]);
+        assert_eq!(status, 200);
+        assert_eq!(current["current"], Value::Null);
+
+        let (status, set) = call(&api, "POST", "/api/set", &[("name", "sunset")]);
+        assert_eq!(status, 200);
+        assert_eq!(set["name"], "Nature/sunset.png");
+
+        let (_, current) = call(&api, "GET", "/api/current", &[]);
+        assert_eq!(current["current"]["name"], "Nature/sunset.png");
+        assert_eq!(current["current"]["width"], 4);
+        assert_eq!(current["current"]["height"], 3);
+        assert!(current["current"]["shown_at"].is_string());
+
+        let (status, _) = call(&api, "POST", "/api/set", &[]);
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn next_and_prev_step_through_the_library_and_wrap_around() {
+        let api = api_for(LIBRARY);
+        let step = |path| call(&api, "POST", path, &[]).1["name"].clone();
+
+        assert_eq!(step("/api/next"), "City/night.png");
+        assert_eq!(step("/api/next"), "Nature/lake.png");
+        assert_eq!(step("/api/prev"), "City/night.png");
+        assert_eq!(step("/api/prev"), "Nature/sunset.png");
+        assert_eq!(step("/api/next"), "City/night.png");
+
+        let (status, _) = call(&api_for(&[]), "POST", "/api/next", &[]);
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn random_picks_from_what_matches() {
+        let api = api_for(LIBRARY);
+
+        let (status, nature) = call(
+            &api,
+            "POST",
+            "/api/random",
+            &[("query", "category:nature"), ("seed", "7")],
+        );
+        assert_eq!(status, 200);
+        assert!(nature["name"].as_str().unwrap().starts_with("Nature/"));
+        let (_, current) = call(&api, "GET", "/api/current", &[]);
+        assert_eq!(current["current"]["name"], nature["name"]);
+
+        let (status, _) = call(&api, "POST", "/api/random", &[("query", "category:space")]);
+        assert_eq!(status, 404);
+        let (status, _) = call(&api, "POST", "/api/random", &[("seed", "soon")]);
+        assert_eq!(status, 400);
+        let (status, error) = call(&api, "POST", "/api/random", &[("playlist", "gone")]);
+        assert_eq!(status, 500);
+        assert_eq!(error["error"], "Playlist 'gone' not found");
+    }
+
+    #[test]
+    fn thumbnails_are_jpegs() {
+        let api = api_for(LIBRARY);
+
+        let response = api.handle(&request("GET", "/api/thumbnail", &[("name", "lake")]));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type, "image/jpeg");
+        assert_eq!(&response.body[..2], [0xFF, 0xD8]);
+
+        let (status, _) = call(&api, "GET", "/api/thumbnail", &[]);
+        assert_eq!(status, 400);
+        let (status, _) = call(&api, "GET", "/api/thumbnail", &[("name", "zzzzzz")]);
+        assert_eq!(status, 404);
+    }
+
+    /// Send a raw HTTP request and return the status line, headers and body
+    fn http(port: u16, head: &str) -> (String, String, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "{}\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            head
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (headers, body) = response.split_once("\r\n\r\n").unwrap();
+        let (status, headers) = headers.split_once("\r\n").unwrap();
+        (status.to_string(), headers.to_lowercase(), body.to_string())
+    }
+
+    #[test]
+    fn answers_local_http_requests() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let serving = thread::spawn(move || {
+            let api = api_for(LIBRARY);
+            for request in server.incoming_requests().take(3) {
+                answer(&api, request);
+            }
+        });
+
+        let (status, headers, _) = http(port, "GET /api/wallpapers HTTP/1.1");
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        assert!(headers.contains("access-control-allow-origin: *"));
+
+        let (status, headers, body) = http(
+            port,
+            &format!("GET /api/wallpapers HTTP/1.1\r\nAuthorization: Bearer {TOKEN}"),
+        );
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(headers.contains("content-type: application/json"));
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(names(&body), LIBRARY);
+
+        let (status, _, body) = http(
+            port,
+            &format!("POST /api/set?token={TOKEN}&name=Nature%2Flake.png HTTP/1.1"),
+        );
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["name"], "Nature/lake.png");
+
+        serving.join().unwrap();
+    }
+}
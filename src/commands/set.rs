@@ -1,96 +1,227 @@
 use anyhow::{Context, Result};
-use rand::seq::SliceRandom;
-use std::fs;
-use std::path::Path;
-use walkdir::WalkDir;
 
-use crate::config;
+use crate::config::{self, Config};
+use crate::display::{self, Monitor, Target};
+use crate::effects::Effects;
+use crate::history;
+use crate::library::{Library, Wallpaper};
+use crate::matching::Resolved;
+use crate::picker;
+use crate::playlist;
+use crate::preview;
+use crate::render::Color;
+use crate::selection::{PickOptions, Selector};
+use crate::setter::{self, Appearance, Mode};
+
+/// What to do with the wallpaper once it has been chosen
+#[derive(Debug, Default, Clone)]
+pub struct SetOptions {
+    /// Only show which wallpaper would be set
+    pub dry_run: bool,
+    /// Show a thumbnail of the wallpaper
+    pub preview: bool,
+    /// How to scale the wallpaper, instead of the configured mode
+    pub mode: Option<Mode>,
+    /// Colour around the wallpaper, instead of the configured one
+    pub letterbox: Option<Color>,
+    /// Effects to apply, instead of the playlist's
+    pub effects: Option<Effects>,
+    /// Which monitors to set
+    pub target: Target,
+}
 
 /// Set a specific wallpaper by name
-pub fn execute(name: Option<String>, random: bool) -> Result<()> {
+pub fn execute(
+    name: Option<String>,
+    first: bool,
+    random: bool,
+    pick: PickOptions,
+    options: SetOptions,
+) -> Result<()> {
     if random {
-        set_random_wallpaper()
+        set_random_wallpaper(pick, options)
     } else if let Some(name) = name {
-        set_wallpaper(&name)
+        set_wallpaper(&name, first, options)
     } else {
-        anyhow::bail!("Please provide a wallpaper name with --name or use --random flag");
+        pick_wallpaper(options)
     }
 }
 
-/// Set a specific wallpaper by name
-fn set_wallpaper(name: &str) -> Result<()> {
-    let wallpaper_dir = config::get_wallpaper_dir()?;
-    let wallpaper_path = wallpaper_dir.join(name);
-
-    if !wallpaper_path.exists() {
-        anyhow::bail!(
-            "Wallpaper '{}' not found in {}",
-            name,
-            wallpaper_dir.display()
-        );
+/// Let the user choose a wallpaper from the list
+fn pick_wallpaper(options: SetOptions) -> Result<()> {
+    let mut library = Library::open()?;
+
+    if library.wallpapers.is_empty() {
+        println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
+        return Ok(());
     }
 
-    set_wallpaper_internal(&wallpaper_path)?;
+    let Some(index) = picker::pick(&library.wallpapers)? else {
+        println!("No wallpaper selected");
+        return Ok(());
+    };
+    let wallpaper = library.wallpapers[index].clone();
 
-    println!("Wallpaper set to: {}", name);
+    let message = format!("Wallpaper set to: {}", wallpaper.name);
+    apply(&mut library, &wallpaper, &options, &message)
+}
 
-    Ok(())
+/// Set a specific wallpaper by name
+///
+/// The name can be the full relative path, the file name, or any part of it.
+/// With `first`, the best match wins when several wallpapers match.
+fn set_wallpaper(name: &str, first: bool, options: SetOptions) -> Result<()> {
+    let mut library = Library::open()?;
+
+    let wallpaper = match library.resolve(name) {
+        Resolved::Found(wallpaper) => wallpaper.clone(),
+        Resolved::Ambiguous(candidates) if first => candidates[0].clone(),
+        Resolved::Ambiguous(candidates) => {
+            let list: Vec<String> = candidates
+                .iter()
+                .map(|c| format!("  - {}", c.name))
+                .collect();
+            anyhow::bail!(
+                "'{}' matches several wallpapers:\n{}\nPlease use a more specific name, or --first to pick the first match",
+                name,
+                list.join("\n")
+            );
+        }
+        Resolved::NotFound(suggestions) => {
+            let mut message = format!(
+                "Wallpaper '{}' not found in {}",
+                name,
+                library.dir.display()
+            );
+            if !suggestions.is_empty() {
+                message.push_str("\nDid you mean:");
+                for suggestion in suggestions {
+                    message.push_str(&format!("\n  - {}", suggestion.name));
+                }
+            }
+            anyhow::bail!(message);
+        }
+    };
+
+    let message = format!("Wallpaper set to: {}", wallpaper.name);
+    apply(&mut library, &wallpaper, &options, &message)
 }
 
 /// Set a random wallpaper from the collection
-fn set_random_wallpaper() -> Result<()> {
-    let wallpaper_dir = config::get_wallpaper_dir()?;
+fn set_random_wallpaper(pick: PickOptions, options: SetOptions) -> Result<()> {
+    let mut library = Library::open()?;
 
-    if !wallpaper_dir.exists() {
+    if !library.dir.exists() {
         println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
         return Ok(());
     }
 
     println!("Selecting random wallpaper...");
 
-    let image_extensions = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
-    let mut wallpapers = Vec::new();
+    let playlist = pick
+        .playlist
+        .as_deref()
+        .map(playlist::load_playlist)
+        .transpose()?;
+    let config = config::load_config()?;
+    let wallpapers = pick.candidates(&mut library, playlist.as_ref(), config.as_ref())?;
+    let options = match &playlist {
+        Some(playlist) if options.effects.is_none() => SetOptions {
+            effects: playlist.parsed_effects()?,
+            ..options
+        },
+        _ => options,
+    };
 
-    for entry in WalkDir::new(&wallpaper_dir)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if image_extensions.contains(&ext.to_str().unwrap_or("").to_lowercase().as_str()) {
-                    wallpapers.push(path.to_path_buf());
-                }
-            }
+    if wallpapers.is_empty() {
+        if pick.is_filtered() {
+            println!("No wallpapers match the given filters.");
+        } else {
+            println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
         }
+        return Ok(());
     }
 
-    if wallpapers.is_empty() {
-        println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
-        return Ok(());
+    let mut selector = Selector::from_config(config.as_ref(), pick.seed, pick.weighted);
+    let history = history::load_history()?;
+
+    if options.target == Target::Each {
+        let monitors = display::layout(config.as_ref());
+        let chosen = selector.pick_many(&wallpapers, monitors.len(), &mut library, &history)?;
+        return spread(&mut library, &chosen, &monitors, &options);
     }
 
-    let mut rng = rand::thread_rng();
-    let chosen = wallpapers
-        .choose(&mut rng)
+    let chosen = selector
+        .pick(&wallpapers, &mut library, &history)?
         .context("Failed to choose random wallpaper")?;
 
-    set_wallpaper_internal(chosen)?;
+    let filename = chosen
+        .path
+        .file_name()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let message = format!("Random wallpaper set to: {}", filename);
+    apply(&mut library, chosen, &options, &message)
+}
 
-    if let Some(filename) = chosen.file_name() {
-        println!("Random wallpaper set to: {}", filename.to_string_lossy());
+/// Set the chosen wallpaper and print `message`, or only report it on a dry run
+fn apply(
+    library: &mut Library,
+    wallpaper: &Wallpaper,
+    options: &SetOptions,
+    message: &str,
+) -> Result<()> {
+    if options.dry_run {
+        println!("Would set wallpaper to: {}", wallpaper.name);
+    } else {
+        let config = config::load_config()?;
+        let appearance = appearance(options, config.as_ref());
+        if let Target::One(monitor) = &options.target {
+            let monitors = display::layout(config.as_ref());
+            let index = monitor.resolve(&monitors)?;
+            setter::apply_to(library, &[(index, wallpaper.clone())], appearance)?;
+            println!("{} (on {})", message, monitors[index].name);
+        } else {
+            setter::apply(library, wallpaper, appearance)?;
+            println!("{}", message);
+        }
     }
 
+    if options.preview {
+        preview::print_thumbnail(&wallpaper.path);
+    }
     Ok(())
 }
 
-fn set_wallpaper_internal(path: &Path) -> Result<()> {
-    let absolute_path = fs::canonicalize(path).context("Failed to get absolute path")?;
-
-    wallpaper::set_from_path(absolute_path.to_str().unwrap())
-        .map_err(|e| anyhow::anyhow!("Failed to set wallpaper: {}", e))?;
+/// Set a different wallpaper on each monitor, or only report them on a dry run
+fn spread(
+    library: &mut Library,
+    chosen: &[Wallpaper],
+    monitors: &[Monitor],
+    options: &SetOptions,
+) -> Result<()> {
+    if options.dry_run {
+        for (monitor, wallpaper) in monitors.iter().zip(chosen) {
+            println!("Would set {} to: {}", monitor.name, wallpaper.name);
+        }
+    } else {
+        let assignments: Vec<(usize, Wallpaper)> = chosen.iter().cloned().enumerate().collect();
+        let appearance = appearance(options, config::load_config()?.as_ref());
+        setter::apply_to(library, &assignments, appearance)?;
+        for (monitor, wallpaper) in monitors.iter().zip(chosen) {
+            println!("{} set to: {}", monitor.name, wallpaper.name);
+        }
+    }
 
+    if options.preview {
+        for wallpaper in chosen {
+            preview::print_thumbnail(&wallpaper.path);
+        }
+    }
     Ok(())
 }
+
+/// How to show the wallpaper, from the options and the config
+fn appearance(options: &SetOptions, config: Option<&Config>) -> Appearance {
+    Appearance::resolve(options.mode, options.letterbox, options.effects, config)
+}
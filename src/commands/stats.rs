@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::config;
+use crate::history;
+use crate::library::{self, Library};
+use crate::quota;
+
+/// Wallpapers counted together, and the space they take
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    count: usize,
+    size: u64,
+}
+
+impl Tally {
+    fn add(&mut self, size: u64) {
+        self.count += 1;
+        self.size += size;
+    }
+}
+
+/// Show how much space the library takes, compared to `max_library_size`
+pub fn execute() -> Result<()> {
+    let config = config::load_config()?.unwrap_or_default();
+    let mut library = Library::open()?;
+
+    if !library.dir.exists() {
+        println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
+        return Ok(());
+    }
+
+    let history = history::load_history()?;
+    let last_shown = history.last_shown();
+
+    let mut total = Tally::default();
+    let mut favorites = Tally::default();
+    let mut banned = Tally::default();
+    let mut never_shown = Tally::default();
+    let mut categories: BTreeMap<String, Tally> = BTreeMap::new();
+    for wallpaper in library.wallpapers.clone() {
+        let size = fs::metadata(&wallpaper.path)
+            .with_context(|| format!("Failed to read {}", wallpaper.path.display()))?
+            .len();
+        let hash = library.hash(&wallpaper)?;
+        let entry = library.state.get(&hash);
+
+        total.add(size);
+        if entry.is_some_and(|e| e.favorite) {
+            favorites.add(size);
+        }
+        if entry.is_some_and(|e| e.banned) {
+            banned.add(size);
+        }
+        if !last_shown.contains_key(hash.as_str()) {
+            never_shown.add(size);
+        }
+        categories
+            .entry(wallpaper.category.unwrap_or_else(|| "(none)".to_string()))
+            .or_default()
+            .add(size);
+    }
+    library.save_index()?;
+
+    println!("Library: {}", library.dir.display());
+    println!(
+        "Wallpapers: {} ({})",
+        total.count,
+        library::format_size(total.size)
+    );
+    match config.max_library_size {
+        Some(limit) if total.size > limit.0 => println!(
+            "Limit: {} ({}% used, {} over; the next fetch evicts wallpapers)",
+            limit,
+            percent(total.size, limit.0),
+            library::format_size(total.size - limit.0)
+        ),
+        Some(limit) => println!(
+            "Limit: {} ({}% used, {} free)",
+            limit,
+            percent(total.size, limit.0),
+            library::format_size(limit.0 - total.size)
+        ),
+        None => println!("Limit: none (set max_library_size to add one)"),
+    }
+    println!(
+        "Favorites: {} ({}), never evicted",
+        favorites.count,
+        library::format_size(favorites.size)
+    );
+    println!(
+        "Banned: {} ({}), evicted first",
+        banned.count,
+        library::format_size(banned.size)
+    );
+    println!(
+        "Never shown: {} ({})",
+        never_shown.count,
+        library::format_size(never_shown.size)
+    );
+    let evicted = quota::load_evicted()?;
+    if config.max_library_size.is_some() && !evicted.names.is_empty() {
+        println!(
+            "Evicted: {}, fetched again when there is room",
+            evicted.names.len()
+        );
+    }
+
+    if categories.is_empty() {
+        return Ok(());
+    }
+
+    let width = categories.keys().map(|c| c.len()).max().unwrap_or(0);
+    println!();
+    for (category, tally) in &categories {
+        println!(
+            "  {:<width$}  {:>5}  {:>9}",
+            category,
+            tally.count,
+            library::format_size(tally.size),
+            width = width
+        );
+    }
+
+    Ok(())
+}
+
+/// `part` as a whole percentage of `whole`
+fn percent(part: u64, whole: u64) -> u64 {
+    if whole == 0 {
+        return 100;
+    }
+    (part as u128 * 100 / whole as u128) as u64
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::library::Library;
+use crate::state;
+
+/// Add tags to a wallpaper
+pub fn add(name: &str, tags: &[String]) -> Result<()> {
+    let tags = normalize(tags)?;
+
+    let mut library = Library::open()?;
+    library.update(name, |entry| entry.tags.extend(tags.iter().cloned()))?;
+    library.save()?;
+
+    println!("Tagged {} with {}", name, tags.join(", "));
+    Ok(())
+}
+
+/// Remove tags from a wallpaper
+pub fn remove(name: &str, tags: &[String]) -> Result<()> {
+    let tags = normalize(tags)?;
+
+    let mut library = Library::open()?;
+    library.update(name, |entry| entry.tags.retain(|t| !tags.contains(t)))?;
+    library.save()?;
+
+    println!("Removed {} from {}", tags.join(", "), name);
+    Ok(())
+}
+
+/// List the tags of one wallpaper, or every tag in use with its count
+pub fn list(name: Option<&str>) -> Result<()> {
+    let mut library = Library::open()?;
+
+    if let Some(name) = name {
+        let wallpaper = library.find(name).cloned().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Wallpaper '{}' not found in {}",
+                name,
+                library.dir.display()
+            )
+        })?;
+
+        let tags: Vec<String> = library
+            .state_of(&wallpaper)?
+            .map(|entry| entry.tags.iter().cloned().collect())
+            .unwrap_or_default();
+        library.save_index()?;
+
+        if tags.is_empty() {
+            println!("{} has no tags", name);
+        } else {
+            println!("{}: {}", name, tags.join(", "));
+        }
+        return Ok(());
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in library.state.wallpapers.values() {
+        for tag in &entry.tags {
+            *counts.entry(tag.as_str()).or_default() += 1;
+        }
+    }
+
+    if counts.is_empty() {
+        println!("No tags yet. Use 'tag add <name> <tags>...' to add some.");
+        return Ok(());
+    }
+
+    for (tag, count) in counts {
+        println!("{} ({})", tag, count);
+    }
+
+    Ok(())
+}
+
+/// Tags are stored lowercase so queries and listings stay consistent
+fn normalize(tags: &[String]) -> Result<Vec<String>> {
+    tags.iter().map(|t| state::normalize_tag(t)).collect()
+}
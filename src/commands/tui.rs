@@ -0,0 +1,746 @@
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, ResetColor, SetAttribute};
+use crossterm::{cursor, queue};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+
+use crate::config;
+use crate::history::{self, History};
+use crate::library::{self, Library, Wallpaper};
+use crate::playlist::{self, Playlist, PlaylistEntry};
+use crate::preview::{self, Preview, Protocol};
+use crate::setter::{self, Appearance};
+use crate::state::{self, WallpaperState};
+
+/// How many rows PageUp/PageDown move
+const PAGE_SIZE: usize = 10;
+
+const HELP: &str = "Enter set · f favorite · b ban · 1-5 rate · 0 unrate · t tag · p playlist · Tab switch · q quit";
+
+/// Browse the library in a full-screen terminal UI
+pub fn execute() -> Result<()> {
+    let mut library = Library::open()?;
+
+    if library.wallpapers.is_empty() {
+        println!("No wallpapers found. Use 'fetch' command to download wallpapers first.");
+        return Ok(());
+    }
+    if !io::stdout().is_terminal() || !io::stdin().is_terminal() {
+        anyhow::bail!("The terminal UI needs an interactive terminal");
+    }
+
+    let hashes = library
+        .wallpapers
+        .clone()
+        .iter()
+        .map(|w| library.hash(w))
+        .collect::<Result<Vec<_>>>()?;
+    library.save_index()?;
+
+    let mut app = App::new(library, hashes, history::load_history()?);
+    let mut terminal = ratatui::try_init().context("Failed to set up the terminal")?;
+    let result = app.run(&mut terminal);
+
+    let _ = io::stdout().write_all(app.protocol.clear_sequence().as_bytes());
+    ratatui::restore();
+    result
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Category {
+    All,
+    Named(String),
+    Uncategorized,
+}
+
+impl Category {
+    fn contains(&self, wallpaper: &Wallpaper) -> bool {
+        match self {
+            Self::All => true,
+            Self::Named(name) => wallpaper.category.as_ref() == Some(name),
+            Self::Uncategorized => wallpaper.category.is_none(),
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            Self::All => "All",
+            Self::Named(name) => name,
+            Self::Uncategorized => "Uncategorized",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pane {
+    Categories,
+    Wallpapers,
+}
+
+/// Text being typed at the bottom of the screen
+struct Input {
+    kind: InputKind,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputKind {
+    Tags,
+    Playlist,
+}
+
+/// Preview of one wallpaper, rendered for one area of the screen
+struct CachedPreview {
+    index: usize,
+    area: Rect,
+    preview: Result<Preview, String>,
+}
+
+struct App {
+    library: Library,
+    /// Content hash of each wallpaper, in library order
+    hashes: Vec<String>,
+    history: History,
+    categories: Vec<Category>,
+    category_state: ListState,
+    /// Indices of the wallpapers in the selected category
+    visible: Vec<usize>,
+    wallpaper_state: ListState,
+    focus: Pane,
+    input: Option<Input>,
+    /// Result of the last action, and whether it failed
+    status: Option<(String, bool)>,
+    last_playlist: String,
+    protocol: Protocol,
+    preview: Option<CachedPreview>,
+    /// Wallpaper and area of the graphic currently on screen
+    shown: Option<(usize, Rect)>,
+    quit: bool,
+}
+
+impl App {
+    fn new(library: Library, hashes: Vec<String>, history: History) -> Self {
+        let mut categories = vec![Category::All];
+        for wallpaper in &library.wallpapers {
+            let category = match &wallpaper.category {
+                Some(name) => Category::Named(name.clone()),
+                None => Category::Uncategorized,
+            };
+            if !categories.contains(&category) {
+                categories.push(category);
+            }
+        }
+        categories[1..].sort_by(|a, b| match (a, b) {
+            (Category::Named(a), Category::Named(b)) => a.cmp(b),
+            (Category::Uncategorized, _) => std::cmp::Ordering::Greater,
+            (_, Category::Uncategorized) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Equal,
+        });
+
+        let mut app = Self {
+            library,
+            hashes,
+            history,
+            categories,
+            category_state: ListState::default().with_selected(Some(0)),
+            visible: Vec::new(),
+            wallpaper_state: ListState::default(),
+            focus: Pane::Wallpapers,
+            input: None,
+            status: None,
+            last_playlist: String::new(),
+            protocol: Protocol::detect(),
+            preview: None,
+            shown: None,
+            quit: false,
+        };
+        app.refresh_visible();
+        app
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.quit {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .context("Failed to draw the terminal UI")?;
+            self.show_graphic()?;
+
+            if let Event::Key(key) = event::read().context("Failed to read terminal input")? {
+                self.handle(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Index of the wallpaper under the cursor
+    fn current(&self) -> Option<usize> {
+        self.wallpaper_state
+            .selected()
+            .and_then(|i| self.visible.get(i).copied())
+    }
+
+    fn state(&self, index: usize) -> Option<&WallpaperState> {
+        self.library.state.get(&self.hashes[index])
+    }
+
+    fn refresh_visible(&mut self) {
+        let category = &self.categories[self.category_state.selected().unwrap_or(0)];
+        self.visible = (0..self.library.wallpapers.len())
+            .filter(|&i| category.contains(&self.library.wallpapers[i]))
+            .collect();
+        self.wallpaper_state.select(if self.visible.is_empty() {
+            None
+        } else {
+            Some(0)
+        });
+    }
+
+    fn handle(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        if self.input.is_some() {
+            self.handle_input(key);
+            return;
+        }
+
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Left | KeyCode::Right => {
+                self.focus = match self.focus {
+                    Pane::Categories => Pane::Wallpapers,
+                    Pane::Wallpapers => Pane::Categories,
+                };
+            }
+            KeyCode::Char('h') => self.focus = Pane::Categories,
+            KeyCode::Char('l') => self.focus = Pane::Wallpapers,
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-(PAGE_SIZE as isize)),
+            KeyCode::PageDown => self.move_by(PAGE_SIZE as isize),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX),
+            KeyCode::Enter if self.focus == Pane::Categories => self.focus = Pane::Wallpapers,
+            KeyCode::Enter | KeyCode::Char('s') => self.act(Self::set),
+            KeyCode::Char('f') => self.act(Self::toggle_favorite),
+            KeyCode::Char('b') => self.act(Self::toggle_ban),
+            KeyCode::Char(c @ '0'..='5') => {
+                let rating = c.to_digit(10).filter(|&r| r > 0).map(|r| r as u8);
+                self.act(|app, index| app.rate(index, rating));
+            }
+            KeyCode::Char('t') if self.current().is_some() => {
+                self.input = Some(Input {
+                    kind: InputKind::Tags,
+                    text: String::new(),
+                });
+            }
+            KeyCode::Char('p') if self.current().is_some() => {
+                self.input = Some(Input {
+                    kind: InputKind::Playlist,
+                    text: self.last_playlist.clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_input(&mut self, key: KeyEvent) {
+        let Some(input) = &mut self.input else {
+            return;
+        };
+
+        match key.code {
+            KeyCode::Esc => self.input = None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input = None
+            }
+            KeyCode::Backspace => {
+                input.text.pop();
+            }
+            KeyCode::Char(c) => input.text.push(c),
+            KeyCode::Enter => {
+                let Some(input) = self.input.take() else {
+                    return;
+                };
+                match input.kind {
+                    InputKind::Tags => self.act(|app, index| app.tag(index, &input.text)),
+                    InputKind::Playlist => {
+                        self.act(|app, index| app.add_to_playlist(index, input.text.trim()))
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn move_by(&mut self, by: isize) {
+        let (state, len) = match self.focus {
+            Pane::Categories => (&mut self.category_state, self.categories.len()),
+            Pane::Wallpapers => (&mut self.wallpaper_state, self.visible.len()),
+        };
+        if len == 0 {
+            return;
+        }
+
+        let selected = state.selected().unwrap_or(0) as isize;
+        let target = selected.saturating_add(by).clamp(0, len as isize - 1) as usize;
+        state.select(Some(target));
+
+        if self.focus == Pane::Categories {
+            self.refresh_visible();
+        }
+    }
+
+    /// Run an action on the wallpaper under the cursor and report the outcome
+    fn act(&mut self, action: impl FnOnce(&mut Self, usize) -> Result<String>) {
+        let Some(index) = self.current() else {
+            return;
+        };
+
+        self.status = Some(match action(self, index) {
+            Ok(message) => (message, false),
+            Err(e) => (format!("{:#}", e), true),
+        });
+    }
+
+    fn set(&mut self, index: usize) -> Result<String> {
+        let wallpaper = &self.library.wallpapers[index];
+        let appearance = Appearance::resolve(None, None, None, config::load_config()?.as_ref());
+        setter::set_wallpaper(wallpaper, &self.hashes[index], appearance)?;
+
+        self.history.record(&self.hashes[index], &wallpaper.name);
+        history::save_history(&self.history)?;
+        Ok(format!("Wallpaper set to: {}", wallpaper.name))
+    }
+
+    fn update(&mut self, index: usize, f: impl FnOnce(&mut WallpaperState)) -> Result<()> {
+        let name = &self.library.wallpapers[index].name;
+        self.library.state.update(&self.hashes[index], name, f);
+        self.library.save()
+    }
+
+    fn toggle_favorite(&mut self, index: usize) -> Result<String> {
+        let favorite = !self.state(index).is_some_and(|e| e.favorite);
+        self.update(index, |entry| entry.favorite = favorite)?;
+
+        let name = &self.library.wallpapers[index].name;
+        Ok(if favorite {
+            format!("★ Added {} to favorites", name)
+        } else {
+            format!("Removed {} from favorites", name)
+        })
+    }
+
+    fn toggle_ban(&mut self, index: usize) -> Result<String> {
+        let banned = !self.state(index).is_some_and(|e| e.banned);
+        self.update(index, |entry| entry.banned = banned)?;
+
+        let name = &self.library.wallpapers[index].name;
+        Ok(if banned {
+            format!("Banned {}", name)
+        } else {
+            format!("Unbanned {}", name)
+        })
+    }
+
+    fn rate(&mut self, index: usize, rating: Option<u8>) -> Result<String> {
+        self.update(index, |entry| entry.rating = rating)?;
+
+        let name = &self.library.wallpapers[index].name;
+        Ok(match rating {
+            Some(rating) => format!("Rated {} {}/5", name, rating),
+            None => format!("Removed the rating of {}", name),
+        })
+    }
+
+    /// Add the words in `text` as tags, or remove them when prefixed with '-'
+    fn tag(&mut self, index: usize, text: &str) -> Result<String> {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for word in text.split_whitespace() {
+            match word.strip_prefix('-') {
+                Some(tag) if !tag.is_empty() => removed.push(state::normalize_tag(tag)?),
+                Some(_) => {}
+                None => added.push(state::normalize_tag(word)?),
+            }
+        }
+        if added.is_empty() && removed.is_empty() {
+            anyhow::bail!("No tags given");
+        }
+
+        self.update(index, |entry| {
+            entry.tags.extend(added.iter().cloned());
+            entry.tags.retain(|t| !removed.contains(t));
+        })?;
+
+        let name = &self.library.wallpapers[index].name;
+        let mut message = Vec::new();
+        if !added.is_empty() {
+            message.push(format!("Tagged {} with {}", name, added.join(", ")));
+        }
+        if !removed.is_empty() {
+            message.push(format!("Removed {} from {}", removed.join(", "), name));
+        }
+        Ok(message.join("; "))
+    }
+
+    /// Append the wallpaper to a static playlist, creating the playlist if needed
+    fn add_to_playlist(&mut self, index: usize, name: &str) -> Result<String> {
+        let path = playlist::get_playlist_path(name)?;
+        let created = !path.exists();
+        let mut playlist = if created {
+            Playlist::default()
+        } else {
+            playlist::load_playlist(name)?
+        };
+        if playlist.query.is_some() {
+            anyhow::bail!(
+                "Playlist '{}' is a saved query; its wallpapers cannot be edited by hand",
+                name
+            );
+        }
+        self.last_playlist = name.to_string();
+
+        let wallpaper = &self.library.wallpapers[index];
+        let hash = &self.hashes[index];
+        if playlist.wallpapers.iter().any(|e| e.hash == *hash) {
+            return Ok(format!("{} is already in '{}'", wallpaper.name, name));
+        }
+
+        playlist.wallpapers.push(PlaylistEntry {
+            hash: hash.clone(),
+            name: wallpaper.name.clone(),
+        });
+        playlist::save_playlist(name, &playlist)?;
+
+        Ok(if created {
+            format!("✓ Created playlist '{}' with {}", name, wallpaper.name)
+        } else {
+            format!("✓ Added {} to '{}'", wallpaper.name, name)
+        })
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [categories, middle, preview] = Layout::horizontal([
+            Constraint::Percentage(20),
+            Constraint::Percentage(40),
+            Constraint::Percentage(40),
+        ])
+        .areas(main);
+        let [wallpapers, details] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(8)]).areas(middle);
+
+        self.draw_categories(frame, categories);
+        self.draw_wallpapers(frame, wallpapers);
+        self.draw_details(frame, details);
+        self.draw_preview(frame, preview);
+        self.draw_status(frame, status);
+    }
+
+    fn pane_block(&self, title: &str, pane: Option<Pane>) -> Block<'static> {
+        let style = if pane.is_some() && pane == Some(self.focus) {
+            Style::default().fg(Color::Cyan)
+        } else {
+            Style::default()
+        };
+        Block::bordered()
+            .title(format!(" {} ", title))
+            .border_style(style)
+    }
+
+    fn highlight_style(&self, pane: Pane) -> Style {
+        if pane == self.focus {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default().add_modifier(Modifier::BOLD)
+        }
+    }
+
+    fn draw_categories(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .categories
+            .iter()
+            .map(|category| {
+                let count = self
+                    .library
+                    .wallpapers
+                    .iter()
+                    .filter(|w| category.contains(w))
+                    .count();
+                ListItem::new(format!("{} ({})", category.label(), count))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(self.pane_block("Categories", Some(Pane::Categories)))
+            .highlight_style(self.highlight_style(Pane::Categories));
+        frame.render_stateful_widget(list, area, &mut self.category_state);
+    }
+
+    fn draw_wallpapers(&mut self, frame: &mut Frame, area: Rect) {
+        let category = &self.categories[self.category_state.selected().unwrap_or(0)];
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&index| {
+                let wallpaper = &self.library.wallpapers[index];
+                let name = match category {
+                    Category::Named(category) => wallpaper
+                        .name
+                        .strip_prefix(category.as_str())
+                        .map(|rest| rest.trim_start_matches(['/', '\\']))
+                        .unwrap_or(&wallpaper.name),
+                    _ => &wallpaper.name,
+                };
+
+                let mut spans = vec![Span::raw(name.to_string())];
+                let mut style = Style::default();
+                if let Some(entry) = self.state(index) {
+                    if entry.favorite {
+                        spans.push(Span::styled(" ★", Style::default().fg(Color::Yellow)));
+                    }
+                    if let Some(rating) = entry.rating {
+                        spans.push(Span::raw(format!(" ({}/5)", rating)));
+                    }
+                    if entry.banned {
+                        spans.push(Span::styled(" [banned]", Style::default().fg(Color::Red)));
+                        style = style.add_modifier(Modifier::DIM);
+                    }
+                }
+                ListItem::new(Line::from(spans)).style(style)
+            })
+            .collect();
+
+        let title = format!("{} ({})", category.label(), self.visible.len());
+        let list = List::new(items)
+            .block(self.pane_block(&title, Some(Pane::Wallpapers)))
+            .highlight_style(self.highlight_style(Pane::Wallpapers));
+        frame.render_stateful_widget(list, area, &mut self.wallpaper_state);
+    }
+
+    fn draw_details(&mut self, frame: &mut Frame, area: Rect) {
+        let block = self.pane_block("Details", None);
+        let Some(index) = self.current() else {
+            frame.render_widget(block, area);
+            return;
+        };
+
+        let wallpaper = self.library.wallpapers[index].clone();
+        let dimensions = self.library.dimensions(&wallpaper).ok().flatten();
+        let size = fs::metadata(&wallpaper.path).map(|m| m.len()).ok();
+        let entry = self.state(index).cloned().unwrap_or_default();
+        let last_shown = self
+            .history
+            .entries
+            .iter()
+            .rev()
+            .find(|e| e.hash == self.hashes[index])
+            .and_then(|e| chrono::DateTime::from_timestamp(e.shown_at, 0))
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            });
+
+        let mut size_text = Vec::new();
+        if let Some((width, height)) = dimensions {
+            size_text.push(format!("{}x{}", width, height));
+        }
+        if let Some(size) = size {
+            size_text.push(library::format_size(size));
+        }
+
+        let rating = match entry.rating {
+            Some(rating) => format!(
+                "{}{}",
+                "★".repeat(rating as usize),
+                "☆".repeat(5 - rating as usize)
+            ),
+            None => "unrated".to_string(),
+        };
+        let mut flags = Vec::new();
+        if entry.favorite {
+            flags.push("favorite");
+        }
+        if entry.banned {
+            flags.push("banned");
+        }
+
+        let field = |label: &str, value: String| {
+            Line::from(vec![
+                Span::styled(
+                    format!("{:<11}", label),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(value),
+            ])
+        };
+        let tags: Vec<&str> = entry.tags.iter().map(|t| t.as_str()).collect();
+        let lines = vec![
+            field("Name", wallpaper.name.clone()),
+            field("Size", size_text.join(", ")),
+            field("Rating", rating),
+            field(
+                "Marked",
+                if flags.is_empty() {
+                    "-".to_string()
+                } else {
+                    flags.join(", ")
+                },
+            ),
+            field(
+                "Tags",
+                if tags.is_empty() {
+                    "-".to_string()
+                } else {
+                    tags.join(", ")
+                },
+            ),
+            field(
+                "Last shown",
+                last_shown.unwrap_or_else(|| "never".to_string()),
+            ),
+        ];
+
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_preview(&mut self, frame: &mut Frame, area: Rect) {
+        let block = self.pane_block("Preview", None);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let Some(index) = self.current() else {
+            self.preview = None;
+            return;
+        };
+        if inner.width == 0 || inner.height == 0 {
+            self.preview = None;
+            return;
+        }
+
+        let cached = self
+            .preview
+            .as_ref()
+            .is_some_and(|p| p.index == index && p.area == inner);
+        if !cached {
+            let path = &self.library.wallpapers[index].path;
+            self.preview = Some(CachedPreview {
+                index,
+                area: inner,
+                preview: preview::render(path, self.protocol, inner.width, inner.height)
+                    .map_err(|e| format!("{:#}", e)),
+            });
+        }
+
+        match self.preview.as_ref().map(|p| &p.preview) {
+            Some(Ok(Preview::HalfBlocks(cells))) => {
+                let rows = cells.len() as u16;
+                let cols = cells.first().map_or(0, |row| row.len()) as u16;
+                let x0 = inner.x + inner.width.saturating_sub(cols) / 2;
+                let y0 = inner.y + inner.height.saturating_sub(rows) / 2;
+
+                let buffer = frame.buffer_mut();
+                for (y, row) in cells.iter().enumerate() {
+                    for (x, &(top, bottom)) in row.iter().enumerate() {
+                        if let Some(cell) = buffer.cell_mut((x0 + x as u16, y0 + y as u16)) {
+                            cell.set_char('▀')
+                                .set_fg(Color::Rgb(top[0], top[1], top[2]))
+                                .set_bg(Color::Rgb(bottom[0], bottom[1], bottom[2]));
+                        }
+                    }
+                }
+            }
+            Some(Err(e)) => {
+                let message = Paragraph::new(format!("No preview available\n\n{}", e))
+                    .style(Style::default().add_modifier(Modifier::DIM))
+                    .wrap(ratatui::widgets::Wrap { trim: true });
+                frame.render_widget(message, inner);
+            }
+            _ => {}
+        }
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let line = if let Some(input) = &self.input {
+            let prompt = match input.kind {
+                InputKind::Tags => "Tags (prefix with - to remove): ",
+                InputKind::Playlist => "Add to playlist: ",
+            };
+            Line::from(vec![
+                Span::styled(prompt, Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(format!("{}█", input.text)),
+            ])
+        } else if let Some((message, failed)) = &self.status {
+            let style = if *failed {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default().fg(Color::Green)
+            };
+            Line::styled(message.clone(), style)
+        } else {
+            Line::styled(HELP, Style::default().add_modifier(Modifier::DIM))
+        };
+
+        frame.render_widget(Paragraph::new(line), area);
+    }
+
+    /// Print the image for graphics protocols, which cannot go through the
+    /// cell buffer, erasing the previous one first
+    fn show_graphic(&mut self) -> Result<()> {
+        let wanted = self
+            .preview
+            .as_ref()
+            .filter(|p| matches!(p.preview, Ok(Preview::Graphic { .. })))
+            .map(|p| (p.index, p.area));
+        if wanted == self.shown {
+            return Ok(());
+        }
+
+        let mut stdout = io::stdout().lock();
+        if let Some((_, area)) = self.shown.take() {
+            queue!(
+                stdout,
+                Print(self.protocol.clear_sequence()),
+                SetAttribute(Attribute::Reset),
+                ResetColor
+            )?;
+            for y in area.top()..area.bottom() {
+                queue!(
+                    stdout,
+                    cursor::MoveTo(area.x, y),
+                    Print(" ".repeat(area.width as usize))
+                )?;
+            }
+        }
+
+        if let Some(CachedPreview {
+            area,
+            preview: Ok(Preview::Graphic { data, cols, rows }),
+            ..
+        }) = &self.preview
+        {
+            let x = area.x + area.width.saturating_sub(*cols) / 2;
+            let y = area.y + area.height.saturating_sub(*rows) / 2;
+            queue!(stdout, cursor::MoveTo(x, y), Print(data))?;
+        }
+
+        stdout.flush()?;
+        self.shown = wanted;
+        Ok(())
+    }
+}
@@ -1,13 +1,32 @@
 use anyhow::{Context, Result};
 use std::env;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use super::clean;
 use crate::config;
+use crate::journal;
+use crate::prompt::{self, Exit};
 use self_replace;
 
-pub fn execute() -> Result<()> {
+/// What `uninstall` removes; without any of `binary`, `config` and
+/// `wallpapers` the user picks from a menu
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UninstallOptions {
+    /// The wcapp binary
+    pub binary: bool,
+    /// Configuration, state, data and caches
+    pub config: bool,
+    /// Downloaded wallpapers
+    pub wallpapers: bool,
+    /// Go ahead without asking
+    pub yes: bool,
+    /// Delete for good instead of moving to the trash
+    pub permanent: bool,
+}
+
+/// Remove wcapp, moving its files to the trash unless `permanent` is set
+pub fn execute(options: UninstallOptions) -> Result<()> {
     println!("wcapp Uninstaller");
     println!();
 
@@ -15,50 +34,36 @@ pub fn execute() -> Result<()> {
     println!("Current installation: {}", current_exe.display());
     println!();
 
-    println!("What would you like to remove?");
-    println!("1. Just the wcapp binary");
-    println!("2. Binary + configuration");
-    println!("3. Binary + configuration + wallpapers");
-    println!("4. Cancel");
-    println!();
-
-    print!("Enter choice (1-4): ");
-    io::stdout().flush()?;
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let choice = input.trim();
-
-    let mut remove_binary_requested = false;
-
-    match choice {
-        "1" => {
-            remove_binary(&current_exe)?;
-            remove_binary_requested = true;
-        }
-        "2" => {
-            remove_config()?;
-            remove_binary(&current_exe)?;
-            remove_binary_requested = true;
-        }
-        "3" => {
-            remove_config()?;
-            remove_wallpapers()?;
-            remove_binary(&current_exe)?;
-            remove_binary_requested = true;
-        }
-        "4" => {
-            println!("Uninstall cancelled");
-            return Ok(());
-        }
-        _ => {
-            println!("Invalid choice");
-            return Ok(());
-        }
+    let options = if options.binary || options.config || options.wallpapers {
+        options
+    } else {
+        choose(options)?
+    };
+
+    // One entry per part asked for, false where the user kept it. Wallpapers
+    // go first, while the config still says where they are
+    let mut done = Vec::new();
+    if options.wallpapers {
+        done.push(remove_wallpapers(options.permanent, options.yes)?);
+    }
+    if options.config {
+        done.push(remove_app_dirs(options.permanent, options.yes)?);
+    }
+    let remove_binary_confirmed = options.binary && remove_binary(&current_exe, options.yes)?;
+    if options.binary {
+        done.push(remove_binary_confirmed);
     }
+    if !done.contains(&true) {
+        return Err(Exit::Cancelled.into());
+    }
+    let incomplete = done.contains(&false);
 
     println!();
-    println!("Uninstall complete!");
+    if incomplete {
+        println!("Uninstall finished, keeping what you chose to keep");
+    } else {
+        println!("Uninstall complete!");
+    }
     println!();
     #[cfg(target_os = "windows")]
     {
@@ -76,55 +81,55 @@ pub fn execute() -> Result<()> {
         println!("      Edit ~/.bashrc or ~/.profile and remove the directory from PATH");
     }
 
-    // If binary removal was requested, delete it now (this will exit the process)
-    if remove_binary_requested {
+    // If binary removal was confirmed, delete it now (this will exit the process)
+    if remove_binary_confirmed {
         println!("Removing binary...");
         self_replace::self_delete().context("Failed to remove binary")?;
         // This line will never be reached - self_delete() exits the process
     }
 
+    if incomplete {
+        return Err(Exit::Incomplete.into());
+    }
     Ok(())
 }
 
-fn remove_binary(exe_path: &PathBuf) -> Result<()> {
+/// Ask whether to remove the binary and check that it can be, returning
+/// whether to go ahead
+fn remove_binary(exe_path: &Path, yes: bool) -> Result<bool> {
     println!();
-    print!("Remove wcapp binary? This action cannot be undone. (y/N): ");
-    io::stdout().flush()?;
-
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-
-    if input.trim().to_lowercase() != "y" {
+    if !yes && !prompt::confirm("Remove wcapp binary? This action cannot be undone.")? {
         println!("Skipped binary removal");
-        return Ok(());
+        return Ok(false);
     }
 
     // Check permissions before attempting self-deletion
     #[cfg(not(target_os = "windows"))]
     {
+        use std::os::unix::fs::PermissionsExt;
+
         // Check if we can write to the binary's directory
-        if let Some(parent) = exe_path.parent() {
-            if let Ok(metadata) = fs::metadata(parent) {
-                use std::os::unix::fs::PermissionsExt;
-                let permissions = metadata.permissions();
-                let can_write = permissions.mode() & 0o200 != 0; // Check write permission
-
-                // Check if it's a system directory that typically requires sudo
-                let is_system_dir = exe_path.starts_with("/usr")
-                    || exe_path.starts_with("/bin")
-                    || exe_path.starts_with("/sbin")
-                    || exe_path.starts_with("/opt") && !exe_path.starts_with("/opt/homebrew");
-
-                if !can_write || is_system_dir {
-                    println!();
-                    println!(
-                        "✗ Insufficient permissions to remove {}",
-                        exe_path.display()
-                    );
-                    println!("Please run with sudo:");
-                    println!("  sudo {} uninstall", exe_path.display());
-                    return Ok(());
-                }
+        if let Some(parent) = exe_path.parent()
+            && let Ok(metadata) = fs::metadata(parent)
+        {
+            let permissions = metadata.permissions();
+            let can_write = permissions.mode() & 0o200 != 0; // Check write permission
+
+            // Check if it's a system directory that typically requires sudo
+            let is_system_dir = exe_path.starts_with("/usr")
+                || exe_path.starts_with("/bin")
+                || exe_path.starts_with("/sbin")
+                || exe_path.starts_with("/opt") && !exe_path.starts_with("/opt/homebrew");
+
+            if !can_write || is_system_dir {
+                println!();
+                println!(
+                    "✗ Insufficient permissions to remove {}",
+                    exe_path.display()
+                );
+                println!("Please run with sudo:");
+                println!("  sudo {} uninstall", exe_path.display());
+                return Err(Exit::Incomplete.into());
             }
         }
     }
@@ -140,54 +145,80 @@ fn remove_binary(exe_path: &PathBuf) -> Result<()> {
 
                 if is_readonly {
                     println!("✗ Cannot remove binary - directory is read-only");
-                    return Ok(());
+                    return Err(Exit::Incomplete.into());
                 }
             }
         }
     }
 
     println!("✓ Binary will be removed when uninstall completes");
-    Ok(())
+    Ok(true)
 }
 
-fn remove_config() -> Result<()> {
-    if let Ok(config_path) = config::get_config_path() {
-        if config_path.exists() {
-            fs::remove_file(&config_path).context("Failed to remove config file")?;
-            println!("✓ Configuration removed");
-
-            if let Some(parent) = config_path.parent() {
-                if parent.read_dir()?.next().is_none() {
-                    if let Err(e) = fs::remove_dir(parent) {
-                        println!("Note: Could not remove empty config directory: {}", e);
-                    }
-                }
-            }
-        } else {
-            println!("✓ No configuration found");
-        }
+/// Offer to remove each directory wcapp keeps files in, or remove them all
+/// without asking if `yes` is set, returning whether none were kept
+fn remove_app_dirs(permanent: bool, yes: bool) -> Result<bool> {
+    let dirs = config::app_dirs()?;
+    if dirs.iter().all(|dir| !dir.path.exists()) {
+        println!("✓ No configuration or data found");
     }
-    Ok(())
+    let mut kept = false;
+    for dir in &dirs {
+        // Directories nested inside this one were offered on their own
+        let nested: Vec<PathBuf> = dirs
+            .iter()
+            .filter(|other| other.path.parent() == Some(dir.path.as_path()))
+            .map(|other| other.path.clone())
+            .collect();
+        kept |= clean::offer_removal(dir, permanent, yes, &nested)?.is_none();
+    }
+    Ok(!kept)
 }
 
-fn remove_wallpapers() -> Result<()> {
+/// Offer to remove the wallpaper directory, returning whether it is gone
+fn remove_wallpapers(permanent: bool, yes: bool) -> Result<bool> {
     if let Ok(wallpaper_dir) = config::get_wallpaper_dir() {
         if wallpaper_dir.exists() {
-            print!("Remove {} wallpapers? (y/N): ", wallpaper_dir.display());
-            io::stdout().flush()?;
-
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-
-            if input.trim().to_lowercase() == "y" {
-                fs::remove_dir_all(&wallpaper_dir).context("Failed to remove wallpapers")?;
-                println!("✓ Wallpapers removed");
+            let question = format!("Remove {} wallpapers?", wallpaper_dir.display());
+            if yes || prompt::confirm(&question)? {
+                journal::remove(std::slice::from_ref(&wallpaper_dir), permanent)?;
+                if permanent {
+                    println!("✓ Wallpapers removed");
+                } else {
+                    println!("✓ Wallpapers moved to the trash");
+                }
             } else {
                 println!("✓ Wallpapers kept");
+                return Ok(false);
             }
         } else {
             println!("✓ No wallpapers found");
         }
     }
-    Ok(())
+    Ok(true)
+}
+
+/// Show the uninstall menu and turn the choice into options
+fn choose(options: UninstallOptions) -> Result<UninstallOptions> {
+    println!("What would you like to remove?");
+    println!("1. Just the wcapp binary");
+    println!("2. Binary + configuration, history and caches");
+    println!("3. Binary + configuration, history, caches + wallpapers");
+    println!("4. Cancel");
+    println!();
+
+    let choice = prompt::ask("Enter choice (1-4): ")?;
+    let (config, wallpapers) = match choice.as_str() {
+        "1" => (false, false),
+        "2" => (true, false),
+        "3" => (true, true),
+        "4" => return Err(Exit::Cancelled.into()),
+        _ => anyhow::bail!("Invalid choice '{}'", choice),
+    };
+    Ok(UninstallOptions {
+        binary: true,
+        config,
+        wallpapers,
+        ..options
+    })
 }
@@ -1,56 +1,1359 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Once, OnceLock, RwLock};
+use toml::{Table, Value};
+
+use crate::display::Monitor;
+use crate::effects::Effects;
+use crate::hooks::Hook;
+use crate::library::ByteSize;
+use crate::migrate::{self, CURRENT_VERSION};
+use crate::palette::ThemeFile;
+use crate::query::Query;
+use crate::render::Color;
+use crate::setter::Mode;
 
 /// Hardcoded wallpaper repository URL
 pub const WALLPAPER_REPO: &str = "https://github.com/Incalculas/wallpapers";
 
+/// Top-level settings of the config file, in the order they are saved
+pub const KEYS: &[&str] = &[
+    "version",
+    "wallpaper_dir",
+    "max_library_size",
+    "cycle_interval",
+    "weighted",
+    "mode",
+    "letterbox",
+    "effects",
+    "query",
+    "setter",
+    "profile",
+    "category_weights",
+    "profiles",
+    "monitors",
+    "themes",
+    "hooks",
+];
+
+/// Settings holding lists of tables, which only `config edit` can change
+pub const LISTS: &[&str] = &["monitors", "themes", "hooks"];
+
+/// Name of the per-project config file, looked up from the current directory
+const PROJECT_FILE: &str = ".wcapp.toml";
+
+/// Settings a project file may change
+///
+/// Project files come with whatever directory wcapp is run in, so they are
+/// limited to settings that cannot run commands, write files or delete
+/// wallpapers.
+pub const PROJECT_KEYS: &[&str] = &[
+    "version",
+    "mode",
+    "letterbox",
+    "effects",
+    "query",
+    "weighted",
+    "category_weights",
+];
+
+/// Prefix of the environment variables that override settings
+const ENV_PREFIX: &str = "WCAPP_";
+
+/// Profile name that stands for using no profile at all
+pub const NO_PROFILE: &str = "none";
+
+static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+static PROFILE: RwLock<Option<String>> = RwLock::new(None);
+static WARNED: Once = Once::new();
+static PROJECT_WARNED: Once = Once::new();
+
 /// Configuration structure
+///
+/// Every setting has a default, so that each config file only needs the
+/// settings it changes.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    /// Schema version, upgraded by `migrate` when an older file is read
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default = "default_wallpaper_dir")]
     pub wallpaper_dir: PathBuf,
+    /// Most disk space the wallpaper directory may take; `fetch` evicts
+    /// wallpapers to stay below it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_library_size: Option<ByteSize>,
     #[serde(default = "default_cycle_interval")]
     pub cycle_interval: u64,
+    /// Use weighted random selection for `set --random` and `cycle`
+    #[serde(default)]
+    pub weighted: bool,
+    /// How wallpapers are scaled to the screen, unless set on the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+    /// Colour around wallpapers that do not cover the screen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub letterbox: Option<Color>,
+    /// Effects applied to every wallpaper, unless set on the command line or playlist
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects: Option<Effects>,
+    /// Query every wallpaper picked by `set --random` and `cycle` must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Command that sets the wallpaper instead of the desktop's own method
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setter: Option<String>,
+    /// Name of the profile in use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Relative weight per category for weighted selection (default 1.0, 0 disables)
+    #[serde(default)]
+    pub category_weights: BTreeMap<String, f64>,
+    /// Named sets of settings, used on top of the others when active
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Table>,
+    /// Monitor layout, instead of asking the system
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub monitors: Vec<Monitor>,
+    /// Theme files written from the wallpaper's palette after every change
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub themes: Vec<ThemeFile>,
+    /// Commands run after wallpapers change
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: default_version(),
+            wallpaper_dir: default_wallpaper_dir(),
+            max_library_size: None,
+            cycle_interval: default_cycle_interval(),
+            weighted: false,
+            mode: None,
+            letterbox: None,
+            effects: None,
+            query: None,
+            setter: None,
+            profile: None,
+            category_weights: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            monitors: Vec::new(),
+            themes: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse the configured query, if any
+    pub fn parsed_query(&self) -> Result<Option<Query>> {
+        self.query
+            .as_deref()
+            .map(Query::from_str)
+            .transpose()
+            .context("Invalid query in config")
+    }
+}
+
+fn default_version() -> u32 {
+    CURRENT_VERSION
 }
 
 fn default_cycle_interval() -> u64 {
     300 // 5 minutes
 }
 
-/// Get the path to the config file based on OS
-pub fn get_config_path() -> Result<PathBuf> {
-    let config_dir = dirs::config_dir().context("Could not find config directory")?;
-    let app_config_dir = config_dir.join("wcapp");
+/// The wallpaper directory used when no config names one
+fn default_wallpaper_dir() -> PathBuf {
+    dirs::picture_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join("Pictures")))
+        .unwrap_or_else(|| PathBuf::from("Pictures"))
+        .join("wcapp")
+}
+
+/// Where the effective value of a setting comes from, from lowest to
+/// highest precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    /// Built into wcapp
+    Default,
+    /// The system-wide config file
+    System,
+    /// The user's config file, or the one passed with `--config`
+    User,
+    /// A `.wcapp.toml` in the current directory or one of its parents,
+    /// limited to `PROJECT_KEYS`
+    Project,
+    /// The active entry of `[profiles]`
+    Profile,
+    /// A `WCAPP_*` environment variable
+    Env,
+    /// `--setting` on the command line
+    Flag,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::System => write!(f, "system"),
+            Origin::User => write!(f, "user"),
+            Origin::Project => write!(f, "project"),
+            Origin::Profile => write!(f, "profile"),
+            Origin::Env => write!(f, "env"),
+            Origin::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+/// Settings from one source
+#[derive(Debug)]
+pub struct Layer {
+    pub origin: Origin,
+    /// The file, environment variable or flag the settings come from
+    pub source: String,
+    pub table: Table,
+}
+
+/// Config options given on the command line, which apply to every command
+#[derive(Debug, Default)]
+pub struct Overrides {
+    /// Config file to use instead of the user's
+    pub config: Option<PathBuf>,
+    /// `key=value` pairs that win over every other source
+    pub settings: Vec<String>,
+    /// Profile to use instead of the configured one
+    pub profile: Option<String>,
+}
+
+/// Remember the command line options for the rest of the run
+pub fn set_overrides(overrides: Overrides) {
+    switch_profile(overrides.profile.as_deref());
+    let _ = OVERRIDES.set(overrides);
+}
+
+/// Use the profile `name` from now on, whatever the config says, or go
+/// back to the configured profile with `None`
+pub fn switch_profile(name: Option<&str>) {
+    if let Ok(mut profile) = PROFILE.write() {
+        *profile = name.map(str::to_string);
+    }
+}
+
+fn overrides() -> &'static Overrides {
+    OVERRIDES.get_or_init(Overrides::default)
+}
+
+/// Get the wcapp directory inside the OS config directory, creating it if needed
+pub fn get_app_dir() -> Result<PathBuf> {
+    let app_config_dir = config_dir()?;
     fs::create_dir_all(&app_config_dir).context("Failed to create config directory")?;
-    Ok(app_config_dir.join("config.toml"))
+    Ok(app_config_dir)
+}
+
+/// Get the directory for history, favorites and what is shown, creating it if needed
+///
+/// This is `$XDG_STATE_HOME/wcapp` on Linux, and a `state` folder in the
+/// local app data directory elsewhere, since other systems have no separate
+/// place for state.
+pub fn get_state_dir() -> Result<PathBuf> {
+    let dir = state_dir()?;
+    fs::create_dir_all(&dir).context("Failed to create state directory")?;
+    Ok(dir)
+}
+
+/// Get the directory for files the user made, such as playlists, creating it if needed
+pub fn get_data_dir() -> Result<PathBuf> {
+    let dir = data_dir()?;
+    fs::create_dir_all(&dir).context("Failed to create data directory")?;
+    Ok(dir)
+}
+
+/// Get the directory for files that can be rebuilt at any time, creating it if needed
+pub fn get_cache_dir() -> Result<PathBuf> {
+    let dir = cache_dir()?;
+    fs::create_dir_all(&dir).context("Failed to create cache directory")?;
+    Ok(dir)
+}
+
+fn config_dir() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().context("Could not find config directory")?;
+    Ok(config_dir.join("wcapp"))
 }
 
-/// Load configuration from file
-pub fn load_config() -> Option<Config> {
-    let config_path = get_config_path().ok()?;
-    if !config_path.exists() {
-        return None;
+fn state_dir() -> Result<PathBuf> {
+    if let Some(state_dir) = dirs::state_dir() {
+        return Ok(state_dir.join("wcapp"));
     }
+    // Elsewhere state gets a folder of its own in the local app data
+    // directory, which is the config directory on macOS
+    let local_dir = dirs::data_local_dir().context("Could not find state directory")?;
+    Ok(local_dir.join("wcapp").join("state"))
+}
 
-    let content = fs::read_to_string(config_path).ok()?;
-    toml::from_str(&content).ok()
+fn data_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir().context("Could not find data directory")?;
+    let data_dir = data_dir.join("wcapp");
+    // macOS and Windows keep config and data in the same place
+    if data_dir == config_dir()? {
+        Ok(data_dir.join("data"))
+    } else {
+        Ok(data_dir)
+    }
 }
 
-/// Save configuration to file
-pub fn save_config(config: &Config) -> Result<()> {
+fn cache_dir() -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir().context("Could not find cache directory")?;
+    // The cache shares the local app data directory with state on Windows
+    if cfg!(target_os = "windows") {
+        Ok(cache_dir.join("wcapp").join("cache"))
+    } else {
+        Ok(cache_dir.join("wcapp"))
+    }
+}
+
+/// The file or directory `name` inside `dir`
+///
+/// Older versions kept everything in the config directory. Whatever is
+/// still there is moved over on first use, and used where it is if it
+/// cannot be moved.
+pub fn locate(dir: PathBuf, name: &str) -> Result<PathBuf> {
+    let path = dir.join(name);
+    let legacy = config_dir()?.join(name);
+    if path == legacy || path.exists() || !legacy.exists() {
+        return Ok(path);
+    }
+
+    match fs::rename(&legacy, &path) {
+        Ok(()) => Ok(path),
+        Err(e) => {
+            eprintln!(
+                "Failed to move {} to {}: {}",
+                legacy.display(),
+                path.display(),
+                e
+            );
+            Ok(legacy)
+        }
+    }
+}
+
+/// A directory wcapp keeps its own files in
+pub struct AppDir {
+    /// What kinds of files it holds, e.g. "cache"
+    pub kinds: Vec<&'static str>,
+    /// The files it holds, for prompts
+    pub contents: String,
+    pub path: PathBuf,
+}
+
+/// Every directory wcapp keeps files in, whether it exists or not
+///
+/// Platforms that use the same directory for several kinds list it once.
+pub fn app_dirs() -> Result<Vec<AppDir>> {
+    let all = [
+        ("config", "settings", config_dir()?),
+        (
+            "state",
+            "history, favorites, ratings and bans",
+            state_dir()?,
+        ),
+        ("data", "playlists", data_dir()?),
+        (
+            "cache",
+            "thumbnails, rendered wallpapers, the hash index and the repository checkout",
+            cache_dir()?,
+        ),
+    ];
+
+    let mut dirs: Vec<AppDir> = Vec::new();
+    for (kind, contents, path) in all {
+        match dirs.iter_mut().find(|dir| dir.path == path) {
+            Some(dir) => {
+                dir.kinds.push(kind);
+                dir.contents = format!("{}; {}", dir.contents, contents);
+            }
+            None => dirs.push(AppDir {
+                kinds: vec![kind],
+                contents: contents.to_string(),
+                path,
+            }),
+        }
+    }
+    Ok(dirs)
+}
+
+/// Get the path to the user's config file, the one `config set` changes
+pub fn get_config_path() -> Result<PathBuf> {
+    match &overrides().config {
+        Some(path) => Ok(path.clone()),
+        None => Ok(get_app_dir()?.join("config.toml")),
+    }
+}
+
+/// Get the path to the system-wide config file based on OS
+pub fn get_system_config_path() -> PathBuf {
+    #[cfg(target_os = "windows")]
+    {
+        let program_data = env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into());
+        PathBuf::from(program_data)
+            .join("wcapp")
+            .join("config.toml")
+    }
+    #[cfg(target_os = "macos")]
+    {
+        PathBuf::from("/Library/Application Support/wcapp/config.toml")
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        PathBuf::from("/etc/wcapp/config.toml")
+    }
+}
+
+/// The nearest project config file above the current directory, if any
+pub fn get_project_config_path() -> Option<PathBuf> {
+    let current = env::current_dir().ok()?;
+    current
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Every source of settings that is present, from lowest to highest precedence
+///
+/// Defaults come first, then the system, user and project files, then the
+/// active profile, `WCAPP_*` environment variables and finally `--setting`
+/// flags.
+pub fn layers() -> Result<Vec<Layer>> {
+    let mut layers = vec![Layer {
+        origin: Origin::Default,
+        source: "built in".to_string(),
+        table: Table::try_from(Config::default()).context("Failed to serialize defaults")?,
+    }];
+
+    let user = get_config_path()?;
+    if overrides().config.is_some() && !user.exists() {
+        anyhow::bail!("Config file {} not found", user.display());
+    }
+
+    let files = [
+        (Origin::System, Some(get_system_config_path())),
+        (Origin::User, Some(user)),
+        (Origin::Project, get_project_config_path()),
+    ];
+    for (origin, path) in files {
+        if let Some(path) = path
+            && let Some(mut table) = read_file(&path, origin)?
+        {
+            if origin == Origin::Project {
+                let refused = restrict_project(&mut table);
+                PROJECT_WARNED.call_once(|| {
+                    for key in refused {
+                        eprintln!(
+                            "Setting '{}' in {} is ignored. Project files cannot change it; set it in your own config file instead",
+                            key,
+                            path.display()
+                        );
+                    }
+                });
+            }
+            layers.push(Layer {
+                origin,
+                source: path.display().to_string(),
+                table,
+            });
+        }
+    }
+
+    let mut overriding = env_layers(|name| env::var(name).ok())?;
+    overriding.extend(flag_layers(&overrides().settings)?);
+
+    let chosen = PROFILE.read().ok().and_then(|profile| profile.clone());
+    stack(layers, overriding, chosen)
+}
+
+/// A layer for each `WCAPP_*` variable that `var` finds
+fn env_layers(var: impl Fn(&str) -> Option<String>) -> Result<Vec<Layer>> {
+    let mut layers = Vec::new();
+    for &key in KEYS {
+        if !is_scalar(key) {
+            continue;
+        }
+        let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+        if let Some(raw) = var(&name) {
+            let value =
+                typed_value(key, &raw).map_err(|e| anyhow::anyhow!("{} (from {})", e, name))?;
+            layers.push(single(Origin::Env, name, key, value));
+        }
+    }
+    Ok(layers)
+}
+
+/// A layer for each `--setting KEY=VALUE`
+fn flag_layers(settings: &[String]) -> Result<Vec<Layer>> {
+    let mut layers = Vec::new();
+    for setting in settings {
+        let (key, raw) = setting
+            .split_once('=')
+            .with_context(|| format!("Expected KEY=VALUE in --setting '{}'", setting))?;
+        let key = key.trim();
+        check_key(key)?;
+        let value = typed_value(key, raw.trim())?;
+        layers.push(single(
+            Origin::Flag,
+            format!("--setting {}", setting),
+            key,
+            value,
+        ));
+    }
+    Ok(layers)
+}
+
+/// Put the active profile between the `layers` from files and the `overriding` ones
+///
+/// The profile is `chosen` by `profile use` for this run, or else named by
+/// the highest layer that sets `profile`.
+fn stack(
+    mut layers: Vec<Layer>,
+    overriding: Vec<Layer>,
+    chosen: Option<String>,
+) -> Result<Vec<Layer>> {
+    // The profile can be chosen by any source, but only overrides the files
+    let active = chosen.or_else(|| {
+        overriding
+            .iter()
+            .rev()
+            .chain(layers.iter().rev())
+            .find_map(|layer| layer.table.get("profile")?.as_str().map(str::to_string))
+    });
+    if let Some(name) = active.filter(|name| name != NO_PROFILE) {
+        let mut table = find_profile(&merge(&layers), &name)?.clone();
+        table.insert("profile".to_string(), Value::String(name.clone()));
+        layers.push(Layer {
+            origin: Origin::Profile,
+            source: format!("profile '{}'", name),
+            table,
+        });
+    }
+
+    layers.extend(overriding);
+    Ok(layers)
+}
+
+/// Remove every known setting a project file may not change from `table`,
+/// returning the removed keys
+///
+/// Unknown keys are left in place, so that they are reported like those of
+/// any other file.
+fn restrict_project(table: &mut Table) -> Vec<String> {
+    let refused: Vec<String> = table
+        .keys()
+        .filter(|key| KEYS.contains(&key.as_str()) && !PROJECT_KEYS.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    for key in &refused {
+        table.remove(key);
+    }
+    refused
+}
+
+/// The settings of the profile `name` in a combined config table
+pub fn find_profile<'a>(merged: &'a Table, name: &str) -> Result<&'a Table> {
+    let profiles = merged.get("profiles").and_then(Value::as_table);
+    if let Some(profile) = profiles.and_then(|p| p.get(name)).and_then(Value::as_table) {
+        return Ok(profile);
+    }
+
+    let names: Vec<&str> = profiles
+        .map(|p| p.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    if names.is_empty() {
+        anyhow::bail!(
+            "Profile '{}' not found. No profiles are defined; add [profiles.{}] to the config file",
+            name,
+            name
+        );
+    }
+    anyhow::bail!(
+        "Profile '{}' not found. Available profiles: {}",
+        name,
+        names.join(", ")
+    )
+}
+
+fn single(origin: Origin, source: String, key: &str, value: Value) -> Layer {
+    let mut table = Table::new();
+    assign(&mut table, key, value);
+    Layer {
+        origin,
+        source,
+        table,
+    }
+}
+
+/// Combine layers into one table, later layers winning
+///
+/// Tables such as `category_weights` are merged entry by entry, while lists
+/// such as `themes` are replaced as a whole.
+pub fn merge(layers: &[Layer]) -> Table {
+    let mut merged = Table::new();
+    for layer in layers {
+        merge_into(&mut merged, &layer.table);
+    }
+    merged
+}
+
+fn merge_into(base: &mut Table, top: &Table) {
+    for (key, value) in top {
+        match (base.get_mut(key), value) {
+            (Some(Value::Table(base)), Value::Table(top)) => merge_into(base, top),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Load the configuration from every layer, or `None` if nothing is configured
+///
+/// A file that cannot be read or does not match the schema is an error, so
+/// that a typo never silently replaces the user's settings with defaults.
+pub fn load_config() -> Result<Option<Config>> {
+    let layers = layers()?;
+    if layers.len() == 1 {
+        return Ok(None);
+    }
+
+    WARNED.call_once(|| {
+        for layer in &layers {
+            for key in unknown_keys(&layer.table) {
+                eprintln!("{}", unknown_message(key, &layer.source));
+            }
+        }
+    });
+
+    let config = merge(&layers)
+        .try_into()
+        .context("Failed to combine config files")?;
+    Ok(Some(config))
+}
+
+/// Parse the contents of a config file, explaining any problem
+pub fn parse(path: &Path, content: &str) -> Result<Config> {
+    let config: Config = toml::from_str(content).map_err(|e| invalid(path, content, &e))?;
+    check(&config).with_context(|| format!("Invalid config file {}", path.display()))?;
+    Ok(config)
+}
+
+/// Checks the schema cannot make, such as whether the query parses
+fn check(config: &Config) -> Result<()> {
+    config.parsed_query()?;
+
+    for (name, table) in &config.profiles {
+        for key in ["version", "profile", "profiles"] {
+            if table.contains_key(key) {
+                anyhow::bail!("Profile '{}' cannot set '{}'", name, key);
+            }
+        }
+        let profile: Config = table
+            .clone()
+            .try_into()
+            .with_context(|| format!("Invalid profile '{}'", name))?;
+        check(&profile).with_context(|| format!("Invalid profile '{}'", name))?;
+    }
+    Ok(())
+}
+
+/// Read the user's config file as a TOML table, or `None` if there is no file
+pub fn read_table() -> Result<Option<Table>> {
+    read_file(&get_config_path()?, Origin::User)
+}
+
+/// Read and check a config file, or `None` if there is no such file
+///
+/// Files written for an older schema are upgraded first. The user's own
+/// file is upgraded in place, other files only in memory, since they are
+/// often shared or owned by someone else.
+fn read_file(path: &Path, origin: Origin) -> Result<Option<Table>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (table, changes) = upgrade(path, &content)?;
+    if !changes.is_empty() && origin == Origin::User {
+        // A file that cannot be written is still read correctly
+        let saved = toml::to_string(&table)
+            .context("Failed to serialize config")
+            .and_then(|upgraded| save_upgrade(path, &upgraded, &changes));
+        if let Err(e) = saved {
+            eprintln!("Failed to upgrade {}: {:#}", path.display(), e);
+        }
+    }
+    Ok(Some(table))
+}
+
+/// Upgrade and check the contents of a config file, returning the upgraded
+/// table and what the upgrade changed
+pub fn upgrade(path: &Path, content: &str) -> Result<(Table, Vec<String>)> {
+    let mut table = content
+        .parse::<Table>()
+        .map_err(|e| invalid(path, content, &e))?;
+    let changes = migrate::migrate(&mut table)
+        .with_context(|| format!("Failed to upgrade {}", path.display()))?;
+    if changes.is_empty() {
+        parse(path, content)?;
+    } else {
+        let upgraded = toml::to_string(&table).context("Failed to serialize config")?;
+        parse(path, &upgraded)?;
+    }
+    Ok((table, changes))
+}
+
+/// Replace a config file with its upgraded `content`, keeping a copy of
+/// the old one and reporting what changed
+fn save_upgrade(path: &Path, content: &str, changes: &[String]) -> Result<()> {
+    let backup = back_up(path)?;
+    fs::write(path, content).context("Failed to write config file")?;
+    report_upgrade(path, changes, &backup);
+    Ok(())
+}
+
+fn report_upgrade(path: &Path, changes: &[String], backup: &Path) {
+    eprintln!(
+        "Upgraded {} to config version {}:",
+        path.display(),
+        CURRENT_VERSION
+    );
+    for change in changes {
+        eprintln!("  - {}", change);
+    }
+    eprintln!("Saved a copy of the old file to {}", backup.display());
+}
+
+/// Change the user's config file, keeping everything else in it
+///
+/// A file that does not parse is copied aside first, since it may hold
+/// settings the user has not finished typing.
+pub fn update(change: impl FnOnce(&mut Table)) -> Result<()> {
     let config_path = get_config_path()?;
-    let content = toml::to_string(config).context("Failed to serialize config")?;
-    fs::write(config_path, content).context("Failed to write config file")?;
+    let content = if config_path.exists() {
+        Some(fs::read_to_string(&config_path).context("Failed to read config file")?)
+    } else {
+        None
+    };
+
+    let mut table = match &content {
+        Some(content) => content
+            .parse::<Table>()
+            .map_err(|e| invalid(&config_path, content, &e))?,
+        None => Table::new(),
+    };
+    let changes = migrate::migrate(&mut table)
+        .with_context(|| format!("Failed to upgrade {}", config_path.display()))?;
+    change(&mut table);
+
+    let updated = toml::to_string(&table).context("Failed to serialize config")?;
+    parse(&config_path, &updated)?;
+
+    if let Some(content) = &content {
+        if !changes.is_empty() {
+            let backup = back_up(&config_path)?;
+            report_upgrade(&config_path, &changes, &backup);
+        } else if parse(&config_path, content).is_err() {
+            let backup = back_up(&config_path)?;
+            println!(
+                "Saved a copy of the invalid config file to {}",
+                backup.display()
+            );
+        }
+    }
+
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+    fs::write(&config_path, updated).context("Failed to write config file")?;
     Ok(())
 }
 
 /// Get the wallpaper directory from config or default
 pub fn get_wallpaper_dir() -> Result<PathBuf> {
-    if let Some(config) = load_config() {
-        return Ok(config.wallpaper_dir);
+    Ok(load_config()?.unwrap_or_default().wallpaper_dir)
+}
+
+/// Whether `key` holds a single value that can come from the environment
+pub fn is_scalar(key: &str) -> bool {
+    !matches!(key, "version" | "category_weights" | "profiles") && !LISTS.contains(&key)
+}
+
+/// Check that `key` names a setting, e.g. `mode` or `category_weights.Nature`
+pub fn check_key(key: &str) -> Result<()> {
+    let (name, rest) = match key.split_once('.') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (key, None),
+    };
+
+    if !KEYS.contains(&name) {
+        match closest_key(name) {
+            Some(k) => anyhow::bail!("Unknown setting '{}'. Did you mean '{}'?", key, k),
+            None => anyhow::bail!(
+                "Unknown setting '{}'. Run 'wcapp config list' to see all settings",
+                key
+            ),
+        }
+    }
+
+    if let Some(rest) = rest
+        && (name != "category_weights" || rest.is_empty())
+    {
+        anyhow::bail!(
+            "Unknown setting '{}'. '{}' has no entry '{}'",
+            key,
+            name,
+            rest
+        );
+    }
+    Ok(())
+}
+
+/// The value of a setting such as `category_weights.Nature` in `table`
+pub fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    match key.split_once('.') {
+        Some((name, entry)) => table.get(name)?.as_table()?.get(entry),
+        None => table.get(key),
+    }
+}
+
+/// Set a setting such as `category_weights.Nature` in `table`
+pub fn assign(table: &mut Table, key: &str, value: Value) {
+    match key.split_once('.') {
+        Some((name, entry)) => {
+            let entries = table
+                .entry(name)
+                .or_insert_with(|| Value::Table(Table::new()));
+            if let Some(entries) = entries.as_table_mut() {
+                entries.insert(entry.to_string(), value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Remove a setting such as `category_weights.Nature` from `table`
+pub fn remove_key(table: &mut Table, key: &str) -> Option<Value> {
+    match key.split_once('.') {
+        Some((name, entry)) => table.get_mut(name)?.as_table_mut()?.remove(entry),
+        None => table.remove(key),
+    }
+}
+
+/// Turn text from the command line or environment into a value for `key`
+///
+/// The text is tried as TOML first, so that `300` is a number and `true` a
+/// boolean, and then as a plain string for paths and names.
+pub fn typed_value(key: &str, raw: &str) -> Result<Value> {
+    let parsed = format!("value = {}", raw)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"));
+    let mut candidates: Vec<Value> = parsed.into_iter().collect();
+    if !matches!(candidates.first(), Some(Value::String(_))) {
+        candidates.push(Value::String(raw.to_string()));
+    }
+
+    let mut errors = Vec::new();
+    for value in candidates {
+        let mut table = Table::new();
+        assign(&mut table, key, value.clone());
+        match table.try_into::<Config>() {
+            Ok(config) => match check(&config) {
+                Ok(()) => return Ok(value),
+                Err(e) => errors.push(format!("{:#}", e)),
+            },
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    // A type mismatch says less than the reason the value itself was refused
+    let error = errors
+        .iter()
+        .find(|e| !e.contains("invalid type"))
+        .or(errors.first())
+        .map(|e| e.trim().replace('\n', " "))
+        .unwrap_or_default();
+    anyhow::bail!("Invalid value for '{}': {}", key, error)
+}
+
+/// Copy the config file next to itself with a timestamp, returning the copy
+fn back_up(config_path: &Path) -> Result<PathBuf> {
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut backup = backup_path(config_path, &stamp, 1);
+    // Never replace an earlier copy made within the same second
+    let mut count = 1;
+    while backup.exists() {
+        count += 1;
+        backup = backup_path(config_path, &stamp, count);
+    }
+    fs::copy(config_path, &backup).context("Failed to back up config file")?;
+    Ok(backup)
+}
+
+/// Name of the `count`th backup of `config_path` made at `stamp`, such as
+/// `work.toml.<stamp>.bak` for `work.toml`
+fn backup_path(config_path: &Path, stamp: &str, count: u32) -> PathBuf {
+    let name = config_path
+        .file_name()
+        .map_or("config.toml".into(), |name| name.to_string_lossy());
+    match count {
+        1 => config_path.with_file_name(format!("{}.{}.bak", name, stamp)),
+        _ => config_path.with_file_name(format!("{}.{}-{}.bak", name, stamp, count)),
+    }
+}
+
+/// The known setting closest to a misspelt `name`, if any is close enough
+pub fn closest_key(name: &str) -> Option<&'static str> {
+    KEYS.iter()
+        .map(|&k| (strsim::jaro_winkler(name, k), k))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .filter(|(score, _)| *score > 0.8)
+        .map(|(_, k)| k)
+}
+
+/// Warning for a setting wcapp does not know, found in `source`
+pub fn unknown_message(key: &str, source: &str) -> String {
+    match closest_key(key) {
+        Some(k) => format!(
+            "Unknown setting '{}' in {} is ignored. Did you mean '{}'?",
+            key, source, k
+        ),
+        None => format!("Unknown setting '{}' in {} is ignored", key, source),
+    }
+}
+
+/// Top-level keys in `table` that wcapp does not know, and therefore ignores
+pub fn unknown_keys(table: &Table) -> Vec<&str> {
+    table
+        .keys()
+        .map(String::as_str)
+        .filter(|key| !KEYS.contains(key))
+        .collect()
+}
+
+/// Describe a parse error with the file, line and column, and a hint
+fn invalid(path: &Path, content: &str, error: &toml::de::Error) -> anyhow::Error {
+    let message = error.message().trim().replace('\n', " ");
+    let mut text = format!("Invalid config file {}", path.display());
+
+    match error.span() {
+        Some(span) => {
+            let (line, column) = position(content, span.start);
+            text += &format!("\n  Line {}, column {}: {}", line, column, message);
+            if let Some(source) = content.lines().nth(line - 1) {
+                text += &format!("\n  | {}", source);
+            }
+        }
+        None => text += &format!("\n  {}", message),
+    }
+
+    let hint =
+        hint(&message).unwrap_or_else(|| "Fix the file with 'wcapp config edit'".to_string());
+    text += &format!("\n  Hint: {}", hint);
+    anyhow::anyhow!(text)
+}
+
+/// 1-based line and column of the byte at `offset`
+fn position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, column)
+}
+
+/// A suggestion for fixing the problem behind a parse error `message`
+fn hint(message: &str) -> Option<String> {
+    let quoted = message.contains("invalid type: string");
+    let wants_text = message.contains("expected a string") || message.contains("expected string");
+    if quoted && !wants_text {
+        Some("Remove the quotes around the value".to_string())
+    } else if !quoted && message.contains("invalid type") && wants_text {
+        Some("Put the value in quotes, e.g. mode = \"fill\"".to_string())
+    } else if message.contains("invalid string") || message.contains("expected `\"`") {
+        Some("Put text values in quotes, e.g. mode = \"fill\"".to_string())
+    } else if message.contains("duplicate key") {
+        Some("Remove one of the two lines setting it".to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(origin: Origin, content: &str) -> Layer {
+        Layer {
+            origin,
+            source: origin.to_string(),
+            table: content.parse().unwrap(),
+        }
+    }
+
+    fn setter(table: &Table) -> &str {
+        table["setter"].as_str().unwrap()
+    }
+
+    #[test]
+    fn each_layer_overrides_the_ones_below() {
+        let files = vec![
+            layer(
+                Origin::Default,
+                "setter = \"default\"\n[profiles.work]\nsetter = \"profile\"",
+            ),
+            layer(Origin::System, "setter = \"system\""),
+            layer(Origin::User, "setter = \"user\"\nprofile = \"work\""),
+            layer(Origin::Project, "setter = \"project\""),
+        ];
+        let overriding = vec![
+            layer(Origin::Env, "setter = \"env\""),
+            layer(Origin::Flag, "setter = \"flag\""),
+        ];
+
+        let layers = stack(files, overriding, None).unwrap();
+        let origins: Vec<Origin> = layers.iter().map(|l| l.origin).collect();
+        assert_eq!(
+            origins,
+            [
+                Origin::Default,
+                Origin::System,
+                Origin::User,
+                Origin::Project,
+                Origin::Profile,
+                Origin::Env,
+                Origin::Flag,
+            ]
+        );
+        assert!(origins.is_sorted());
+
+        // Taking away the top layer each time reveals the one below
+        let expected = [
+            "default", "system", "user", "project", "profile", "env", "flag",
+        ];
+        for (count, setting) in expected.iter().enumerate() {
+            assert_eq!(setter(&merge(&layers[..=count])), *setting);
+        }
     }
 
-    let pictures_dir = dirs::picture_dir().context("Could not find Pictures directory")?;
-    Ok(pictures_dir.join("wcapp"))
+    #[test]
+    fn the_highest_layer_naming_a_profile_picks_it() {
+        let files = || {
+            vec![layer(
+                Origin::User,
+                "profile = \"work\"\n[profiles.work]\nsetter = \"work\"\n[profiles.home]\nsetter = \"home\"",
+            )]
+        };
+
+        let layers = stack(files(), vec![], None).unwrap();
+        assert_eq!(setter(&merge(&layers)), "work");
+
+        let env = vec![layer(Origin::Env, "profile = \"home\"")];
+        let layers = stack(files(), env, None).unwrap();
+        assert_eq!(layers[1].origin, Origin::Profile);
+        assert_eq!(setter(&merge(&layers)), "home");
+
+        // `profile use` for this run beats every layer
+        let env = vec![layer(Origin::Env, "profile = \"home\"")];
+        let layers = stack(files(), env, Some("work".to_string())).unwrap();
+        assert_eq!(setter(&merge(&layers)), "work");
+
+        let layers = stack(files(), vec![], Some(NO_PROFILE.to_string())).unwrap();
+        assert_eq!(layers.len(), 1);
+    }
+
+    #[test]
+    fn unknown_profiles_are_an_error() {
+        let files = vec![layer(Origin::User, "[profiles.work]\n[profiles.home]")];
+        let error = stack(files, vec![], Some("gym".to_string())).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Profile 'gym' not found. Available profiles: work, home"
+        );
+
+        let error = stack(vec![], vec![], Some("gym".to_string())).unwrap_err();
+        assert!(error.to_string().contains("No profiles are defined"));
+    }
+
+    #[test]
+    fn tables_merge_by_entry_and_lists_are_replaced() {
+        let merged = merge(&[
+            layer(
+                Origin::User,
+                "[category_weights]\nNature = 2.0\nCity = 1.0\n\n[[themes]]\ntemplate = \"a\"\n\n[[themes]]\ntemplate = \"b\"",
+            ),
+            layer(
+                Origin::Project,
+                "[category_weights]\nCity = 0.0\n\n[[themes]]\ntemplate = \"c\"",
+            ),
+        ]);
+
+        let weights = merged["category_weights"].as_table().unwrap();
+        assert_eq!(weights["Nature"].as_float(), Some(2.0));
+        assert_eq!(weights["City"].as_float(), Some(0.0));
+
+        let themes = merged["themes"].as_array().unwrap();
+        assert_eq!(themes.len(), 1);
+        assert_eq!(themes[0]["template"].as_str(), Some("c"));
+    }
+
+    #[test]
+    fn project_files_only_keep_harmless_settings() {
+        let mut table: Table = r#"
+            mode = "fit"
+            query = "tag:dark"
+            wallpaper_dir = "/elsewhere"
+            setter = "rm -rf ~"
+            moed = "fill"
+            [category_weights]
+            City = 2.0
+            [[hooks]]
+            command = "curl example.com"
+            [[themes]]
+            template = "a"
+        "#
+        .parse()
+        .unwrap();
+
+        let mut refused = restrict_project(&mut table);
+        refused.sort();
+        assert_eq!(refused, ["hooks", "setter", "themes", "wallpaper_dir"]);
+
+        // Unknown keys stay, to be reported like in any other file
+        let kept: Vec<&str> = table.keys().map(String::as_str).collect();
+        assert_eq!(kept, ["mode", "query", "moed", "category_weights"]);
+    }
+
+    fn error(content: &str) -> String {
+        parse(Path::new("/home/me/wcapp.toml"), content)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_line_and_column() {
+        assert_eq!(
+            error("weighted = true\ncycle_interval = \"5 minutes\"\n"),
+            "Invalid config file /home/me/wcapp.toml\n  \
+             Line 2, column 18: invalid type: string \"5 minutes\", expected u64\n  \
+             | cycle_interval = \"5 minutes\"\n  \
+             Hint: Remove the quotes around the value"
+        );
+    }
+
+    #[test]
+    fn backups_are_named_after_the_config_file() {
+        let stamp = "20260101-120000";
+        assert_eq!(
+            backup_path(Path::new("/home/me/work.toml"), stamp, 1),
+            Path::new("/home/me/work.toml.20260101-120000.bak")
+        );
+        assert_eq!(
+            backup_path(Path::new("/home/me/work.toml"), stamp, 2),
+            Path::new("/home/me/work.toml.20260101-120000-2.bak")
+        );
+
+        let dir = std::env::temp_dir().join(format!("wcapp-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("work.toml");
+        fs::write(&path, "mode = \"fill\"\n").unwrap();
+        let first = back_up(&path).unwrap();
+        let second = back_up(&path).unwrap();
+        let copied = fs::read_to_string(&second).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let name = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name(&first).starts_with("work.toml.") && name(&first).ends_with(".bak"));
+        assert_ne!(first, second);
+        assert_eq!(copied, "mode = \"fill\"\n");
+    }
+
+    #[test]
+    fn columns_count_characters_not_bytes() {
+        assert_eq!(position("a = 1\n", 0), (1, 1));
+        assert_eq!(position("a = 1\nb = 2", 10), (2, 5));
+        assert_eq!(position("# été\nx", 8), (2, 1));
+        assert_eq!(position("é = ?", 5), (1, 5));
+        // Offsets past the end stop at the last character
+        assert_eq!(position("a", 40), (1, 2));
+    }
+
+    #[test]
+    fn parse_errors_suggest_a_fix() {
+        assert!(
+            error("mode = fill\n")
+                .ends_with("Hint: Put text values in quotes, e.g. mode = \"fill\"")
+        );
+        assert!(
+            error("query = 3\n").ends_with("Hint: Put the value in quotes, e.g. mode = \"fill\"")
+        );
+        assert!(
+            error("mode = \"fit\"\nmode = \"fill\"\n")
+                .ends_with("Hint: Remove one of the two lines setting it")
+        );
+        assert!(error("[broken\n").ends_with("Hint: Fix the file with 'wcapp config edit'"));
+    }
+
+    #[test]
+    fn environment_variables_are_typed_by_setting() {
+        let vars: BTreeMap<&str, &str> = [
+            ("WCAPP_CYCLE_INTERVAL", "60"),
+            ("WCAPP_MODE", "fit"),
+            ("WCAPP_WEIGHTED", "true"),
+            ("WCAPP_WALLPAPER_DIR", "/home/me/Pictures"),
+            // Lists cannot come from the environment
+            ("WCAPP_HOOKS", "[]"),
+        ]
+        .into_iter()
+        .collect();
+
+        let layers = env_layers(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+        assert!(layers.iter().all(|l| l.origin == Origin::Env));
+        let merged = merge(&layers);
+        assert_eq!(merged["cycle_interval"], Value::Integer(60));
+        assert_eq!(merged["mode"], Value::String("fit".to_string()));
+        assert_eq!(merged["weighted"], Value::Boolean(true));
+        assert_eq!(
+            merged["wallpaper_dir"],
+            Value::String("/home/me/Pictures".to_string())
+        );
+        assert!(!merged.contains_key("hooks"));
+        assert_eq!(layers[0].source, "WCAPP_WALLPAPER_DIR");
+    }
+
+    #[test]
+    fn bad_environment_values_name_the_variable() {
+        let error = env_layers(|name| (name == "WCAPP_MODE").then(|| "sideways".to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Invalid value for 'mode'"), "{error}");
+        assert!(error.ends_with("(from WCAPP_MODE)"), "{error}");
+    }
+
+    #[test]
+    fn setting_flags_are_parsed_and_checked() {
+        let settings = [
+            "weighted=true".to_string(),
+            " category_weights.Nature = 2.5 ".to_string(),
+        ];
+        let layers = flag_layers(&settings).unwrap();
+        assert_eq!(
+            layers[1].source,
+            "--setting  category_weights.Nature = 2.5 "
+        );
+        let merged = merge(&layers);
+        assert_eq!(merged["weighted"], Value::Boolean(true));
+        assert_eq!(
+            lookup(&merged, "category_weights.Nature"),
+            Some(&Value::Float(2.5))
+        );
+
+        let error = |setting: &str| flag_layers(&[setting.to_string()]).unwrap_err().to_string();
+        assert_eq!(error("mode"), "Expected KEY=VALUE in --setting 'mode'");
+        assert_eq!(
+            error("mdoe=fill"),
+            "Unknown setting 'mdoe'. Did you mean 'mode'?"
+        );
+        assert!(error("cycle_interval=soon").starts_with("Invalid value for 'cycle_interval'"));
+    }
+
+    #[test]
+    fn settings_are_checked_by_name() {
+        assert!(check_key("mode").is_ok());
+        assert!(check_key("category_weights.Nature").is_ok());
+
+        let error = |key: &str| check_key(key).unwrap_err().to_string();
+        assert_eq!(
+            error("mdoe"),
+            "Unknown setting 'mdoe'. Did you mean 'mode'?"
+        );
+        assert_eq!(
+            error("colour_scheme"),
+            "Unknown setting 'colour_scheme'. Run 'wcapp config list' to see all settings"
+        );
+        assert_eq!(
+            error("mode.fill"),
+            "Unknown setting 'mode.fill'. 'mode' has no entry 'fill'"
+        );
+        assert_eq!(
+            error("category_weights."),
+            "Unknown setting 'category_weights.'. 'category_weights' has no entry ''"
+        );
+    }
+
+    #[test]
+    fn values_are_typed_by_setting() {
+        let typed = |key: &str, raw: &str| typed_value(key, raw).unwrap();
+        assert_eq!(typed("cycle_interval", "60"), Value::Integer(60));
+        assert_eq!(typed("weighted", "true"), Value::Boolean(true));
+        assert_eq!(typed("category_weights.Nature", "2.5"), Value::Float(2.5));
+        assert_eq!(typed("mode", "fill"), Value::String("fill".to_string()));
+        assert_eq!(
+            typed("wallpaper_dir", "/home/me/Pictures"),
+            Value::String("/home/me/Pictures".to_string())
+        );
+        // Text settings keep what looks like a number as text
+        assert_eq!(typed("profile", "2024"), Value::String("2024".to_string()));
+
+        let error = |key: &str, raw: &str| typed_value(key, raw).unwrap_err().to_string();
+        assert!(error("cycle_interval", "soon").starts_with("Invalid value for 'cycle_interval'"));
+        assert!(error("weighted", "maybe").starts_with("Invalid value for 'weighted'"));
+        assert!(error("category_weights.Nature", "heavy").starts_with("Invalid value for"));
+        // The reason a value is refused wins over the type mismatch
+        assert!(
+            error("mode", "sideways").contains("sideways"),
+            "{}",
+            error("mode", "sideways")
+        );
+        assert!(
+            error("query", "(tag:nature").contains("Missing ')' in query"),
+            "{}",
+            error("query", "(tag:nature")
+        );
+    }
+
+    #[test]
+    fn contents_are_upgraded_before_they_are_checked() {
+        let path = Path::new("/home/me/wcapp.toml");
+
+        let (table, changes) = upgrade(path, "cycle_interval = 300\nmode = \"fill\"\n").unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(table["version"], Value::Integer(CURRENT_VERSION.into()));
+        assert!(!table.contains_key("cycle_interval"));
+
+        let current = format!("version = {}\nmode = \"fill\"\n", CURRENT_VERSION);
+        let (table, changes) = upgrade(path, &current).unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(table["mode"], Value::String("fill".to_string()));
+
+        // Plain parsing would take a newer version as just another number
+        let newer = format!("version = {}\n", CURRENT_VERSION + 1);
+        assert!(parse(path, &newer).is_ok());
+        let error = format!("{:#}", upgrade(path, &newer).unwrap_err());
+        assert!(error.contains("for a newer wcapp"), "{error}");
+
+        let error = format!(
+            "{:#}",
+            upgrade(path, "version = 0\nmode = 3\n").unwrap_err()
+        );
+        assert!(error.contains("/home/me/wcapp.toml"), "{error}");
+    }
 }
@@ -0,0 +1,218 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config;
+
+/// How long a client waits for a running `cycle` to answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Something a running `cycle` is asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Switch to the profile with this name, or "none" for no profile
+    Profile(String),
+}
+
+impl Message {
+    fn to_line(&self) -> String {
+        match self {
+            Message::Profile(name) => format!("profile {}", name),
+        }
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        match line.split_once(' ')? {
+            ("profile", name) if !name.trim().is_empty() => {
+                Some(Message::Profile(name.trim().to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// File a running `cycle` writes its port and token to
+fn get_port_path() -> Result<PathBuf> {
+    Ok(config::get_state_dir()?.join("cycle.port"))
+}
+
+/// Accept messages from other wcapp processes on a local port
+///
+/// The port and a random token are written to `cycle.port` in the state
+/// directory; only clients that can read that file can send messages.
+/// Messages arrive on the returned channel.
+pub fn listen() -> Result<Receiver<Message>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .context("Failed to listen for profile changes")?;
+    let port = listener.local_addr()?.port();
+    let token = format!("{:016x}", rand::random::<u64>());
+
+    write_private(&get_port_path()?, &format!("{} {}\n", port, token))
+        .context("Failed to write cycle port file")?;
+
+    Ok(serve(listener, token))
+}
+
+/// Write a file only its owner can read, since it holds the token
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, and an old one may be left over
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
+}
+
+/// Answer clients on `listener` in the background, passing on messages
+/// that carry `token`
+fn serve(listener: TcpListener, token: String) -> Receiver<Message> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle(stream, &token, &sender) {
+                eprintln!("Failed to read message: {}", e);
+            }
+        }
+    });
+    receiver
+}
+
+/// Read one message from a client and answer it
+fn handle(stream: TcpStream, token: &str, sender: &Sender<Message>) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let mut writer = &stream;
+    let message = line
+        .trim_end()
+        .strip_prefix(token)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .and_then(Message::from_line);
+    match message {
+        Some(message) => {
+            sender.send(message)?;
+            writeln!(writer, "ok")?;
+        }
+        None => writeln!(writer, "error")?,
+    }
+    Ok(())
+}
+
+/// Send `message` to a running `cycle`, returning whether one received it
+pub fn send(message: &Message) -> Result<bool> {
+    let port_path = get_port_path()?;
+    let Ok(content) = fs::read_to_string(&port_path) else {
+        return Ok(false);
+    };
+    let Some((port, token)) = content
+        .trim()
+        .split_once(' ')
+        .and_then(|(port, token)| Some((port.parse::<u16>().ok()?, token)))
+    else {
+        anyhow::bail!("Invalid cycle port file {}", port_path.display());
+    };
+
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let stream = match TcpStream::connect_timeout(&address, TIMEOUT) {
+        Ok(stream) => stream,
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            // The cycle that wrote the file is gone
+            let _ = fs::remove_file(&port_path);
+            return Ok(false);
+        }
+        Err(e) => return Err(e).context("Failed to reach the running cycle"),
+    };
+    exchange(&stream, token, message)?;
+    Ok(true)
+}
+
+/// Send `message` with `token` and wait for the answer
+fn exchange(stream: &TcpStream, token: &str, message: &Message) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut writer = stream;
+    writeln!(writer, "{} {}", token, message.to_line())?;
+    let mut answer = String::new();
+    BufReader::new(stream)
+        .read_line(&mut answer)
+        .context("Failed to hear back from the running cycle")?;
+
+    if answer.trim() != "ok" {
+        anyhow::bail!("The running cycle refused the message");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn running() -> (TcpStream, Receiver<Message>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let receiver = serve(listener, TOKEN.to_string());
+        (TcpStream::connect(address).unwrap(), receiver)
+    }
+
+    #[test]
+    fn messages_read_back_as_written() {
+        let message = Message::Profile("work laptop".to_string());
+        assert_eq!(Message::from_line(&message.to_line()), Some(message));
+
+        assert_eq!(
+            Message::from_line("profile  home "),
+            Some(Message::Profile("home".to_string()))
+        );
+        assert_eq!(Message::from_line("profile "), None);
+        assert_eq!(Message::from_line("profile"), None);
+        assert_eq!(Message::from_line("restart now"), None);
+    }
+
+    #[test]
+    fn messages_with_the_token_reach_the_cycle() {
+        let (stream, receiver) = running();
+        let message = Message::Profile("work".to_string());
+
+        exchange(&stream, TOKEN, &message).unwrap();
+        assert_eq!(receiver.recv_timeout(TIMEOUT).unwrap(), message);
+    }
+
+    #[test]
+    fn messages_without_the_token_are_refused() {
+        let (stream, receiver) = running();
+        let message = Message::Profile("work".to_string());
+
+        let error = exchange(&stream, "fedcba9876543210", &message).unwrap_err();
+        assert_eq!(error.to_string(), "The running cycle refused the message");
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn port_file_is_private() {
+        let path = std::env::temp_dir().join(format!("wcapp-port-{}", std::process::id()));
+        fs::write(&path, "left over").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, "1234 token\n").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(content, "1234 token\n");
+    }
+}
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::config::{self, Config};
+
+/// Screen size assumed when it cannot be detected
+const FALLBACK_SIZE: (u32, u32) = (1920, 1080);
+
+/// A display and where it sits on the desktop, in pixels
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    /// Output name, e.g. "DP-1"
+    pub name: String,
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Whether this is the main display
+    #[serde(default)]
+    pub primary: bool,
+}
+
+impl Monitor {
+    fn new(name: &str, (width, height): (u32, u32)) -> Self {
+        Self {
+            name: name.to_string(),
+            x: 0,
+            y: 0,
+            width,
+            height,
+            primary: true,
+        }
+    }
+}
+
+/// A monitor given on the command line, by position or by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MonitorRef {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for MonitorRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            anyhow::bail!("Monitor cannot be empty");
+        }
+        Ok(match s.parse() {
+            Ok(index) => MonitorRef::Index(index),
+            Err(_) => MonitorRef::Name(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for MonitorRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MonitorRef::Index(index) => write!(f, "{}", index),
+            MonitorRef::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl MonitorRef {
+    /// Position of the monitor in `monitors`
+    pub fn resolve(&self, monitors: &[Monitor]) -> Result<usize> {
+        let index = match self {
+            MonitorRef::Index(index) => Some(*index).filter(|&i| i < monitors.len()),
+            MonitorRef::Name(name) => monitors
+                .iter()
+                .position(|m| m.name.eq_ignore_ascii_case(name)),
+        };
+
+        index.with_context(|| {
+            let list: Vec<String> = monitors
+                .iter()
+                .enumerate()
+                .map(|(i, m)| format!("  {}: {}", i, m.name))
+                .collect();
+            format!(
+                "Monitor '{}' not found. Monitors:\n{}",
+                self,
+                list.join("\n")
+            )
+        })
+    }
+}
+
+/// Which displays a wallpaper goes to
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The same wallpaper everywhere, as the desktop shows it
+    #[default]
+    All,
+    /// A single monitor, keeping what the others show
+    One(MonitorRef),
+    /// A different wallpaper on every monitor
+    Each,
+}
+
+impl Target {
+    pub fn new(monitor: Option<MonitorRef>, spread: bool) -> Self {
+        match monitor {
+            Some(monitor) => Target::One(monitor),
+            None if spread => Target::Each,
+            None => Target::All,
+        }
+    }
+}
+
+/// The monitors from the config, or as reported by the system
+///
+/// Monitors are ordered left to right, then top to bottom, so `--monitor 0`
+/// is the leftmost one. There is always at least one monitor.
+pub fn layout(config: Option<&Config>) -> Vec<Monitor> {
+    let mut monitors = config
+        .map(|c| c.monitors.clone())
+        .filter(|m| !m.is_empty())
+        .or_else(xrandr_monitors)
+        .or_else(sway_monitors)
+        .or_else(|| macos_size().map(|size| vec![Monitor::new("default", size)]))
+        .unwrap_or_else(|| vec![Monitor::new("default", FALLBACK_SIZE)]);
+
+    monitors.sort_by_key(|m| (m.x, m.y));
+    monitors
+}
+
+/// Size of the primary screen in pixels
+pub fn screen_size(monitors: &[Monitor]) -> (u32, u32) {
+    monitors
+        .iter()
+        .find(|m| m.primary)
+        .or(monitors.first())
+        .map_or(FALLBACK_SIZE, |m| (m.width, m.height))
+}
+
+/// Smallest rectangle around all monitors, as `(x, y, width, height)`
+pub fn bounds(monitors: &[Monitor]) -> (i32, i32, u32, u32) {
+    let left = monitors.iter().map(|m| m.x).min().unwrap_or(0);
+    let top = monitors.iter().map(|m| m.y).min().unwrap_or(0);
+    let right = monitors
+        .iter()
+        .map(|m| i64::from(m.x) + i64::from(m.width))
+        .max()
+        .unwrap_or(0);
+    let bottom = monitors
+        .iter()
+        .map(|m| i64::from(m.y) + i64::from(m.height))
+        .max()
+        .unwrap_or(0);
+
+    (
+        left,
+        top,
+        (right - i64::from(left)).max(1) as u32,
+        (bottom - i64::from(top)).max(1) as u32,
+    )
+}
+
+/// A wallpaper shown on a monitor
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Shown {
+    pub name: String,
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// What the monitors show
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Screens {
+    /// Wallpaper last set on the whole desktop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub all: Option<Shown>,
+    /// Wallpapers set on single monitors since then, by monitor name
+    #[serde(default)]
+    pub monitors: BTreeMap<String, Shown>,
+}
+
+impl Screens {
+    /// The wallpaper on `monitor`
+    pub fn on(&self, monitor: &Monitor) -> Option<&Shown> {
+        self.monitors.get(&monitor.name).or(self.all.as_ref())
+    }
+}
+
+/// Get the path to the file recording what each monitor shows
+pub fn get_screens_path() -> Result<PathBuf> {
+    config::locate(config::get_state_dir()?, "monitors.json")
+}
+
+/// Load what each monitor shows
+pub fn load_screens() -> Result<Screens> {
+    let path = get_screens_path()?;
+    if !path.exists() {
+        return Ok(Screens::default());
+    }
+
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Save what each monitor shows
+pub fn save_screens(screens: &Screens) -> Result<()> {
+    let path = get_screens_path()?;
+    let content = serde_json::to_string(screens).context("Failed to serialize monitors")?;
+    fs::write(path, content).context("Failed to write monitors file")?;
+    Ok(())
+}
+
+/// Run a command and return its output if it succeeded
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse `DP-1 connected primary 2560x1440+0+0 ...` lines from xrandr
+fn xrandr_monitors() -> Option<Vec<Monitor>> {
+    let output = run("xrandr", &["--query"])?;
+    let monitors: Vec<Monitor> = output
+        .lines()
+        .filter(|line| line.contains(" connected"))
+        .filter_map(|line| {
+            let name = line.split_whitespace().next()?;
+            let (width, height, x, y) = line.split_whitespace().find_map(parse_geometry)?;
+            Some(Monitor {
+                name: name.to_string(),
+                x,
+                y,
+                width,
+                height,
+                primary: line.contains(" primary "),
+            })
+        })
+        .collect();
+
+    (!monitors.is_empty()).then_some(monitors)
+}
+
+/// Parse a `WIDTHxHEIGHT+X+Y` geometry
+fn parse_geometry(geometry: &str) -> Option<(u32, u32, i32, i32)> {
+    let (size, position) = geometry.split_once('+')?;
+    let (width, height) = size.split_once('x')?;
+    let (x, y) = position.split_once('+')?;
+    Some((
+        width.parse().ok()?,
+        height.parse().ok()?,
+        x.parse().ok()?,
+        y.parse().ok()?,
+    ))
+}
+
+/// Active outputs in sway
+fn sway_monitors() -> Option<Vec<Monitor>> {
+    let output = run("swaymsg", &["-t", "get_outputs", "--raw"])?;
+    let outputs: Vec<serde_json::Value> = serde_json::from_str(&output).ok()?;
+    let monitors: Vec<Monitor> = outputs
+        .iter()
+        .filter(|o| o["active"] != false)
+        .filter_map(|o| {
+            let rect = &o["rect"];
+            Some(Monitor {
+                name: o["name"].as_str()?.to_string(),
+                x: rect["x"].as_i64()?.try_into().ok()?,
+                y: rect["y"].as_i64()?.try_into().ok()?,
+                width: rect["width"].as_u64()?.try_into().ok()?,
+                height: rect["height"].as_u64()?.try_into().ok()?,
+                primary: o["focused"] == true,
+            })
+        })
+        .collect();
+
+    (!monitors.is_empty()).then_some(monitors)
+}
+
+/// Parse `Resolution: 3024 x 1964 Retina` from system_profiler on macOS
+fn macos_size() -> Option<(u32, u32)> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+
+    let output = run("system_profiler", &["SPDisplaysDataType"])?;
+    let resolution = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Resolution:"))?;
+    let mut numbers = resolution
+        .split_whitespace()
+        .filter_map(|word| word.parse().ok());
+    Some((numbers.next()?, numbers.next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i32, y: i32, width: u32, height: u32) -> Monitor {
+        Monitor {
+            name: name.to_string(),
+            x,
+            y,
+            width,
+            height,
+            primary: false,
+        }
+    }
+
+    fn names(monitors: &[Monitor]) -> Vec<&str> {
+        monitors.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    /// The layout for monitors given in the config
+    fn configured(monitors: Vec<Monitor>) -> Vec<Monitor> {
+        let config = Config {
+            monitors,
+            ..Config::default()
+        };
+        layout(Some(&config))
+    }
+
+    #[test]
+    fn bounds_of_side_by_side_monitors() {
+        let monitors = [
+            monitor("A", 0, 0, 1920, 1080),
+            monitor("B", 1920, 0, 2560, 1440),
+        ];
+        assert_eq!(bounds(&monitors), (0, 0, 4480, 1440));
+    }
+
+    #[test]
+    fn bounds_with_negative_offsets() {
+        let monitors = [
+            monitor("left", -1280, 200, 1280, 1024),
+            monitor("main", 0, 0, 1920, 1080),
+        ];
+        assert_eq!(bounds(&monitors), (-1280, 0, 3200, 1224));
+    }
+
+    #[test]
+    fn bounds_include_gaps() {
+        let monitors = [
+            monitor("A", 0, 0, 1000, 800),
+            monitor("B", 1200, 100, 1000, 800),
+        ];
+        assert_eq!(bounds(&monitors), (0, 0, 2200, 900));
+    }
+
+    #[test]
+    fn bounds_of_stacked_monitors() {
+        let monitors = [
+            monitor("top", 0, -1080, 1920, 1080),
+            monitor("bottom", 0, 0, 1920, 1080),
+        ];
+        assert_eq!(bounds(&monitors), (0, -1080, 1920, 2160));
+    }
+
+    #[test]
+    fn bounds_of_no_monitors_are_not_empty() {
+        assert_eq!(bounds(&[]), (0, 0, 1, 1));
+    }
+
+    #[test]
+    fn layout_orders_left_to_right_then_top_to_bottom() {
+        let monitors = configured(vec![
+            monitor("right", 1920, 0, 1920, 1080),
+            monitor("bottom", 0, 0, 1920, 1080),
+            monitor("left", -1280, 0, 1280, 1024),
+            monitor("top", 0, -1080, 1920, 1080),
+        ]);
+        assert_eq!(names(&monitors), ["left", "top", "bottom", "right"]);
+    }
+
+    #[test]
+    fn monitor_refs_resolve_against_the_sorted_layout() {
+        let monitors = configured(vec![
+            monitor("DP-2", 1920, 0, 1920, 1080),
+            monitor("DP-1", 0, 0, 1920, 1080),
+        ]);
+        assert_eq!(MonitorRef::Index(0).resolve(&monitors).unwrap(), 0);
+        assert_eq!(monitors[0].name, "DP-1");
+        assert_eq!(
+            MonitorRef::Name("dp-2".to_string())
+                .resolve(&monitors)
+                .unwrap(),
+            1
+        );
+        assert!(MonitorRef::Index(2).resolve(&monitors).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::render::Color;
+
+/// Size of the copy used to find the most detailed part of an image
+const ENERGY_SIZE: u32 = 256;
+/// Vignette strength when none is given
+const DEFAULT_VIGNETTE: f32 = 0.5;
+/// Names accepted in an effect list, for error messages
+const NAMES: &str = "crop, downscale, blur, darken, brightness, grayscale, vignette, border";
+
+/// Adjustments made to a wallpaper before it is set, e.g. `blur=8,darken=0.3`
+///
+/// Effects always run in the order of the fields, whatever order they were
+/// written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Effects {
+    /// Crop to the aspect ratio of the screen, keeping the most detailed part
+    pub crop: bool,
+    /// Scale down to fit the screen
+    pub downscale: bool,
+    /// Blur radius in pixels
+    pub blur: Option<f32>,
+    /// How much to darken, from 0 to 1
+    pub darken: Option<f32>,
+    /// Brightness factor, above 1 to brighten and below to darken
+    pub brightness: Option<f32>,
+    /// Remove all colour
+    pub grayscale: bool,
+    /// How much to darken the corners, from 0 to 1
+    pub vignette: Option<f32>,
+    /// Width in pixels and colour of a frame around the edges
+    pub border: Option<(u32, Color)>,
+}
+
+impl Effects {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Run the effects on `image` for a screen of `size` pixels
+    pub fn apply(&self, image: DynamicImage, size: (u32, u32)) -> RgbImage {
+        let mut image = image.to_rgb8();
+
+        if self.crop {
+            image = smart_crop(&image, size);
+        }
+        if self.downscale && (image.width() > size.0 || image.height() > size.1) {
+            image = DynamicImage::ImageRgb8(image)
+                .resize(size.0, size.1, FilterType::Lanczos3)
+                .to_rgb8();
+        }
+        if let Some(radius) = self.blur {
+            image = imageops::fast_blur(&image, radius);
+        }
+
+        let factor = self.darken.map_or(1.0, |d| 1.0 - d) * self.brightness.unwrap_or(1.0);
+        if factor != 1.0 {
+            for pixel in image.pixels_mut() {
+                pixel.0 = pixel
+                    .0
+                    .map(|c| (f32::from(c) * factor).round().min(255.0) as u8);
+            }
+        }
+
+        if self.grayscale {
+            image = DynamicImage::ImageRgb8(image).grayscale().to_rgb8();
+        }
+        if let Some(strength) = self.vignette {
+            vignette(&mut image, strength);
+        }
+        if let Some((width, color)) = self.border {
+            border(&mut image, width, color);
+        }
+
+        image
+    }
+}
+
+impl FromStr for Effects {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut effects = Effects::default();
+        if s.trim() == "none" {
+            return Ok(effects);
+        }
+
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (name, value) = match item.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (item, None),
+            };
+
+            match (name, value) {
+                ("crop", None) => effects.crop = true,
+                ("downscale", None) => effects.downscale = true,
+                ("grayscale" | "greyscale", None) => effects.grayscale = true,
+                ("vignette", None) => effects.vignette = Some(DEFAULT_VIGNETTE),
+                ("blur", Some(value)) => effects.blur = Some(number(name, value, 0.0, 100.0)?),
+                ("darken", Some(value)) => effects.darken = Some(number(name, value, 0.0, 1.0)?),
+                ("brightness", Some(value)) => {
+                    effects.brightness = Some(number(name, value, 0.0, 10.0)?)
+                }
+                ("vignette", Some(value)) => {
+                    effects.vignette = Some(number(name, value, 0.0, 1.0)?)
+                }
+                ("border", Some(value)) => {
+                    let (width, color) = match value.split_once(':') {
+                        Some((width, color)) => (width, color.parse()?),
+                        None => (value, Color::default()),
+                    };
+                    let width = width
+                        .parse()
+                        .with_context(|| format!("Invalid border width '{}'", width))?;
+                    effects.border = Some((width, color));
+                }
+                ("crop" | "downscale" | "grayscale" | "greyscale", Some(_)) => {
+                    anyhow::bail!("Effect '{}' does not take a value", name)
+                }
+                ("blur" | "darken" | "brightness" | "border", None) => {
+                    anyhow::bail!("Effect '{}' needs a value, e.g. {}=...", name, name)
+                }
+                _ => anyhow::bail!("Unknown effect '{}' (expected one of: {})", name, NAMES),
+            }
+        }
+
+        Ok(effects)
+    }
+}
+
+impl fmt::Display for Effects {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items = Vec::new();
+        if self.crop {
+            items.push("crop".to_string());
+        }
+        if self.downscale {
+            items.push("downscale".to_string());
+        }
+        if let Some(radius) = self.blur {
+            items.push(format!("blur={}", radius));
+        }
+        if let Some(amount) = self.darken {
+            items.push(format!("darken={}", amount));
+        }
+        if let Some(factor) = self.brightness {
+            items.push(format!("brightness={}", factor));
+        }
+        if self.grayscale {
+            items.push("grayscale".to_string());
+        }
+        if let Some(strength) = self.vignette {
+            items.push(format!("vignette={}", strength));
+        }
+        if let Some((width, color)) = self.border {
+            items.push(format!("border={}:{}", width, color));
+        }
+
+        if items.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", items.join(","))
+        }
+    }
+}
+
+impl TryFrom<String> for Effects {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Effects> for String {
+    fn from(effects: Effects) -> Self {
+        effects.to_string()
+    }
+}
+
+/// Parse an effect value and check that it lies in `min..=max`
+fn number(name: &str, value: &str, min: f32, max: f32) -> Result<f32> {
+    let number: f32 = value
+        .parse()
+        .with_context(|| format!("Invalid value '{}' for {}", value, name))?;
+    if !(min..=max).contains(&number) {
+        anyhow::bail!("{} must be between {} and {}", name, min, max);
+    }
+    Ok(number)
+}
+
+/// Crop `image` to the aspect ratio of `size`, keeping the most detailed part
+///
+/// Detail is measured as the sum of brightness differences between
+/// neighbouring pixels in a small copy of the image. Ties go to the window
+/// closest to the middle, so plain images are cropped evenly on both sides.
+fn smart_crop(image: &RgbImage, size: (u32, u32)) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let aspect = f64::from(size.0) / f64::from(size.1);
+    let horizontal = f64::from(width) / f64::from(height) > aspect;
+
+    let (crop_width, crop_height) = if horizontal {
+        (((f64::from(height) * aspect).round() as u32).max(1), height)
+    } else {
+        (width, ((f64::from(width) / aspect).round() as u32).max(1))
+    };
+    if (crop_width, crop_height) == (width, height) {
+        return image.clone();
+    }
+
+    // Energy of each column (or row) of a small grayscale copy
+    let small = DynamicImage::ImageRgb8(image.clone())
+        .thumbnail(ENERGY_SIZE, ENERGY_SIZE)
+        .to_luma8();
+    let (small_width, small_height) = small.dimensions();
+    let lines = if horizontal {
+        small_width
+    } else {
+        small_height
+    };
+    let mut energy = vec![0u64; lines as usize];
+    for y in 0..small_height {
+        for x in 0..small_width {
+            let here = i32::from(small.get_pixel(x, y).0[0]);
+            let right = i32::from(small.get_pixel((x + 1).min(small_width - 1), y).0[0]);
+            let below = i32::from(small.get_pixel(x, (y + 1).min(small_height - 1)).0[0]);
+            let line = if horizontal { x } else { y };
+            energy[line as usize] += (here.abs_diff(right) + here.abs_diff(below)) as u64;
+        }
+    }
+
+    // Slide a window of the cropped size over the lines and keep the best
+    let (full, cropped) = if horizontal {
+        (width, crop_width)
+    } else {
+        (height, crop_height)
+    };
+    let window = ((u64::from(cropped) * u64::from(lines)) / u64::from(full)).max(1) as usize;
+    let positions = energy.len().saturating_sub(window) + 1;
+    let middle = (positions - 1) as f64 / 2.0;
+    let best = (0..positions)
+        .map(|start| (start, energy[start..start + window].iter().sum::<u64>()))
+        .max_by(|(a, ea), (b, eb)| {
+            ea.cmp(eb).then_with(|| {
+                let distance = |p: usize| (p as f64 - middle).abs();
+                distance(*b).total_cmp(&distance(*a))
+            })
+        })
+        .map_or(0, |(start, _)| start);
+
+    let offset = if positions > 1 {
+        (u64::from(full - cropped) * best as u64 / (positions - 1) as u64) as u32
+    } else {
+        (full - cropped) / 2
+    };
+
+    if horizontal {
+        imageops::crop_imm(image, offset, 0, crop_width, crop_height).to_image()
+    } else {
+        imageops::crop_imm(image, 0, offset, crop_width, crop_height).to_image()
+    }
+}
+
+/// Darken the corners of `image`, leaving the middle untouched
+fn vignette(image: &mut RgbImage, strength: f32) {
+    let (width, height) = image.dimensions();
+    let center = (width as f32 / 2.0, height as f32 / 2.0);
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = (x as f32 + 0.5 - center.0) / center.0;
+        let dy = (y as f32 + 0.5 - center.1) / center.1;
+        // 0 in the middle, 1 in the corners
+        let distance = ((dx * dx + dy * dy) / 2.0).min(1.0);
+        let factor = 1.0 - strength * distance * distance;
+        pixel.0 = pixel.0.map(|c| (f32::from(c) * factor).round() as u8);
+    }
+}
+
+/// Paint a frame of `width` pixels in `color` along the edges of `image`
+fn border(image: &mut RgbImage, width: u32, color: Color) {
+    let (image_width, image_height) = image.dimensions();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if x < width || y < width || x + width >= image_width || y + width >= image_height {
+            *pixel = Rgb(color.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAY: Rgb<u8> = Rgb([200, 200, 200]);
+
+    fn effects(s: &str) -> Effects {
+        s.parse().unwrap()
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<Effects>().unwrap_err().to_string()
+    }
+
+    /// A gray image with a checkerboard in the `x..x + w, y..y + h` part
+    fn detailed(size: (u32, u32), detail: (u32, u32, u32, u32)) -> RgbImage {
+        let (dx, dy, dw, dh) = detail;
+        RgbImage::from_fn(size.0, size.1, |x, y| {
+            let inside = (dx..dx + dw).contains(&x) && (dy..dy + dh).contains(&y);
+            match (inside, (x + y) % 2) {
+                (true, 0) => Rgb([0, 0, 0]),
+                (true, _) => Rgb([255, 255, 255]),
+                (false, _) => GRAY,
+            }
+        })
+    }
+
+    #[test]
+    fn effects_read_back_as_written() {
+        let parsed = effects("blur=8,darken=0.3");
+        assert_eq!(parsed.blur, Some(8.0));
+        assert_eq!(parsed.darken, Some(0.3));
+        assert_eq!(parsed.to_string(), "blur=8,darken=0.3");
+
+        // Written in the order they run, whatever order they were given in
+        let parsed = effects(" border=4:#ff0000, vignette ,greyscale,crop,");
+        assert_eq!(
+            parsed.to_string(),
+            "crop,grayscale,vignette=0.5,border=4:#ff0000"
+        );
+        assert_eq!(effects(&parsed.to_string()), parsed);
+
+        let parsed = effects("downscale,brightness=1.2,border=2");
+        assert_eq!(parsed.border, Some((2, Color([0, 0, 0]))));
+        assert_eq!(effects(&parsed.to_string()), parsed);
+
+        assert!(effects("none").is_empty());
+        assert!(effects("").is_empty());
+        assert_eq!(Effects::default().to_string(), "none");
+    }
+
+    #[test]
+    fn bad_effects_are_refused() {
+        assert_eq!(error("blur"), "Effect 'blur' needs a value, e.g. blur=...");
+        assert_eq!(error("crop=1"), "Effect 'crop' does not take a value");
+        assert_eq!(
+            error("sharpen=2"),
+            format!("Unknown effect 'sharpen' (expected one of: {})", NAMES)
+        );
+        assert_eq!(error("darken=2"), "darken must be between 0 and 1");
+        assert_eq!(error("vignette=-0.5"), "vignette must be between 0 and 1");
+        assert_eq!(error("blur=lots"), "Invalid value 'lots' for blur");
+        assert_eq!(error("border=wide"), "Invalid border width 'wide'");
+        assert!(error("border=2:purple").starts_with("Invalid colour 'purple'"));
+    }
+
+    #[test]
+    fn crops_keep_the_detailed_part() {
+        // Detail on the right of a wide image
+        let image = detailed((64, 32), (32, 0, 32, 32));
+        let cropped = smart_crop(&image, (1, 1));
+        assert_eq!(cropped.dimensions(), (32, 32));
+        assert_eq!(
+            cropped,
+            imageops::crop_imm(&image, 32, 0, 32, 32).to_image()
+        );
+
+        // Detail at the top of a tall image
+        let image = detailed((32, 64), (0, 0, 32, 32));
+        let cropped = smart_crop(&image, (1, 1));
+        assert_eq!(cropped, imageops::crop_imm(&image, 0, 0, 32, 32).to_image());
+    }
+
+    #[test]
+    fn plain_images_are_cropped_evenly() {
+        // Too little red to change the brightness, so the mark adds no detail
+        let mark = Rgb([4, 0, 0]);
+        let mut image = RgbImage::new(64, 32);
+        image.put_pixel(16, 0, mark);
+
+        let cropped = smart_crop(&image, (1, 1));
+        assert_eq!(cropped.dimensions(), (32, 32));
+        // Within a pixel of the middle, since lines are scored on a scaled copy
+        let (x, _, _) = cropped.enumerate_pixels().find(|p| *p.2 == mark).unwrap();
+        let offset = 16 - x;
+        assert!(offset.abs_diff(16) <= 1, "cropped at {}", offset);
+
+        // Images already in shape are left alone
+        assert_eq!(smart_crop(&image, (1920, 960)), image);
+    }
+
+    #[test]
+    fn vignettes_darken_towards_the_corners() {
+        let mut image = RgbImage::from_pixel(5, 5, GRAY);
+        vignette(&mut image, 1.0);
+
+        let level = |x, y| image.get_pixel(x, y).0[0];
+        assert_eq!(level(2, 2), 200);
+        assert!(level(2, 0) < level(2, 1) && level(2, 1) < 200);
+        assert!(level(0, 0) < level(2, 0));
+        // 0.8 from the middle on both axes: 200 * (1 - 0.64²)
+        assert_eq!(level(0, 0), 118);
+        assert_eq!(level(4, 4), level(0, 0));
+
+        let mut unchanged = RgbImage::from_pixel(5, 5, GRAY);
+        vignette(&mut unchanged, 0.0);
+        assert!(unchanged.pixels().all(|p| *p == GRAY));
+    }
+
+    #[test]
+    fn borders_cover_only_the_edges() {
+        let red = Color([255, 0, 0]);
+        let mut image = RgbImage::from_pixel(6, 5, GRAY);
+        border(&mut image, 1, red);
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let edge = x == 0 || y == 0 || x == 5 || y == 4;
+            assert_eq!(*pixel, if edge { Rgb(red.0) } else { GRAY }, "({x}, {y})");
+        }
+
+        let mut image = RgbImage::from_pixel(6, 5, GRAY);
+        border(&mut image, 0, red);
+        assert!(image.pixels().all(|p| *p == GRAY));
+
+        border(&mut image, 3, red);
+        assert!(image.pixels().all(|p| *p == Rgb(red.0)));
+    }
+
+    #[test]
+    fn darkening_and_brightness_combine() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([100, 50, 200])));
+        let applied = effects("darken=0.5,brightness=1.5").apply(image, (2, 2));
+        assert!(applied.pixels().all(|p| *p == Rgb([75, 38, 150])));
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+
+/// How many entries are kept before the oldest ones are dropped
+const MAX_ENTRIES: usize = 1000;
+
+/// Log of wallpapers that have been set, oldest first
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct History {
+    #[serde(default)]
+    pub entries: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// Content hash of the wallpaper
+    pub hash: String,
+    /// Name at the time it was shown
+    pub name: String,
+    /// Unix timestamp in seconds
+    pub shown_at: i64,
+}
+
+impl History {
+    /// Append an entry for a wallpaper shown now
+    pub fn record(&mut self, hash: &str, name: &str) {
+        self.entries.push(HistoryEntry {
+            hash: hash.to_string(),
+            name: name.to_string(),
+            shown_at: chrono::Utc::now().timestamp(),
+        });
+
+        if self.entries.len() > MAX_ENTRIES {
+            let excess = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Most recent time each wallpaper was shown, keyed by hash
+    pub fn last_shown(&self) -> HashMap<&str, i64> {
+        let mut last_shown = HashMap::new();
+        for entry in &self.entries {
+            last_shown.insert(entry.hash.as_str(), entry.shown_at);
+        }
+        last_shown
+    }
+}
+
+/// Get the path to the history file
+pub fn get_history_path() -> Result<PathBuf> {
+    config::locate(config::get_state_dir()?, "history.json")
+}
+
+/// Load the history file, starting fresh if it does not exist yet
+pub fn load_history() -> Result<History> {
+    let history_path = get_history_path()?;
+    if !history_path.exists() {
+        return Ok(History::default());
+    }
+
+    let content = fs::read_to_string(&history_path)
+        .with_context(|| format!("Failed to read {}", history_path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", history_path.display()))
+}
+
+/// Save the history file
+pub fn save_history(history: &History) -> Result<()> {
+    let history_path = get_history_path()?;
+    let content = serde_json::to_string(history).context("Failed to serialize history")?;
+    fs::write(history_path, content).context("Failed to write history file")?;
+    Ok(())
+}
+
+/// Record a single wallpaper change in the history file
+pub fn record(hash: &str, name: &str) -> Result<()> {
+    let mut history = load_history()?;
+    history.record(hash, name);
+    save_history(&history)
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use wait_timeout::ChildExt;
+
+use crate::config::Config;
+use crate::library::Wallpaper;
+
+/// Seconds a hook may run before it is killed
+const DEFAULT_TIMEOUT: u64 = 30;
+
+/// Shell commands run when something happens, configured as `[[hooks]]`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hook {
+    /// Run after a wallpaper is set, by any command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_set: Option<String>,
+    /// Run after `fetch` has copied new wallpapers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_fetch_complete: Option<String>,
+    /// Run after every change made by `cycle`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_cycle_tick: Option<String>,
+    /// Seconds before the command is killed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Wait for the command to finish instead of running it in the background
+    #[serde(default)]
+    pub sync: bool,
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT
+}
+
+/// Something hooks can run on
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    Set,
+    FetchComplete,
+    CycleTick,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Set => "on_set",
+            Event::FetchComplete => "on_fetch_complete",
+            Event::CycleTick => "on_cycle_tick",
+        }
+    }
+}
+
+impl Hook {
+    /// The command to run on `event`, if any
+    fn command(&self, event: Event) -> Option<&str> {
+        match event {
+            Event::Set => self.on_set.as_deref(),
+            Event::FetchComplete => self.on_fetch_complete.as_deref(),
+            Event::CycleTick => self.on_cycle_tick.as_deref(),
+        }
+    }
+}
+
+/// Environment describing a wallpaper that was just set and the one it replaced
+pub fn wallpaper_env(
+    wallpaper: &Wallpaper,
+    previous: Option<&Path>,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("WCAPP_PATH", wallpaper.path.display().to_string()),
+        ("WCAPP_NAME", wallpaper.name.clone()),
+        (
+            "WCAPP_CATEGORY",
+            wallpaper.category.clone().unwrap_or_default(),
+        ),
+        (
+            "WCAPP_PREVIOUS",
+            previous
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Run the configured hooks for `event` with `env` added to their environment
+///
+/// Hooks never fail the command that triggered them: problems are printed
+/// and otherwise ignored. Background hooks are still killed after their
+/// timeout as long as wcapp keeps running.
+pub fn run(config: Option<&Config>, event: Event, env: &[(&'static str, String)]) {
+    let Some(hooks) = config.map(|c| &c.hooks) else {
+        return;
+    };
+
+    for hook in hooks {
+        let Some(command) = hook.command(event) else {
+            continue;
+        };
+
+        let child = shell(command)
+            .env("WCAPP_EVENT", event.as_str())
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Failed to run {} hook `{}`: {}", event.as_str(), command, e);
+                continue;
+            }
+        };
+
+        let timeout = Duration::from_secs(hook.timeout);
+        let command = command.to_string();
+        if hook.sync {
+            wait(child, timeout, event, &command);
+        } else {
+            thread::spawn(move || wait(child, timeout, event, &command));
+        }
+    }
+}
+
+/// Wait for a hook to exit, killing it once `timeout` has passed
+fn wait(mut child: Child, timeout: Duration, event: Event, command: &str) {
+    match child.wait_timeout(timeout) {
+        Ok(Some(status)) if status.success() => {}
+        Ok(Some(status)) => {
+            eprintln!("{} hook `{}` failed: {}", event.as_str(), command, status);
+        }
+        Ok(None) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            eprintln!(
+                "{} hook `{}` timed out after {} seconds",
+                event.as_str(),
+                command,
+                timeout.as_secs()
+            );
+        }
+        Err(e) => {
+            eprintln!(
+                "Failed to wait for {} hook `{}`: {}",
+                event.as_str(),
+                command,
+                e
+            );
+        }
+    }
+}
+
+/// A command running `command` in the platform's shell
+pub fn shell(command: &str) -> Command {
+    #[cfg(target_os = "windows")]
+    {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+/// Name of the journal file in the state directory
+pub const JOURNAL_FILE: &str = "journal.json";
+
+/// How many removals are remembered before the oldest ones are dropped
+const MAX_ENTRIES: usize = 20;
+
+/// Log of files `clean` removed, oldest first
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Journal {
+    #[serde(default)]
+    pub entries: Vec<JournalEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    /// Unix timestamp in seconds
+    pub removed_at: i64,
+    /// Whether the files were deleted for good instead of moved to the trash
+    #[serde(default)]
+    pub permanent: bool,
+    /// Files and folders removed, as they were named on disk
+    pub paths: Vec<PathBuf>,
+    /// Whether `restore` has brought them back
+    #[serde(default)]
+    pub restored: bool,
+}
+
+impl Journal {
+    /// Append an entry for files removed at `removed_at`
+    pub fn record(&mut self, paths: Vec<PathBuf>, permanent: bool, removed_at: i64) {
+        self.entries.push(JournalEntry {
+            removed_at,
+            permanent,
+            paths,
+            restored: false,
+        });
+
+        if self.entries.len() > MAX_ENTRIES {
+            let excess = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Add files removed later in the same run to the entry recorded at
+    /// `removed_at`, or record them on their own if it is gone
+    pub fn extend(&mut self, removed_at: i64, paths: Vec<PathBuf>, permanent: bool) {
+        match self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.removed_at == removed_at && !entry.restored)
+        {
+            Some(entry) => entry.paths.extend(paths),
+            None => self.record(paths, permanent, removed_at),
+        }
+    }
+
+    /// The most recent removal that can still be restored: one that went to
+    /// the trash and has not been brought back yet
+    ///
+    /// Files deleted for good, by `clean --permanent` or when `fetch` evicts
+    /// them, are passed over so they do not hide an earlier removal.
+    pub fn last_mut(&mut self) -> Option<&mut JournalEntry> {
+        self.entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.is_restorable())
+    }
+
+    /// The most recent removal deleted for good since the last one that can
+    /// be restored, so `restore` can say why it reaches further back
+    pub fn last_deleted(&self) -> Option<&JournalEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .take_while(|entry| !entry.is_restorable())
+            .find(|entry| entry.permanent)
+    }
+}
+
+impl JournalEntry {
+    fn is_restorable(&self) -> bool {
+        !self.restored && !self.permanent
+    }
+}
+
+/// Get the path to the journal file
+pub fn get_journal_path() -> Result<PathBuf> {
+    Ok(config::get_state_dir()?.join(JOURNAL_FILE))
+}
+
+/// Load the journal file, starting fresh if it does not exist yet
+pub fn load_journal() -> Result<Journal> {
+    let journal_path = get_journal_path()?;
+    if !journal_path.exists() {
+        return Ok(Journal::default());
+    }
+
+    let content = fs::read_to_string(&journal_path)
+        .with_context(|| format!("Failed to read {}", journal_path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", journal_path.display()))
+}
+
+/// Save the journal file
+pub fn save_journal(journal: &Journal) -> Result<()> {
+    let journal_path = get_journal_path()?;
+    let content = serde_json::to_string(journal).context("Failed to serialize journal")?;
+    fs::write(journal_path, content).context("Failed to write journal file")?;
+    Ok(())
+}
+
+/// Record files removed at `removed_at`, so that `restore` can bring them back
+///
+/// The time must be taken before the files are removed, since `restore`
+/// only looks at what went to the trash after it.
+pub fn record(paths: Vec<PathBuf>, permanent: bool, removed_at: i64) -> Result<()> {
+    let mut journal = load_journal()?;
+    journal.record(paths, permanent, removed_at);
+    save_journal(&journal)
+}
+
+/// Add files removed later in the same run to the removal recorded at
+/// `removed_at`, so that one `restore` brings back both
+pub fn extend(removed_at: i64, paths: Vec<PathBuf>, permanent: bool) -> Result<()> {
+    let mut journal = load_journal()?;
+    journal.extend(removed_at, paths, permanent);
+    save_journal(&journal)
+}
+
+/// Move files and folders to the system trash, or delete them for good,
+/// returning their absolute paths for the journal
+pub fn remove(paths: &[PathBuf], permanent: bool) -> Result<Vec<PathBuf>> {
+    let absolute = paths
+        .iter()
+        .map(|path| {
+            fs::canonicalize(path)
+                .with_context(|| format!("Failed to get absolute path of {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if permanent {
+        for path in &absolute {
+            remove_permanently(path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    } else {
+        trash::delete_all(&absolute).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to move files to the trash: {}. Use --permanent to delete them instead",
+                e
+            )
+        })?;
+    }
+    Ok(absolute)
+}
+
+fn remove_permanently(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|name| PathBuf::from("/w").join(name))
+            .collect()
+    }
+
+    #[test]
+    fn only_the_latest_removals_are_kept() {
+        let mut journal = Journal::default();
+        for removed_at in 0..MAX_ENTRIES as i64 + 5 {
+            journal.record(paths(&["a.jpg"]), false, removed_at);
+        }
+
+        assert_eq!(journal.entries.len(), MAX_ENTRIES);
+        assert_eq!(journal.entries[0].removed_at, 5);
+    }
+
+    #[test]
+    fn removals_in_one_run_share_an_entry() {
+        let mut journal = Journal::default();
+        journal.record(paths(&["a.jpg"]), false, 100);
+        journal.extend(100, paths(&["state"]), false);
+
+        assert_eq!(journal.entries.len(), 1);
+        assert_eq!(journal.entries[0].paths, paths(&["a.jpg", "state"]));
+    }
+
+    #[test]
+    fn extending_a_restored_removal_starts_a_new_one() {
+        let mut journal = Journal::default();
+        journal.record(paths(&["a.jpg"]), false, 100);
+        journal.entries[0].restored = true;
+        journal.extend(100, paths(&["state"]), false);
+
+        assert_eq!(journal.entries.len(), 2);
+        assert_eq!(journal.entries[1].paths, paths(&["state"]));
+        assert!(!journal.entries[1].restored);
+    }
+
+    #[test]
+    fn restored_removals_are_passed_over() {
+        let mut journal = Journal::default();
+        journal.record(paths(&["a.jpg"]), false, 100);
+        journal.record(paths(&["b.jpg"]), false, 200);
+        journal.last_mut().unwrap().restored = true;
+
+        assert_eq!(journal.last_mut().unwrap().removed_at, 100);
+        journal.last_mut().unwrap().restored = true;
+        assert!(journal.last_mut().is_none());
+    }
+
+    #[test]
+    fn permanent_removals_since_the_last_restorable_one_are_reported() {
+        let mut journal = Journal::default();
+        journal.record(paths(&["old.jpg"]), true, 50);
+        journal.record(paths(&["a.jpg"]), false, 100);
+        assert!(journal.last_deleted().is_none());
+
+        journal.record(paths(&["b.jpg"]), true, 200);
+        journal.record(paths(&["c.jpg", "d.jpg"]), true, 300);
+        assert_eq!(journal.last_deleted().unwrap().removed_at, 300);
+        assert_eq!(journal.last_mut().unwrap().removed_at, 100);
+
+        // With nothing left to restore, the latest deletion is still reported
+        journal.last_mut().unwrap().restored = true;
+        assert_eq!(journal.last_deleted().unwrap().removed_at, 300);
+        assert!(journal.last_mut().is_none());
+    }
+
+    #[test]
+    fn evictions_do_not_hide_an_earlier_clean() {
+        let mut journal = Journal::default();
+        journal.record(paths(&["a.jpg", "b.jpg"]), false, 100);
+        // What `fetch` records when it evicts
+        journal.record(paths(&["c.jpg"]), true, 200);
+
+        let entry = journal.last_mut().unwrap();
+        assert_eq!(entry.removed_at, 100);
+        assert_eq!(entry.paths, paths(&["a.jpg", "b.jpg"]));
+    }
+}
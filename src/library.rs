@@ -0,0 +1,419 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+use crate::config;
+use crate::matching::{self, Resolved};
+use crate::query::{Query, Subject};
+use crate::state::{self, State, WallpaperState};
+
+/// File extensions recognised as wallpapers
+pub const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
+
+/// A wallpaper file found in the library
+#[derive(Debug, Clone)]
+pub struct Wallpaper {
+    /// Full path on disk
+    pub path: PathBuf,
+    /// Path relative to the wallpaper directory, e.g. "Nature/sunset.jpg"
+    pub name: String,
+    /// Top-level folder the wallpaper lives in, if any
+    pub category: Option<String>,
+}
+
+impl Wallpaper {
+    fn from_path(root: &Path, path: &Path) -> Option<Self> {
+        let relative = path.strip_prefix(root).ok()?;
+        let category = if relative.components().count() > 1 {
+            relative
+                .components()
+                .next()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        } else {
+            None
+        };
+
+        Some(Self {
+            path: path.to_path_buf(),
+            name: relative.display().to_string(),
+            category,
+        })
+    }
+}
+
+/// Check whether a path has one of the supported image extensions
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Recursively collect all wallpapers under `dir`, sorted by name
+pub fn scan(dir: &Path) -> Vec<Wallpaper> {
+    let mut wallpapers: Vec<Wallpaper> = WalkDir::new(dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file() && is_image(e.path()))
+        .filter_map(|e| Wallpaper::from_path(dir, e.path()))
+        .collect();
+
+    wallpapers.sort_by(|a, b| a.name.cmp(&b.name));
+    wallpapers
+}
+
+/// Which wallpapers are eligible for a command
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    /// Only keep wallpapers marked as favorite
+    pub favorites: bool,
+    /// Drop wallpapers that have been banned
+    pub skip_banned: bool,
+    /// Only keep wallpapers matching this query
+    pub query: Option<Query>,
+}
+
+/// The wallpaper directory together with the user's per-wallpaper state
+pub struct Library {
+    pub dir: PathBuf,
+    pub wallpapers: Vec<Wallpaper>,
+    pub state: State,
+    index: Index,
+}
+
+impl Library {
+    /// Scan the configured wallpaper directory and load the saved state
+    pub fn open() -> Result<Self> {
+        let dir = config::get_wallpaper_dir()?;
+        Self::open_at(dir)
+    }
+
+    /// Scan `dir` and load the saved state
+    pub fn open_at(dir: PathBuf) -> Result<Self> {
+        Ok(Self {
+            index: Index::load(),
+            ..Self::with_state(dir, state::load_state()?)
+        })
+    }
+
+    /// Scan `dir` and start from `state`, without the saved state or hashes
+    ///
+    /// For callers that keep the state somewhere of their own; saving such a
+    /// library still writes to the usual files.
+    pub fn with_state(dir: PathBuf, state: State) -> Self {
+        let wallpapers = if dir.exists() { scan(&dir) } else { Vec::new() };
+
+        Self {
+            dir,
+            wallpapers,
+            state,
+            index: Index::default(),
+        }
+    }
+
+    /// Look up a wallpaper by its path relative to the wallpaper directory
+    pub fn find(&self, name: &str) -> Option<&Wallpaper> {
+        let path = self.dir.join(name);
+        self.wallpapers.iter().find(|w| w.path == path)
+    }
+
+    /// Find wallpapers by exact path, file name, substring or fuzzy match
+    pub fn resolve(&self, name: &str) -> Resolved<'_> {
+        matching::resolve(&self.wallpapers, name)
+    }
+
+    /// Look up a wallpaper by name and update its state, failing if it does not exist
+    pub fn update(&mut self, name: &str, f: impl FnOnce(&mut WallpaperState)) -> Result<()> {
+        let wallpaper = self.find(name).cloned().ok_or_else(|| {
+            anyhow::anyhow!("Wallpaper '{}' not found in {}", name, self.dir.display())
+        })?;
+
+        let hash = self.hash(&wallpaper)?;
+        self.state.update(&hash, &wallpaper.name, f);
+        Ok(())
+    }
+
+    /// Content hash of a wallpaper, served from the index when the file is unchanged
+    pub fn hash(&mut self, wallpaper: &Wallpaper) -> Result<String> {
+        self.index.hash(wallpaper)
+    }
+
+    /// Width and height of a wallpaper, or `None` if the image header cannot be read
+    pub fn dimensions(&mut self, wallpaper: &Wallpaper) -> Result<Option<(u32, u32)>> {
+        self.index.dimensions(wallpaper)
+    }
+
+    /// Saved state of a wallpaper, if it has any
+    pub fn state_of(&mut self, wallpaper: &Wallpaper) -> Result<Option<&WallpaperState>> {
+        if self.state.is_empty() {
+            return Ok(None);
+        }
+
+        let hash = self.hash(wallpaper)?;
+        Ok(self.state.get(&hash))
+    }
+
+    /// Wallpapers matching `filter`, in name order
+    pub fn select(&mut self, filter: &Filter) -> Result<Vec<Wallpaper>> {
+        self.select_from(self.wallpapers.clone(), filter)
+    }
+
+    /// The subset of `candidates` matching `filter`, keeping their order
+    pub fn select_from(
+        &mut self,
+        candidates: Vec<Wallpaper>,
+        filter: &Filter,
+    ) -> Result<Vec<Wallpaper>> {
+        if !filter.favorites
+            && filter.query.is_none()
+            && (!filter.skip_banned || self.state.is_empty())
+        {
+            return Ok(candidates);
+        }
+
+        let needs_dimensions = filter.query.as_ref().is_some_and(|q| q.needs_dimensions());
+
+        let mut selected = Vec::new();
+        for wallpaper in candidates {
+            let dimensions = if needs_dimensions {
+                self.dimensions(&wallpaper)?
+            } else {
+                None
+            };
+            let entry = self.state_of(&wallpaper)?;
+
+            if filter.favorites && !entry.is_some_and(|e| e.favorite) {
+                continue;
+            }
+            if filter.skip_banned && entry.is_some_and(|e| e.banned) {
+                continue;
+            }
+            if let Some(query) = &filter.query {
+                let subject = Subject {
+                    wallpaper: &wallpaper,
+                    state: entry,
+                    dimensions,
+                };
+                if !query.matches(&subject) {
+                    continue;
+                }
+            }
+            selected.push(wallpaper);
+        }
+
+        Ok(selected)
+    }
+
+    /// Persist the state file and any newly computed hashes
+    pub fn save(&self) -> Result<()> {
+        state::save_state(&self.state)?;
+        self.index.save()
+    }
+
+    /// Persist newly computed hashes without touching the state file
+    pub fn save_index(&self) -> Result<()> {
+        self.index.save()
+    }
+}
+
+/// Cache of content hashes, keyed by wallpaper path
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Index {
+    #[serde(default)]
+    entries: BTreeMap<PathBuf, IndexEntry>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexEntry {
+    size: u64,
+    modified: u64,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dimensions: Option<(u32, u32)>,
+}
+
+/// Get the path to the hash index
+pub fn get_index_path() -> Result<PathBuf> {
+    config::locate(config::get_cache_dir()?, "index.json")
+}
+
+impl Index {
+    /// Load the index; a missing or unreadable index is simply rebuilt
+    fn load() -> Self {
+        get_index_path()
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let content = serde_json::to_string(self).context("Failed to serialize index")?;
+        fs::write(get_index_path()?, content).context("Failed to write index file")?;
+        Ok(())
+    }
+
+    /// Up-to-date entry for a wallpaper, rehashing it if the file changed
+    fn entry(&mut self, wallpaper: &Wallpaper) -> Result<&mut IndexEntry> {
+        let metadata = fs::metadata(&wallpaper.path)
+            .with_context(|| format!("Failed to read {}", wallpaper.path.display()))?;
+        let size = metadata.len();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let fresh = self
+            .entries
+            .get(&wallpaper.path)
+            .is_some_and(|entry| entry.size == size && entry.modified == modified);
+
+        if !fresh {
+            let hash = hash_file(&wallpaper.path)?;
+            self.entries.insert(
+                wallpaper.path.clone(),
+                IndexEntry {
+                    size,
+                    modified,
+                    hash,
+                    dimensions: None,
+                },
+            );
+            self.dirty = true;
+        }
+
+        Ok(self
+            .entries
+            .get_mut(&wallpaper.path)
+            .expect("index entry was just inserted"))
+    }
+
+    fn hash(&mut self, wallpaper: &Wallpaper) -> Result<String> {
+        Ok(self.entry(wallpaper)?.hash.clone())
+    }
+
+    fn dimensions(&mut self, wallpaper: &Wallpaper) -> Result<Option<(u32, u32)>> {
+        let entry = self.entry(wallpaper)?;
+        if entry.dimensions.is_some() {
+            return Ok(entry.dimensions);
+        }
+
+        let dimensions = imagesize::size(&wallpaper.path)
+            .ok()
+            .map(|size| (size.width as u32, size.height as u32));
+        entry.dimensions = dimensions;
+        self.dirty |= dimensions.is_some();
+
+        Ok(dimensions)
+    }
+}
+
+/// SHA-256 of a file's contents as a lowercase hex string
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash {}", path.display()))?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Human-readable file size
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// A number of bytes written like "10MB" or "1.5GB", counted in steps of 1024
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: f64 = number.parse().map_err(|_| {
+            anyhow::anyhow!("Invalid size '{}' (expected e.g. 500KB, 10MB or 2GB)", s)
+        })?;
+
+        let exponent = match unit.trim().to_uppercase().as_str() {
+            "" | "B" => 0,
+            "K" | "KB" | "KIB" => 1,
+            "M" | "MB" | "MIB" => 2,
+            "G" | "GB" | "GIB" => 3,
+            "T" | "TB" | "TIB" => 4,
+            other => anyhow::bail!(
+                "Unknown size unit '{}' (expected B, KB, MB, GB or TB)",
+                other
+            ),
+        };
+        Ok(ByteSize((number * 1024f64.powi(exponent)) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_size(self.0))
+    }
+}
+
+impl TryFrom<String> for ByteSize {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<ByteSize> for String {
+    /// The largest unit the size is a whole number of, so that saving a
+    /// size never rounds it
+    fn from(size: ByteSize) -> Self {
+        const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+        let mut number = size.0;
+        let mut unit = 0;
+        while number != 0 && number.is_multiple_of(1024) && unit < UNITS.len() - 1 {
+            number /= 1024;
+            unit += 1;
+        }
+        format!("{}{}", number, UNITS[unit])
+    }
+}
@@ -1,10 +1,45 @@
 mod commands;
 mod config;
+mod control;
+mod display;
+mod effects;
+mod history;
+mod hooks;
+mod journal;
+mod library;
+mod matching;
+mod migrate;
+mod palette;
+mod picker;
+mod playlist;
+mod preview;
+mod prompt;
+mod query;
+mod quota;
+mod render;
+mod selection;
+mod setter;
+mod state;
+mod thumbnail;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::commands::clean::{Age, CleanOptions};
+use crate::commands::set::SetOptions;
+use crate::commands::uninstall::UninstallOptions;
+use crate::config::Overrides;
+use crate::display::{MonitorRef, Target};
+use crate::effects::Effects;
+use crate::library::ByteSize;
+use crate::palette::Format;
+use crate::prompt::Exit;
+use crate::query::Query;
+use crate::render::Color;
+use crate::selection::PickOptions;
+use crate::setter::Mode;
+
 /// A CLI tool to fetch and set wallpapers
 #[derive(Parser, Debug)]
 #[command(name = "wcapp")]
@@ -12,6 +47,18 @@ use std::path::PathBuf;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Use this config file instead of the user's
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Override a setting for this run, e.g. "mode=fit" (repeatable)
+    #[arg(long = "setting", global = true, value_name = "KEY=VALUE")]
+    settings: Vec<String>,
+
+    /// Use this profile for this run, or "none" for no profile
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -22,18 +69,94 @@ enum Commands {
         #[arg(short, long)]
         destination: Option<PathBuf>,
     },
-    /// Set a specific wallpaper
+    /// Set a specific wallpaper, or pick one from a list
     Set {
-        /// Name of the wallpaper file (you can use tab completion or list available ones)
+        /// Name of the wallpaper: a path like "Nature/sunset.jpg", a file name, or part of one
         #[arg(short, long)]
         name: Option<String>,
 
+        /// Use the best match when several wallpapers match the name
+        #[arg(long, requires = "name")]
+        first: bool,
+
         /// Set a random wallpaper instead
         #[arg(short, long)]
         random: bool,
+
+        /// Only pick from favorite wallpapers
+        #[arg(short, long, requires = "random")]
+        favorites: bool,
+
+        /// Only pick wallpapers matching a query, e.g. "tag:dark and width>=2560"
+        #[arg(short, long, requires = "random")]
+        query: Option<Query>,
+
+        /// Only pick from a playlist
+        #[arg(short, long, requires = "random")]
+        playlist: Option<String>,
+
+        /// Weight the pick by rating, favorites, category and recency
+        #[arg(short, long, requires = "random")]
+        weighted: bool,
+
+        /// Seed for the random pick, for reproducible results
+        #[arg(long, requires = "random")]
+        seed: Option<u64>,
+
+        /// Show which wallpaper would be set without setting it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Show a thumbnail of the wallpaper in the terminal
+        #[arg(long)]
+        preview: bool,
+
+        /// How to scale the wallpaper to the screen (default: from config)
+        #[arg(short, long, value_enum)]
+        mode: Option<Mode>,
+
+        /// Colour around fitted or centered wallpapers, e.g. "#1e1e2e"
+        #[arg(long)]
+        letterbox: Option<Color>,
+
+        /// Effects to apply first, e.g. "blur=8,darken=0.3" (overrides the playlist's)
+        #[arg(short, long)]
+        effect: Option<Effects>,
+
+        /// Only set one monitor, by number or name, e.g. 0 or DP-1
+        #[arg(short = 'M', long)]
+        monitor: Option<MonitorRef>,
+
+        /// Set a different random wallpaper on every monitor
+        #[arg(long, requires = "random", conflicts_with = "monitor")]
+        spread: bool,
     },
     /// List all available wallpapers
-    List,
+    List {
+        /// Only list favorite wallpapers
+        #[arg(short, long)]
+        favorites: bool,
+
+        /// Only list wallpapers matching a query, e.g. "tag:dark and width>=2560"
+        #[arg(short, long)]
+        query: Option<Query>,
+
+        /// Show a thumbnail of each wallpaper in the terminal
+        #[arg(long)]
+        preview: bool,
+    },
+    /// Show the wallpapers that were set most recently
+    History {
+        /// How many entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+
+        /// Show a thumbnail of each wallpaper in the terminal
+        #[arg(long)]
+        preview: bool,
+    },
+    /// Show how much space the library takes, against max_library_size
+    Stats,
     /// Cycle through wallpapers at a given interval
     Cycle {
         /// Interval in seconds (default: from config or 300)
@@ -43,28 +166,546 @@ enum Commands {
         /// Save the interval as default in config
         #[arg(short = 'd', long)]
         set_default: bool,
+
+        /// Only cycle through favorite wallpapers
+        #[arg(short, long)]
+        favorites: bool,
+
+        /// Only cycle through wallpapers matching a query, e.g. "tag:dark and width>=2560"
+        #[arg(short, long)]
+        query: Option<Query>,
+
+        /// Cycle through a playlist, in order unless the playlist is shuffled
+        #[arg(short, long)]
+        playlist: Option<String>,
+
+        /// Weight picks by rating, favorites, category and recency
+        #[arg(short, long)]
+        weighted: bool,
+
+        /// Seed for the random picks, for reproducible results
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// How to scale wallpapers to the screen (default: from config)
+        #[arg(short, long, value_enum)]
+        mode: Option<Mode>,
+
+        /// Colour around fitted or centered wallpapers, e.g. "#1e1e2e"
+        #[arg(long)]
+        letterbox: Option<Color>,
+
+        /// Effects to apply first, e.g. "blur=8,darken=0.3" (overrides the playlist's)
+        #[arg(short, long)]
+        effect: Option<Effects>,
+
+        /// Only cycle one monitor, by number or name, e.g. 0 or DP-1
+        #[arg(short = 'M', long)]
+        monitor: Option<MonitorRef>,
+
+        /// Show a different wallpaper on every monitor
+        #[arg(long, conflicts_with = "monitor")]
+        spread: bool,
+    },
+    /// List the monitors and what each one shows
+    Monitors,
+    /// Show the colour palette of a wallpaper, or write it as a theme
+    Palette {
+        /// Name of the wallpaper (default: the current one)
+        name: Option<String>,
+
+        /// Print the palette in this format instead
+        #[arg(short, long, value_enum)]
+        format: Option<Format>,
+
+        /// Write the theme files from the config
+        #[arg(short, long)]
+        write: bool,
+    },
+    /// Mark a wallpaper as a favorite
+    Fav {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
+    },
+    /// Remove a wallpaper from the favorites
+    Unfav {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
+    },
+    /// Rate a wallpaper from 1 to 5
+    Rate {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
+
+        /// Rating between 1 and 5
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=5))]
+        rating: u8,
+    },
+    /// Never pick a wallpaper in random mode or while cycling
+    Ban {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
     },
-    /// Remove all downloaded wallpapers
-    Clean,
+    /// Allow a banned wallpaper to be picked again
+    Unban {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
+    },
+    /// Manage wallpaper tags
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// Manage playlists of wallpapers
+    Playlist {
+        #[command(subcommand)]
+        action: PlaylistAction,
+    },
+    /// Browse the library in a full-screen terminal UI with previews
+    Tui,
+    /// Generate a static HTML gallery of the library
+    Gallery {
+        /// Folder to write the gallery to
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Serve a JSON API to control wallpapers over HTTP
+    ///
+    /// Every request must send a token; there is no way to turn this off.
+    /// Without --token, --token-file or WCAPP_API_TOKEN, a token is made up
+    /// and printed at startup.
+    Serve {
+        /// Address and port to listen on
+        #[arg(short, long, default_value = "127.0.0.1:7878")]
+        bind: String,
+
+        /// Token every request must send, made up for each run if none is
+        /// given; visible to other users in the process list, so prefer
+        /// --token-file or WCAPP_API_TOKEN
+        #[arg(short, long)]
+        token: Option<String>,
+
+        /// Read the token from this file
+        #[arg(long, conflicts_with = "token")]
+        token_file: Option<PathBuf>,
+    },
+    /// Show and change settings
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Switch between named sets of settings
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Remove downloaded wallpapers: all of them, or those matching the filters
+    Clean {
+        /// Only wallpapers in this category
+        #[arg(long)]
+        category: Option<String>,
+
+        /// Only wallpapers downloaded longer ago than this, e.g. "90d" or "2w"
+        #[arg(long, value_name = "AGE")]
+        older_than: Option<Age>,
+
+        /// Only files larger than this, e.g. "10MB"
+        #[arg(long, value_name = "SIZE")]
+        larger_than: Option<ByteSize>,
+
+        /// Keep favorite wallpapers
+        #[arg(long)]
+        not_favorite: bool,
+
+        /// Only banned wallpapers
+        #[arg(long)]
+        banned: bool,
+
+        /// Only wallpapers matching a query, e.g. "tag:old or width<1920"
+        #[arg(short, long)]
+        query: Option<Query>,
+
+        /// List the files that would be removed and their total size, without removing them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Delete for good instead of moving to the trash
+        #[arg(long)]
+        permanent: bool,
+
+        /// Remove without asking; only wallpapers go, never state, data or cache
+        #[arg(short, long, visible_alias = "force")]
+        yes: bool,
+    },
+    /// Bring back the wallpapers removed by the last clean from the trash
+    Restore,
     /// Uninstall wcapp from the system
-    Uninstall,
+    #[command(group(clap::ArgGroup::new("picked").args(["binary", "config_files", "wallpapers"]).multiple(true)))]
+    Uninstall {
+        /// Remove the wcapp binary
+        #[arg(long)]
+        binary: bool,
+
+        /// Remove configuration, history, state and caches
+        #[arg(long)]
+        config_files: bool,
+
+        /// Remove downloaded wallpapers
+        #[arg(long)]
+        wallpapers: bool,
+
+        /// Remove what was picked without asking
+        #[arg(short, long, requires = "picked")]
+        yes: bool,
+
+        /// Delete for good instead of moving to the trash
+        #[arg(long)]
+        permanent: bool,
+    },
     /// Update wcapp to the latest version
     Update,
 }
 
-fn main() -> Result<()> {
+#[derive(Subcommand, Debug)]
+enum TagAction {
+    /// Add tags to a wallpaper
+    Add {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
+
+        /// Tags to add
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from a wallpaper
+    Rm {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: String,
+
+        /// Tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// List the tags of a wallpaper, or all tags in use
+    Ls {
+        /// Name of the wallpaper (path relative to the wallpaper directory)
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PlaylistAction {
+    /// Create a playlist
+    Create {
+        /// Name of the playlist
+        name: String,
+
+        /// Save a query instead of a static list, e.g. "tag:dark and is:favorite"
+        #[arg(short, long)]
+        query: Option<String>,
+
+        /// Pick randomly from the playlist instead of playing it in order
+        #[arg(short, long)]
+        shuffle: bool,
+
+        /// Effects for wallpapers set from the playlist, e.g. "blur=8,darken=0.3"
+        #[arg(short, long)]
+        effect: Option<Effects>,
+    },
+    /// Add wallpapers to a playlist
+    Add {
+        /// Name of the playlist
+        playlist: String,
+
+        /// Wallpapers to add (paths relative to the wallpaper directory)
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Remove wallpapers from a playlist
+    Remove {
+        /// Name of the playlist
+        playlist: String,
+
+        /// Wallpapers to remove (paths relative to the wallpaper directory)
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Show the wallpapers in a playlist, or list all playlists
+    Show {
+        /// Name of the playlist
+        name: Option<String>,
+    },
+    /// Delete a playlist
+    Delete {
+        /// Name of the playlist
+        name: String,
+    },
+    /// Export a playlist to share it
+    Export {
+        /// Name of the playlist
+        name: String,
+
+        /// File to write to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import a playlist from a file
+    Import {
+        /// Playlist file to import
+        file: PathBuf,
+
+        /// Name for the playlist (defaults to the file name)
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Overwrite an existing playlist with the same name
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the value of a setting, e.g. "cycle_interval" or "category_weights.Nature"
+    Get {
+        /// Name of the setting
+        key: String,
+    },
+    /// Change a setting
+    Set {
+        /// Name of the setting
+        key: String,
+
+        /// New value, checked before it is saved
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+    /// Remove a setting so that its default applies again
+    Unset {
+        /// Name of the setting
+        key: String,
+    },
+    /// List all settings and where their values come from
+    List {
+        /// Show the file, environment variable or flag behind each value
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Open the config file in $EDITOR and check it when it is saved
+    Edit,
+    /// Check the config file, exiting with an error if anything in it is wrong
+    Check,
+    /// Print the path of the config file
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileAction {
+    /// Make a profile the active one, switching a running cycle over to it
+    Use {
+        /// Name of the profile, or "none" to stop using profiles
+        name: String,
+    },
+    /// List the profiles in the config
+    List,
+    /// Print the settings of a profile
+    Show {
+        /// Name of the profile (defaults to the active one)
+        name: Option<String>,
+    },
+}
+
+fn main() {
+    if let Err(e) = run() {
+        // Ways of stopping that scripts need to tell apart get their own exit codes
+        match e.downcast_ref::<Exit>() {
+            Some(exit) => {
+                eprintln!("{}", exit);
+                std::process::exit(exit.code());
+            }
+            None => {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
+    config::set_overrides(Overrides {
+        config: cli.config,
+        settings: cli.settings,
+        profile: cli.profile,
+    });
 
     match cli.command {
         Commands::Fetch { destination } => commands::fetch::execute(destination)?,
-        Commands::Set { name, random } => commands::set::execute(name, random)?,
-        Commands::List => commands::list::execute()?,
+        Commands::Set {
+            name,
+            first,
+            random,
+            favorites,
+            query,
+            playlist,
+            weighted,
+            seed,
+            dry_run,
+            preview,
+            mode,
+            letterbox,
+            effect,
+            monitor,
+            spread,
+        } => commands::set::execute(
+            name,
+            first,
+            random,
+            PickOptions {
+                favorites,
+                query,
+                playlist,
+                weighted,
+                seed,
+            },
+            SetOptions {
+                dry_run,
+                preview,
+                mode,
+                letterbox,
+                effects: effect,
+                target: Target::new(monitor, spread),
+            },
+        )?,
+        Commands::List {
+            favorites,
+            query,
+            preview,
+        } => commands::list::execute(favorites, query, preview)?,
+        Commands::History { limit, preview } => commands::history::execute(limit, preview)?,
+        Commands::Stats => commands::stats::execute()?,
         Commands::Cycle {
             interval,
             set_default,
-        } => commands::cycle::execute(interval, set_default)?,
-        Commands::Clean => commands::clean::execute()?,
-        Commands::Uninstall => commands::uninstall::execute()?,
+            favorites,
+            query,
+            playlist,
+            weighted,
+            seed,
+            mode,
+            letterbox,
+            effect,
+            monitor,
+            spread,
+        } => commands::cycle::execute(
+            interval,
+            set_default,
+            PickOptions {
+                favorites,
+                query,
+                playlist,
+                weighted,
+                seed,
+            },
+            mode,
+            letterbox,
+            effect,
+            Target::new(monitor, spread),
+        )?,
+        Commands::Monitors => commands::monitors::execute()?,
+        Commands::Palette {
+            name,
+            format,
+            write,
+        } => commands::palette::execute(name.as_deref(), format, write)?,
+        Commands::Fav { name } => commands::mark::favorite(&name)?,
+        Commands::Unfav { name } => commands::mark::unfavorite(&name)?,
+        Commands::Rate { name, rating } => commands::mark::rate(&name, rating)?,
+        Commands::Ban { name } => commands::mark::ban(&name)?,
+        Commands::Unban { name } => commands::mark::unban(&name)?,
+        Commands::Tag { action } => match action {
+            TagAction::Add { name, tags } => commands::tag::add(&name, &tags)?,
+            TagAction::Rm { name, tags } => commands::tag::remove(&name, &tags)?,
+            TagAction::Ls { name } => commands::tag::list(name.as_deref())?,
+        },
+        Commands::Playlist { action } => match action {
+            PlaylistAction::Create {
+                name,
+                query,
+                shuffle,
+                effect,
+            } => commands::playlist::create(&name, query, shuffle, effect)?,
+            PlaylistAction::Add { playlist, names } => {
+                commands::playlist::add(&playlist, &names)?
+            }
+            PlaylistAction::Remove { playlist, names } => {
+                commands::playlist::remove(&playlist, &names)?
+            }
+            PlaylistAction::Show { name } => commands::playlist::show(name.as_deref())?,
+            PlaylistAction::Delete { name } => commands::playlist::delete(&name)?,
+            PlaylistAction::Export { name, output } => {
+                commands::playlist::export(&name, output.as_deref())?
+            }
+            PlaylistAction::Import { file, name, force } => {
+                commands::playlist::import(&file, name.as_deref(), force)?
+            }
+        },
+        Commands::Tui => commands::tui::execute()?,
+        Commands::Gallery { out } => commands::gallery::execute(&out)?,
+        Commands::Serve {
+            bind,
+            token,
+            token_file,
+        } => commands::serve::execute(&bind, token, token_file)?,
+        Commands::Config { action } => match action {
+            ConfigAction::Get { key } => commands::config::get(&key)?,
+            ConfigAction::Set { key, value } => commands::config::set(&key, &value)?,
+            ConfigAction::Unset { key } => commands::config::unset(&key)?,
+            ConfigAction::List { origin } => commands::config::list(origin)?,
+            ConfigAction::Edit => commands::config::edit()?,
+            ConfigAction::Check => commands::config::check()?,
+            ConfigAction::Path => commands::config::path()?,
+        },
+        Commands::Profile { action } => match action {
+            ProfileAction::Use { name } => commands::profile::use_profile(&name)?,
+            ProfileAction::List => commands::profile::list()?,
+            ProfileAction::Show { name } => commands::profile::show(name.as_deref())?,
+        },
+        Commands::Clean {
+            category,
+            older_than,
+            larger_than,
+            not_favorite,
+            banned,
+            query,
+            dry_run,
+            permanent,
+            yes,
+        } => commands::clean::execute(CleanOptions {
+            category,
+            older_than,
+            larger_than,
+            not_favorite,
+            banned,
+            query,
+            dry_run,
+            permanent,
+            yes,
+        })?,
+        Commands::Restore => commands::restore::execute()?,
+        Commands::Uninstall {
+            binary,
+            config_files,
+            wallpapers,
+            yes,
+            permanent,
+        } => commands::uninstall::execute(UninstallOptions {
+            binary,
+            config: config_files,
+            wallpapers,
+            yes,
+            permanent,
+        })?,
         Commands::Update => commands::update::execute()?,
     }
 
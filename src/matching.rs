@@ -0,0 +1,307 @@
+use crate::library::Wallpaper;
+
+/// How many candidates or suggestions are shown at most
+const MAX_CANDIDATES: usize = 10;
+const MAX_SUGGESTIONS: usize = 3;
+/// Minimum similarity (0 to 1) for a name to be suggested
+const MIN_SIMILARITY: f64 = 0.4;
+
+/// Outcome of resolving a wallpaper name typed by the user
+pub enum Resolved<'a> {
+    /// Exactly one wallpaper matches
+    Found(&'a Wallpaper),
+    /// Several wallpapers match at the first step of [`resolve`] that matches
+    /// anything: fuzzy matches best first, the other steps in library order
+    Ambiguous(Vec<&'a Wallpaper>),
+    /// Nothing matches; these are the closest names
+    NotFound(Vec<&'a Wallpaper>),
+}
+
+/// Resolve `name` against the library, trying in turn:
+/// the exact relative path, the bare file name (with or without extension),
+/// a case-insensitive substring and finally a fuzzy subsequence match
+pub fn resolve<'a>(wallpapers: &'a [Wallpaper], name: &str) -> Resolved<'a> {
+    let wanted = normalize(name);
+
+    if let Some(wallpaper) = wallpapers.iter().find(|w| normalize(&w.name) == wanted) {
+        return Resolved::Found(wallpaper);
+    }
+
+    let by_filename: Vec<&Wallpaper> = wallpapers
+        .iter()
+        .filter(|w| {
+            w.path
+                .file_name()
+                .is_some_and(|f| normalize(&f.to_string_lossy()) == wanted)
+                || w.path
+                    .file_stem()
+                    .is_some_and(|f| normalize(&f.to_string_lossy()) == wanted)
+        })
+        .collect();
+    if let Some(resolved) = pick(by_filename) {
+        return resolved;
+    }
+
+    let by_substring: Vec<&Wallpaper> = wallpapers
+        .iter()
+        .filter(|w| normalize(&w.name).contains(&wanted))
+        .collect();
+    if let Some(resolved) = pick(by_substring) {
+        return resolved;
+    }
+
+    let mut by_fuzzy: Vec<(i64, &Wallpaper)> = wallpapers
+        .iter()
+        .filter_map(|w| fuzzy_score(&wanted, &normalize(&w.name)).map(|score| (score, w)))
+        .collect();
+    by_fuzzy.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    if let Some(resolved) = pick(by_fuzzy.into_iter().map(|(_, w)| w).collect()) {
+        return resolved;
+    }
+
+    Resolved::NotFound(suggest(wallpapers, &wanted))
+}
+
+fn pick(mut matches: Vec<&Wallpaper>) -> Option<Resolved<'_>> {
+    match matches.len() {
+        0 => None,
+        1 => Some(Resolved::Found(matches[0])),
+        _ => {
+            matches.truncate(MAX_CANDIDATES);
+            Some(Resolved::Ambiguous(matches))
+        }
+    }
+}
+
+/// Lowercase and use forward slashes so names typed on any OS compare equal
+fn normalize(name: &str) -> String {
+    name.trim().replace('\\', "/").to_lowercase()
+}
+
+/// Score how well `pattern` matches `text` as a subsequence, or `None` if it
+/// does not occur at all. Consecutive characters and characters at the start
+/// of a word score higher; long gaps and long names score lower.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0i64;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+
+    for pc in pattern.chars().filter(|c| !c.is_whitespace()) {
+        let pos = next + text[next..].iter().position(|&c| c == pc)?;
+
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == pos) {
+            score += 5;
+        }
+        if pos == 0 || !text[pos - 1].is_alphanumeric() {
+            score += 3;
+        }
+        if previous.is_some() {
+            score -= (pos - next).min(5) as i64;
+        }
+
+        previous = Some(pos);
+        next = pos + 1;
+    }
+
+    Some(score - text.len() as i64 / 10)
+}
+
+/// The names most similar to `wanted`, best first
+fn suggest<'a>(wallpapers: &'a [Wallpaper], wanted: &str) -> Vec<&'a Wallpaper> {
+    let mut scored: Vec<(f64, &Wallpaper)> = wallpapers
+        .iter()
+        .map(|w| {
+            let full = strsim::normalized_damerau_levenshtein(wanted, &normalize(&w.name));
+            let file = w
+                .path
+                .file_stem()
+                .map(|f| {
+                    strsim::normalized_damerau_levenshtein(wanted, &normalize(&f.to_string_lossy()))
+                })
+                .unwrap_or(0.0);
+            (full.max(file), w)
+        })
+        .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+        .collect();
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, w)| w)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn wallpapers(names: &[&str]) -> Vec<Wallpaper> {
+        names
+            .iter()
+            .map(|name| Wallpaper {
+                path: PathBuf::from("/wallpapers").join(name),
+                name: name.to_string(),
+                category: name.split_once('/').map(|(c, _)| c.to_string()),
+            })
+            .collect()
+    }
+
+    /// The outcome as a comparable value: its kind and the names it carries
+    fn outcome(resolved: Resolved) -> (&'static str, Vec<String>) {
+        let names = |ws: Vec<&Wallpaper>| ws.iter().map(|w| w.name.clone()).collect();
+        match resolved {
+            Resolved::Found(w) => ("found", vec![w.name.clone()]),
+            Resolved::Ambiguous(ws) => ("ambiguous", names(ws)),
+            Resolved::NotFound(ws) => ("not found", names(ws)),
+        }
+    }
+
+    fn check(library: &[Wallpaper], cases: &[(&str, &str, &[&str])]) {
+        for &(name, kind, expected) in cases {
+            assert_eq!(
+                outcome(resolve(library, name)),
+                (kind, expected.iter().map(|s| s.to_string()).collect()),
+                "resolving {name:?}"
+            );
+        }
+    }
+
+    const LIBRARY: &[&str] = &[
+        "City/night.jpg",
+        "City/sunset.png",
+        "Nature/sunrise-2.png",
+        "Nature/sunrise.png",
+        "Nature/sunset.jpg",
+        "Space/nebula.jpg",
+        "abstract.png",
+    ];
+
+    #[test]
+    fn exact_names_win_in_any_case_and_separator() {
+        check(
+            &wallpapers(LIBRARY),
+            &[
+                ("Nature/sunset.jpg", "found", &["Nature/sunset.jpg"]),
+                ("nature\\SUNSET.JPG", "found", &["Nature/sunset.jpg"]),
+                ("  abstract.png ", "found", &["abstract.png"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn file_names_match_with_or_without_extension() {
+        check(
+            &wallpapers(LIBRARY),
+            &[
+                ("night", "found", &["City/night.jpg"]),
+                ("night.jpg", "found", &["City/night.jpg"]),
+                // Also a substring of sunrise-2, but the file name step comes first
+                ("sunrise", "found", &["Nature/sunrise.png"]),
+                (
+                    "sunset",
+                    "ambiguous",
+                    &["City/sunset.png", "Nature/sunset.jpg"],
+                ),
+                // The extension tells them apart
+                ("sunset.png", "found", &["City/sunset.png"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn substrings_match_when_no_file_name_does() {
+        check(
+            &wallpapers(LIBRARY),
+            &[
+                ("neb", "found", &["Space/nebula.jpg"]),
+                ("sunrise-", "found", &["Nature/sunrise-2.png"]),
+                ("space/", "found", &["Space/nebula.jpg"]),
+                (
+                    "nature/sun",
+                    "ambiguous",
+                    &[
+                        "Nature/sunrise-2.png",
+                        "Nature/sunrise.png",
+                        "Nature/sunset.jpg",
+                    ],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn fuzzy_matches_are_the_last_resort() {
+        check(
+            &wallpapers(LIBRARY),
+            &[
+                ("nbl", "found", &["Space/nebula.jpg"]),
+                ("cty ngt", "found", &["City/night.jpg"]),
+            ],
+        );
+    }
+
+    #[test]
+    fn fuzzy_matches_are_ranked_by_score_then_name() {
+        // moon-light scores higher: its letters are closer together and it is shorter
+        check(
+            &wallpapers(&["Art/mountain-lake.png", "Zoo/moon-light.png"]),
+            &[(
+                "ml",
+                "ambiguous",
+                &["Zoo/moon-light.png", "Art/mountain-lake.png"],
+            )],
+        );
+        // Equal scores fall back to the name
+        check(
+            &wallpapers(&["Nature/sunset.jpg", "City/sunset.png"]),
+            &[(
+                "snst",
+                "ambiguous",
+                &["City/sunset.png", "Nature/sunset.jpg"],
+            )],
+        );
+        assert_eq!(
+            fuzzy_score("snst", "city/sunset.png"),
+            fuzzy_score("snst", "nature/sunset.jpg")
+        );
+    }
+
+    #[test]
+    fn ambiguous_matches_are_capped() {
+        let names: Vec<String> = (0..15).map(|i| format!("sky-{i:02}.png")).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let library = wallpapers(&names);
+
+        match resolve(&library, "sky") {
+            Resolved::Ambiguous(ws) => {
+                assert_eq!(ws.len(), MAX_CANDIDATES);
+                assert_eq!(ws[0].name, "sky-00.png");
+            }
+            other => panic!("expected ambiguous, got {:?}", outcome(other)),
+        }
+    }
+
+    #[test]
+    fn nothing_matching_suggests_close_names() {
+        check(
+            &wallpapers(LIBRARY),
+            &[
+                // At most three, most similar first
+                (
+                    "sunsett",
+                    "not found",
+                    &[
+                        "City/sunset.png",
+                        "Nature/sunset.jpg",
+                        "Nature/sunrise-2.png",
+                    ],
+                ),
+                ("zzzzzz", "not found", &[]),
+            ],
+        );
+    }
+}
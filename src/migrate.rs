@@ -0,0 +1,157 @@
+use anyhow::Result;
+use toml::{Table, Value};
+
+/// Version of the config schema this build reads and writes
+pub const CURRENT_VERSION: u32 = 1;
+
+/// One step of the upgrade chain, from version `from` to the next one
+struct Migration {
+    from: u32,
+    /// Change the table in place, describing each change made
+    apply: fn(&mut Table, &mut Vec<String>),
+}
+
+/// Every step from the first schema on, oldest first
+///
+/// A step is added whenever a setting is renamed, removed or changes
+/// meaning, together with a bump of `CURRENT_VERSION`. Steps are never
+/// edited once released, since files at every version may still be around.
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    apply: drop_written_defaults,
+}];
+
+/// Bring a config table up to the current schema, returning what changed
+///
+/// Tables without a `version` predate versioning and start at version 0.
+/// Tables from a newer wcapp are refused rather than guessed at.
+pub fn migrate(table: &mut Table) -> Result<Vec<String>> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(Value::Integer(version)) => u32::try_from(*version)
+            .map_err(|_| anyhow::anyhow!("Invalid config version {}", version))?,
+        Some(other) => anyhow::bail!("Invalid config version {}", other),
+    };
+    if version > CURRENT_VERSION {
+        anyhow::bail!(
+            "The config file is for a newer wcapp (version {}, this one reads up to {}). Update wcapp with 'wcapp update'",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    if version == CURRENT_VERSION {
+        return Ok(Vec::new());
+    }
+
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        (migration.apply)(table, &mut changes);
+    }
+
+    // The version goes first, so that it is the first thing a reader sees
+    let mut upgraded = Table::new();
+    upgraded.insert(
+        "version".to_string(),
+        Value::Integer(CURRENT_VERSION.into()),
+    );
+    upgraded.extend(
+        std::mem::take(table)
+            .into_iter()
+            .filter(|(k, _)| k != "version"),
+    );
+    *table = upgraded;
+    changes.push(format!(
+        "Set version from {} to {}",
+        version, CURRENT_VERSION
+    ));
+    Ok(changes)
+}
+
+/// Version 0 to 1: settings are layered now, so the `cycle_interval = 300`
+/// older versions wrote into every file would hide the system-wide file
+///
+/// Version 0 files only ever held `wallpaper_dir` and `cycle_interval`.
+/// The old default is spelled out, since this step must not change when the
+/// current default does.
+fn drop_written_defaults(table: &mut Table, changes: &mut Vec<String>) {
+    if table.get("cycle_interval") == Some(&Value::Integer(300)) {
+        table.remove("cycle_interval");
+        changes.push("Removed 'cycle_interval = 300', which is the default".to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::path::Path;
+
+    /// A file as written by `save_config` before versioning
+    const BASELINE: &str = "wallpaper_dir = \"/home/me/Pictures/wcapp\"\ncycle_interval = 300\n";
+
+    fn upgrade(content: &str) -> (Table, Vec<String>) {
+        let mut table = content.parse::<Table>().unwrap();
+        let changes = migrate(&mut table).unwrap();
+        (table, changes)
+    }
+
+    #[test]
+    fn baseline_file_round_trips() {
+        let (table, changes) = upgrade(BASELINE);
+        assert_eq!(
+            changes,
+            [
+                "Removed 'cycle_interval = 300', which is the default",
+                "Set version from 0 to 1",
+            ]
+        );
+
+        let written = toml::to_string(&table).unwrap();
+        assert_eq!(
+            written,
+            "version = 1\nwallpaper_dir = \"/home/me/Pictures/wcapp\"\n"
+        );
+
+        let config: Config = toml::from_str(&written).unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.wallpaper_dir, Path::new("/home/me/Pictures/wcapp"));
+        assert_eq!(config.cycle_interval, 300);
+
+        let serialized = toml::to_string(&config).unwrap();
+        let reread: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(toml::to_string(&reread).unwrap(), serialized);
+        assert_eq!(reread.wallpaper_dir, config.wallpaper_dir);
+        assert_eq!(reread.cycle_interval, config.cycle_interval);
+    }
+
+    #[test]
+    fn upgraded_file_is_left_alone() {
+        let (mut table, _) = upgrade(BASELINE);
+        let before = table.clone();
+        assert!(migrate(&mut table).unwrap().is_empty());
+        assert_eq!(table, before);
+    }
+
+    #[test]
+    fn changed_interval_is_kept() {
+        let (table, changes) =
+            upgrade("wallpaper_dir = \"/home/me/Pictures/wcapp\"\ncycle_interval = 600\n");
+        assert_eq!(changes, ["Set version from 0 to 1"]);
+        assert_eq!(
+            toml::to_string(&table).unwrap(),
+            "version = 1\nwallpaper_dir = \"/home/me/Pictures/wcapp\"\ncycle_interval = 600\n"
+        );
+
+        let config: Config = table.try_into().unwrap();
+        assert_eq!(config.cycle_interval, 600);
+    }
+
+    #[test]
+    fn newer_file_is_refused() {
+        let mut table = format!("version = {}\n", CURRENT_VERSION + 1)
+            .parse::<Table>()
+            .unwrap();
+        let error = migrate(&mut table).unwrap_err().to_string();
+        assert!(error.contains("newer wcapp"), "{}", error);
+    }
+}
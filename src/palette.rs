@@ -0,0 +1,433 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::library::Wallpaper;
+use crate::render::{self, Color};
+
+/// Longest side of the copy colours are sampled from, in pixels
+const SAMPLE_SIZE: u32 = 128;
+/// How many dominant colours to look for
+const DOMINANT: usize = 8;
+/// Names of the first eight terminal colours, as alacritty calls them
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// A terminal colour scheme derived from a wallpaper
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub background: Color,
+    pub foreground: Color,
+    /// The 16 terminal colours: 0-7 normal, 8-15 bright
+    pub colors: [Color; 16],
+}
+
+/// A file format a palette can be written in
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Xresources,
+    Kitty,
+    Alacritty,
+    Css,
+    Json,
+}
+
+/// A theme file written after every wallpaper change
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ThemeFile {
+    /// Where to write the theme, `~` is expanded
+    pub path: PathBuf,
+    /// Built-in format to write
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+    /// Custom template file, with placeholders like `{background}` and `{color4}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+}
+
+impl Palette {
+    /// Extract a palette from the image at `path`
+    pub fn extract(path: &Path) -> Result<Self> {
+        let image = render::open(path)?
+            .thumbnail(SAMPLE_SIZE, SAMPLE_SIZE)
+            .to_rgb8();
+        let pixels: Vec<[u8; 3]> = image.pixels().map(|p| p.0).collect();
+        if pixels.is_empty() {
+            anyhow::bail!("{} has no pixels", path.display());
+        }
+        Ok(Self::from_dominant(&median_cut(pixels, DOMINANT)))
+    }
+
+    /// Build a readable dark scheme around the dominant colours of an image
+    ///
+    /// The darkest colour becomes the background and the lightest the
+    /// foreground. The rest, ordered by hue, become the six accent colours,
+    /// lightened where needed to stand out against the background.
+    fn from_dominant(dominant: &[[u8; 3]]) -> Self {
+        let mut by_luminance = dominant.to_vec();
+        by_luminance.sort_by(|a, b| luminance(*a).total_cmp(&luminance(*b)));
+
+        let darkest = by_luminance[0];
+        let lightest = by_luminance[by_luminance.len() - 1];
+        let background = mix(darkest, [0, 0, 0], 0.7);
+        let foreground = mix(lightest, [255, 255, 255], 0.75);
+
+        // Prefer colourful colours for the accents, repeating them if there are few
+        let mut accents: Vec<[u8; 3]> = dominant.to_vec();
+        accents.sort_by(|a, b| saturation(*b).total_cmp(&saturation(*a)));
+        accents.truncate(6);
+        let mut i = 0;
+        while accents.len() < 6 {
+            accents.push(mix(accents[i], [255, 255, 255], 0.2));
+            i += 1;
+        }
+        accents.sort_by(|a, b| hue(*a).total_cmp(&hue(*b)));
+        let accents: Vec<[u8; 3]> = accents.into_iter().map(|c| lighten_to(c, 0.3)).collect();
+
+        let mut colors = [Color::default(); 16];
+        colors[0] = Color(background);
+        for (slot, accent) in accents.iter().enumerate() {
+            colors[slot + 1] = Color(*accent);
+            colors[slot + 9] = Color(mix(*accent, [255, 255, 255], 0.25));
+        }
+        colors[7] = Color(mix(foreground, background, 0.2));
+        colors[8] = Color(mix(background, foreground, 0.35));
+        colors[15] = Color(foreground);
+
+        Self {
+            background: Color(background),
+            foreground: Color(foreground),
+            colors,
+        }
+    }
+
+    /// Write the palette in `format`, noting which wallpaper it came from
+    pub fn format(&self, format: Format, wallpaper: &Path) -> String {
+        let mut out = String::new();
+        match format {
+            Format::Xresources => {
+                let _ = writeln!(out, "! Generated by wcapp from {}", wallpaper.display());
+                let _ = writeln!(out, "*.background: {}", self.background);
+                let _ = writeln!(out, "*.foreground: {}", self.foreground);
+                let _ = writeln!(out, "*.cursorColor: {}", self.foreground);
+                for (i, color) in self.colors.iter().enumerate() {
+                    let _ = writeln!(out, "*.color{}: {}", i, color);
+                }
+            }
+            Format::Kitty => {
+                let _ = writeln!(out, "# Generated by wcapp from {}", wallpaper.display());
+                let _ = writeln!(out, "background {}", self.background);
+                let _ = writeln!(out, "foreground {}", self.foreground);
+                let _ = writeln!(out, "cursor {}", self.foreground);
+                for (i, color) in self.colors.iter().enumerate() {
+                    let _ = writeln!(out, "color{} {}", i, color);
+                }
+            }
+            Format::Alacritty => {
+                let _ = writeln!(out, "# Generated by wcapp from {}", wallpaper.display());
+                let _ = writeln!(out, "[colors.primary]");
+                let _ = writeln!(out, "background = \"{}\"", self.background);
+                let _ = writeln!(out, "foreground = \"{}\"", self.foreground);
+                for (table, colors) in
+                    [("normal", &self.colors[..8]), ("bright", &self.colors[8..])]
+                {
+                    let _ = writeln!(out, "\n[colors.{}]", table);
+                    for (name, color) in COLOR_NAMES.iter().zip(colors) {
+                        let _ = writeln!(out, "{} = \"{}\"", name, color);
+                    }
+                }
+            }
+            Format::Css => {
+                let _ = writeln!(out, "/* Generated by wcapp from {} */", wallpaper.display());
+                let _ = writeln!(out, ":root {{");
+                let _ = writeln!(out, "  --background: {};", self.background);
+                let _ = writeln!(out, "  --foreground: {};", self.foreground);
+                for (i, color) in self.colors.iter().enumerate() {
+                    let _ = writeln!(out, "  --color{}: {};", i, color);
+                }
+                let _ = writeln!(out, "}}");
+            }
+            Format::Json => {
+                let colors: serde_json::Map<String, serde_json::Value> = self
+                    .colors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, color)| (format!("color{}", i), json!(color.to_string())))
+                    .collect();
+                let value = json!({
+                    "wallpaper": wallpaper,
+                    "special": {
+                        "background": self.background.to_string(),
+                        "foreground": self.foreground.to_string(),
+                        "cursor": self.foreground.to_string(),
+                    },
+                    "colors": colors,
+                });
+                out = serde_json::to_string_pretty(&value).unwrap_or_default();
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Fill in the placeholders of a custom template
+    ///
+    /// Supports `{background}`, `{foreground}`, `{cursor}`, `{color0}` to
+    /// `{color15}` and `{wallpaper}`.
+    pub fn fill(&self, template: &str, wallpaper: &Path) -> String {
+        let mut out = template
+            .replace("{background}", &self.background.to_string())
+            .replace("{foreground}", &self.foreground.to_string())
+            .replace("{cursor}", &self.foreground.to_string())
+            .replace("{wallpaper}", &wallpaper.display().to_string());
+        for (i, color) in self.colors.iter().enumerate() {
+            out = out.replace(&format!("{{color{}}}", i), &color.to_string());
+        }
+        out
+    }
+}
+
+impl ThemeFile {
+    /// Render the theme for `palette` and write it to its path
+    pub fn write(&self, palette: &Palette, wallpaper: &Path) -> Result<PathBuf> {
+        let content = match (&self.template, self.format) {
+            (Some(template), _) => {
+                let template = expand_home(template);
+                let text = fs::read_to_string(&template)
+                    .with_context(|| format!("Failed to read {}", template.display()))?;
+                palette.fill(&text, wallpaper)
+            }
+            (None, Some(format)) => palette.format(format, wallpaper),
+            (None, None) => {
+                anyhow::bail!("Theme {} needs a format or a template", self.path.display())
+            }
+        };
+
+        let path = expand_home(&self.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Write the configured theme files for a wallpaper that was just set
+///
+/// Failures are reported but never stop the wallpaper change.
+pub fn write_themes(config: Option<&Config>, wallpaper: &Wallpaper) {
+    let Some(themes) = config.map(|c| &c.themes).filter(|t| !t.is_empty()) else {
+        return;
+    };
+
+    let palette = match Palette::extract(&wallpaper.path) {
+        Ok(palette) => palette,
+        Err(e) => {
+            eprintln!("Failed to extract palette: {:#}", e);
+            return;
+        }
+    };
+
+    for theme in themes {
+        if let Err(e) = theme.write(&palette, &wallpaper.path) {
+            eprintln!("Failed to write theme: {:#}", e);
+        }
+    }
+}
+
+/// Replace a leading `~` with the home directory
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Split the pixels into `count` boxes of similar colour and average each
+///
+/// Boxes are split at the median of their widest channel until there are
+/// enough of them. The result is ordered from most to least common.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![pixels];
+
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest_channel(b)))
+            .max_by_key(|(_, (_, range))| *range);
+        let Some((index, (channel, range))) = widest else {
+            break;
+        };
+        if range == 0 {
+            break;
+        }
+
+        let mut pixels = boxes.swap_remove(index);
+        pixels.sort_unstable_by_key(|p| p[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|b| std::cmp::Reverse(b.len()));
+    boxes.iter().map(|b| average(b)).collect()
+}
+
+/// The channel with the largest spread of values, and that spread
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = pixels.iter().map(|p| p[channel]).min().unwrap_or(0);
+            let max = pixels.iter().map(|p| p[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn average(pixels: &[[u8; 3]]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    for pixel in pixels {
+        for channel in 0..3 {
+            sum[channel] += u64::from(pixel[channel]);
+        }
+    }
+    let count = pixels.len().max(1) as u64;
+    sum.map(|s| (s / count) as u8)
+}
+
+/// Blend `a` towards `b` by `amount`, from 0 to 1
+fn mix(a: [u8; 3], b: [u8; 3], amount: f32) -> [u8; 3] {
+    let mut out = [0; 3];
+    for channel in 0..3 {
+        let value = f32::from(a[channel]) * (1.0 - amount) + f32::from(b[channel]) * amount;
+        out[channel] = value.round() as u8;
+    }
+    out
+}
+
+/// Perceived brightness, from 0 to 1
+fn luminance(c: [u8; 3]) -> f32 {
+    (0.2126 * f32::from(c[0]) + 0.7152 * f32::from(c[1]) + 0.0722 * f32::from(c[2])) / 255.0
+}
+
+/// Lighten `c` until its luminance is at least `min`
+fn lighten_to(c: [u8; 3], min: f32) -> [u8; 3] {
+    let mut color = c;
+    while luminance(color) < min {
+        color = mix(color, [255, 255, 255], 0.1);
+    }
+    color
+}
+
+fn saturation(c: [u8; 3]) -> f32 {
+    let max = c.iter().copied().max().unwrap_or(0);
+    let min = c.iter().copied().min().unwrap_or(0);
+    if max == 0 {
+        0.0
+    } else {
+        f32::from(max - min) / f32::from(max)
+    }
+}
+
+/// Hue in degrees, from 0 to 360
+fn hue(c: [u8; 3]) -> f32 {
+    let [r, g, b] = c.map(|v| f32::from(v) / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if delta == 0.0 {
+        return 0.0;
+    }
+
+    let hue = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    hue.rem_euclid(360.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [200, 10, 10];
+    const BLUE: [u8; 3] = [10, 10, 200];
+
+    fn pixels(counts: &[([u8; 3], usize)]) -> Vec<[u8; 3]> {
+        counts
+            .iter()
+            .flat_map(|&(color, count)| std::iter::repeat_n(color, count))
+            .collect()
+    }
+
+    #[test]
+    fn boxes_are_split_at_the_median() {
+        let pixels = pixels(&[(RED, 6), (BLUE, 2)]);
+
+        // Half of the pixels on each side, so one box still mixes both colours
+        assert_eq!(median_cut(pixels.clone(), 2), [RED, [105, 10, 105]]);
+        // Then the mixed box is split, and the largest box comes first
+        assert_eq!(median_cut(pixels.clone(), 3), [RED, RED, BLUE]);
+        // Boxes of one colour are never split
+        assert_eq!(median_cut(pixels, 8), [RED, RED, BLUE]);
+    }
+
+    #[test]
+    fn single_colour_images_get_a_full_palette() {
+        let color = [40, 80, 120];
+        let dominant = median_cut(vec![color; 100], DOMINANT);
+        assert_eq!(dominant, [color]);
+
+        let palette = Palette::from_dominant(&dominant);
+        assert_eq!(palette.background, Color([12, 24, 36]));
+        assert_eq!(palette.foreground, Color([201, 211, 221]));
+        assert_eq!(palette.colors[0], palette.background);
+        assert_eq!(palette.colors[15], palette.foreground);
+
+        // The one colour is lightened step by step into six distinct accents
+        let mut accents: Vec<[u8; 3]> = palette.colors[1..7].iter().map(|c| c.0).collect();
+        assert!(accents.iter().all(|&c| luminance(c) >= 0.3));
+        accents.sort_unstable();
+        accents.dedup();
+        assert_eq!(accents.len(), 6);
+        for slot in 1..7 {
+            let bright = mix(palette.colors[slot].0, [255, 255, 255], 0.25);
+            assert_eq!(palette.colors[slot + 8], Color(bright));
+        }
+    }
+
+    #[test]
+    fn templates_are_filled_in() {
+        let palette = Palette {
+            background: Color([0x10, 0x10, 0x10]),
+            foreground: Color([0xf0, 0xf0, 0xf0]),
+            colors: std::array::from_fn(|i| Color([i as u8; 3])),
+        };
+        let template = "bg={background} fg={foreground} cursor={cursor}\n\
+                        {color1} {color15} {color4}\n\
+                        from {wallpaper} {unknown}";
+
+        assert_eq!(
+            palette.fill(
+                template,
+                Path::new("/home/me/Pictures/wcapp/Nature/lake.jpg")
+            ),
+            "bg=#101010 fg=#f0f0f0 cursor=#f0f0f0\n\
+             #010101 #0f0f0f #040404\n\
+             from /home/me/Pictures/wcapp/Nature/lake.jpg {unknown}"
+        );
+    }
+}
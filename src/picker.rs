@@ -0,0 +1,548 @@
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, BufRead, Write};
+
+use crate::library::Wallpaper;
+use crate::matching;
+
+/// How many rows PageUp/PageDown move
+const PAGE_SIZE: usize = 10;
+
+/// Input understood by the picker, independent of the terminal backend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    Esc,
+    Backspace,
+    Char(char),
+}
+
+/// What the caller should do after a key press
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Continue,
+    /// Index into the wallpapers the picker was created with
+    Selected(usize),
+    Cancelled,
+}
+
+/// A line of the picker's list
+#[derive(Debug, Clone, PartialEq)]
+pub enum Row<'a> {
+    Category(&'a str),
+    /// Wallpaper name and whether it is under the cursor
+    Wallpaper(&'a str, bool),
+}
+
+/// Incremental-search list of wallpapers grouped by category
+///
+/// The picker only tracks state; feed it keys with [`Picker::handle`] and draw
+/// [`Picker::rows`], so it can be driven by a terminal or by scripted input.
+pub struct Picker<'a> {
+    wallpapers: &'a [Wallpaper],
+    query: String,
+    /// Indices of wallpapers matching the query, grouped by category
+    matches: Vec<usize>,
+    cursor: usize,
+}
+
+impl<'a> Picker<'a> {
+    pub fn new(wallpapers: &'a [Wallpaper]) -> Self {
+        let mut picker = Self {
+            wallpapers,
+            query: String::new(),
+            matches: Vec::new(),
+            cursor: 0,
+        };
+        picker.refresh();
+        picker
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Index of the wallpaper under the cursor, if anything matches
+    pub fn current(&self) -> Option<usize> {
+        self.matches.get(self.cursor).copied()
+    }
+
+    pub fn handle(&mut self, key: Key) -> Outcome {
+        match key {
+            Key::Up => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down => self.move_down(1),
+            Key::PageUp => self.cursor = self.cursor.saturating_sub(PAGE_SIZE),
+            Key::PageDown => self.move_down(PAGE_SIZE),
+            Key::Enter => {
+                return match self.current() {
+                    Some(index) => Outcome::Selected(index),
+                    None => Outcome::Continue,
+                };
+            }
+            Key::Esc => return Outcome::Cancelled,
+            Key::Backspace => {
+                if self.query.pop().is_some() {
+                    self.refresh();
+                }
+            }
+            Key::Char(c) => {
+                self.query.push(c);
+                self.refresh();
+            }
+        }
+        Outcome::Continue
+    }
+
+    /// Feed a sequence of keys, stopping at the first selection or cancellation
+    pub fn run_keys(&mut self, keys: impl IntoIterator<Item = Key>) -> Outcome {
+        for key in keys {
+            let outcome = self.handle(key);
+            if outcome != Outcome::Continue {
+                return outcome;
+            }
+        }
+        Outcome::Continue
+    }
+
+    /// Up to `height` rows around the cursor, with category headers
+    pub fn rows(&self, height: usize) -> Vec<Row<'a>> {
+        let mut rows = Vec::new();
+        let mut cursor_row = 0;
+        // Nested, since uncategorized wallpapers need a header too
+        let mut last_category: Option<Option<&str>> = None;
+
+        for (i, &index) in self.matches.iter().enumerate() {
+            let wallpaper = &self.wallpapers[index];
+            let category = wallpaper.category.as_deref();
+            if last_category != Some(category) {
+                rows.push(Row::Category(category.unwrap_or("Uncategorized")));
+                last_category = Some(category);
+            }
+            if i == self.cursor {
+                cursor_row = rows.len();
+            }
+            rows.push(Row::Wallpaper(&wallpaper.name, i == self.cursor));
+        }
+
+        if rows.len() <= height {
+            return rows;
+        }
+
+        let start = cursor_row
+            .saturating_sub(height / 2)
+            .min(rows.len() - height);
+        rows.drain(start..start + height).collect()
+    }
+
+    fn move_down(&mut self, by: usize) {
+        self.cursor = (self.cursor + by).min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Recompute matches after the query changed and move back to the top
+    ///
+    /// Substring matches win; fuzzy matches are only used when there are none.
+    fn refresh(&mut self) {
+        let query = self.query.to_lowercase();
+        let names: Vec<String> = self
+            .wallpapers
+            .iter()
+            .map(|w| w.name.to_lowercase())
+            .collect();
+
+        self.matches = (0..names.len())
+            .filter(|&i| names[i].contains(&query))
+            .collect();
+        if self.matches.is_empty() {
+            self.matches = (0..names.len())
+                .filter(|&i| matching::fuzzy_score(&query, &names[i]).is_some())
+                .collect();
+        }
+
+        self.matches.sort_by(|&a, &b| {
+            self.wallpapers[a]
+                .category
+                .cmp(&self.wallpapers[b].category)
+        });
+        self.cursor = 0;
+    }
+}
+
+/// Let the user pick a wallpaper, interactively on a terminal or from a
+/// numbered list otherwise. Returns `None` if the user cancelled.
+pub fn pick(wallpapers: &[Wallpaper]) -> Result<Option<usize>> {
+    use std::io::IsTerminal;
+
+    if io::stdout().is_terminal() && io::stdin().is_terminal() {
+        pick_interactive(wallpapers)
+    } else {
+        let stdin = io::stdin();
+        pick_numbered(wallpapers, &mut stdin.lock(), &mut io::stdout())
+    }
+}
+
+/// Full-screen incremental search on the terminal
+fn pick_interactive(wallpapers: &[Wallpaper]) -> Result<Option<usize>> {
+    let mut picker = Picker::new(wallpapers);
+    let _guard = RawTerminal::enter()?;
+    let mut stdout = io::stdout();
+
+    loop {
+        draw(&mut stdout, &picker)?;
+
+        let Event::Key(key) = event::read().context("Failed to read terminal input")? else {
+            continue;
+        };
+        let Some(key) = map_key(key) else {
+            continue;
+        };
+
+        match picker.handle(key) {
+            Outcome::Continue => {}
+            Outcome::Selected(index) => return Ok(Some(index)),
+            Outcome::Cancelled => return Ok(None),
+        }
+    }
+}
+
+fn map_key(key: KeyEvent) -> Option<Key> {
+    if key.kind != KeyEventKind::Press {
+        return None;
+    }
+
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Esc),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::PageUp => Some(Key::PageUp),
+        KeyCode::PageDown => Some(Key::PageDown),
+        KeyCode::Enter => Some(Key::Enter),
+        KeyCode::Esc => Some(Key::Esc),
+        KeyCode::Backspace => Some(Key::Backspace),
+        KeyCode::Char(c) => Some(Key::Char(c)),
+        _ => None,
+    }
+}
+
+fn draw(stdout: &mut impl Write, picker: &Picker) -> Result<()> {
+    let height = terminal::size()
+        .ok()
+        .map(|(_, height)| height)
+        .filter(|&height| height > 0)
+        .unwrap_or(24);
+    let list_height = (height as usize).saturating_sub(4).max(1);
+
+    queue!(
+        stdout,
+        cursor::MoveTo(0, 0),
+        terminal::Clear(terminal::ClearType::All),
+        Print("Pick a wallpaper (type to search, ↑/↓ to move, Enter to apply, Esc to cancel)\r\n"),
+        Print(format!("> {}\r\n\r\n", picker.query())),
+    )?;
+
+    for row in picker.rows(list_height) {
+        match row {
+            Row::Category(category) => queue!(
+                stdout,
+                SetAttribute(Attribute::Bold),
+                Print(format!("[{}]\r\n", category)),
+                SetAttribute(Attribute::Reset),
+            )?,
+            Row::Wallpaper(name, true) => queue!(
+                stdout,
+                SetAttribute(Attribute::Reverse),
+                Print(format!("▸ {}", name)),
+                SetAttribute(Attribute::Reset),
+                Print("\r\n"),
+            )?,
+            Row::Wallpaper(name, false) => queue!(stdout, Print(format!("  {}\r\n", name)))?,
+        }
+    }
+
+    queue!(
+        stdout,
+        cursor::MoveTo(0, height.saturating_sub(1)),
+        Print(format!(
+            "{}/{} wallpapers",
+            picker.match_count(),
+            picker.wallpapers.len()
+        )),
+    )?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Raw mode on the alternate screen, restored when dropped
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode().context("Failed to enable raw terminal mode")?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Print a numbered list and read the choice from `input`
+///
+/// Anything that is not a number narrows the list down like a search.
+/// An empty line or end of input cancels.
+pub fn pick_numbered(
+    wallpapers: &[Wallpaper],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Option<usize>> {
+    let mut picker = Picker::new(wallpapers);
+
+    loop {
+        let mut last_category = None;
+        for (number, &index) in picker.matches.iter().enumerate() {
+            let wallpaper = &wallpapers[index];
+            let category = wallpaper.category.as_deref();
+            if last_category != Some(category) {
+                last_category = Some(category);
+                writeln!(output, "[{}]", category.unwrap_or("Uncategorized"))?;
+            }
+            writeln!(output, "  {:>3}. {}", number + 1, wallpaper.name)?;
+        }
+        writeln!(output)?;
+        write!(
+            output,
+            "Enter a number, text to search, or nothing to cancel: "
+        )?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        if let Ok(number) = line.parse::<usize>() {
+            match picker.matches.get(number.wrapping_sub(1)) {
+                Some(&index) => return Ok(Some(index)),
+                None => {
+                    writeln!(output, "No wallpaper number {}", number)?;
+                    continue;
+                }
+            }
+        }
+
+        picker.query.clear();
+        picker.run_keys(line.chars().map(Key::Char));
+
+        if picker.match_count() == 1 {
+            return Ok(picker.current());
+        }
+        if picker.match_count() == 0 {
+            writeln!(output, "Nothing matches '{}'", line)?;
+            picker.query.clear();
+            picker.refresh();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    /// Shown as plain.jpg, then City, then Nature
+    fn wallpapers() -> Vec<Wallpaper> {
+        [
+            "Nature/forest.jpg",
+            "City/night.png",
+            "Nature/sunset.jpg",
+            "plain.jpg",
+        ]
+        .iter()
+        .map(|name| Wallpaper {
+            path: PathBuf::from("/wallpapers").join(name),
+            name: name.to_string(),
+            category: name.split_once('/').map(|(c, _)| c.to_string()),
+        })
+        .collect()
+    }
+
+    fn keys(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    /// Run `pick_numbered` on scripted input, returning the choice and output
+    fn numbered(input: &str) -> (Option<usize>, String) {
+        let mut output = Vec::new();
+        let choice = pick_numbered(&wallpapers(), &mut Cursor::new(input), &mut output).unwrap();
+        (choice, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn rows_are_grouped_by_category() {
+        let wallpapers = wallpapers();
+        let picker = Picker::new(&wallpapers);
+        assert_eq!(
+            picker.rows(10),
+            [
+                Row::Category("Uncategorized"),
+                Row::Wallpaper("plain.jpg", true),
+                Row::Category("City"),
+                Row::Wallpaper("City/night.png", false),
+                Row::Category("Nature"),
+                Row::Wallpaper("Nature/forest.jpg", false),
+                Row::Wallpaper("Nature/sunset.jpg", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_keep_the_cursor_in_view() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+        picker.run_keys([Key::PageDown]);
+        assert_eq!(
+            picker.rows(3),
+            [
+                Row::Category("Nature"),
+                Row::Wallpaper("Nature/forest.jpg", false),
+                Row::Wallpaper("Nature/sunset.jpg", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn typing_filters_and_enter_selects() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+        let mut script = keys("SUN");
+        script.push(Key::Enter);
+        assert_eq!(picker.run_keys(script), Outcome::Selected(2));
+        assert_eq!(picker.query(), "SUN");
+    }
+
+    #[test]
+    fn fuzzy_matches_are_used_when_nothing_contains_the_query() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+        picker.run_keys(keys("ntfrst"));
+        assert_eq!(picker.match_count(), 1);
+        assert_eq!(picker.current(), Some(0));
+    }
+
+    #[test]
+    fn moving_stays_within_the_matches() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+
+        assert_eq!(picker.run_keys([Key::Up, Key::Enter]), Outcome::Selected(3));
+        assert_eq!(
+            picker.run_keys([Key::Down, Key::Down, Key::Enter]),
+            Outcome::Selected(0)
+        );
+        assert_eq!(
+            picker.run_keys([Key::PageDown, Key::Down, Key::Enter]),
+            Outcome::Selected(2)
+        );
+        assert_eq!(
+            picker.run_keys([Key::PageUp, Key::Enter]),
+            Outcome::Selected(3)
+        );
+    }
+
+    #[test]
+    fn typing_moves_back_to_the_top() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+        let mut script = vec![Key::Down, Key::Down];
+        script.extend(keys("nature"));
+        script.push(Key::Enter);
+        assert_eq!(picker.run_keys(script), Outcome::Selected(0));
+    }
+
+    #[test]
+    fn backspace_widens_the_search_again() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+
+        assert_eq!(picker.run_keys(keys("sunx")), Outcome::Continue);
+        assert_eq!(picker.match_count(), 0);
+        assert_eq!(picker.handle(Key::Enter), Outcome::Continue);
+
+        picker.run_keys([Key::Backspace]);
+        assert_eq!(picker.match_count(), 1);
+        picker.run_keys([
+            Key::Backspace,
+            Key::Backspace,
+            Key::Backspace,
+            Key::Backspace,
+        ]);
+        assert_eq!(picker.match_count(), wallpapers.len());
+    }
+
+    #[test]
+    fn escape_cancels_and_stops_reading_keys() {
+        let wallpapers = wallpapers();
+        let mut picker = Picker::new(&wallpapers);
+        assert_eq!(
+            picker.run_keys([Key::Char('x'), Key::Esc, Key::Enter]),
+            Outcome::Cancelled
+        );
+    }
+
+    #[test]
+    fn numbered_list_picks_by_number() {
+        let (choice, output) = numbered("2\n");
+        assert_eq!(choice, Some(1));
+        assert!(output.starts_with(
+            "[Uncategorized]\n    1. plain.jpg\n[City]\n    2. City/night.png\n[Nature]\n"
+        ));
+    }
+
+    #[test]
+    fn numbered_list_asks_again_when_out_of_range() {
+        let (choice, output) = numbered("9\n0\n4\n");
+        assert_eq!(choice, Some(2));
+        assert!(output.contains("No wallpaper number 9\n"));
+        assert!(output.contains("No wallpaper number 0\n"));
+    }
+
+    #[test]
+    fn numbered_list_cancels_on_empty_line_or_end_of_input() {
+        assert_eq!(numbered("\n2\n").0, None);
+        assert_eq!(numbered("").0, None);
+        assert_eq!(numbered("9\n").0, None);
+    }
+
+    #[test]
+    fn numbered_list_searches_text() {
+        assert_eq!(numbered("sunset\n").0, Some(2));
+
+        // Numbers count within the narrowed list
+        let (choice, output) = numbered("nature\n2\n");
+        assert_eq!(choice, Some(2));
+        assert!(output.contains("[Nature]\n    1. Nature/forest.jpg\n    2. Nature/sunset.jpg\n"));
+
+        let (choice, output) = numbered("zzz\n1\n");
+        assert_eq!(choice, Some(3));
+        assert!(output.contains("Nothing matches 'zzz'\n"));
+    }
+}
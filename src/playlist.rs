@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::config;
+use crate::effects::Effects;
+use crate::library::{Filter, Library, Wallpaper};
+use crate::query::Query;
+
+/// A named wallpaper collection, either a static ordered list or a saved query
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Playlist {
+    /// Saved query; when set, the playlist contains every matching wallpaper
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Pick randomly instead of playing in order
+    #[serde(default)]
+    pub shuffle: bool,
+    /// Effects for wallpapers set from the playlist, e.g. "blur=8,darken=0.3"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects: Option<String>,
+    /// Static entries, in playback order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wallpapers: Vec<PlaylistEntry>,
+}
+
+/// A wallpaper in a static playlist, keyed by content hash so it survives
+/// renames and can be shared with other machines
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaylistEntry {
+    pub hash: String,
+    pub name: String,
+}
+
+impl Playlist {
+    /// Parse the saved query, if any
+    pub fn parsed_query(&self) -> Result<Option<Query>> {
+        self.query
+            .as_deref()
+            .map(Query::from_str)
+            .transpose()
+            .context("Invalid playlist query")
+    }
+
+    /// Parse the saved effects, if any
+    pub fn parsed_effects(&self) -> Result<Option<Effects>> {
+        self.effects
+            .as_deref()
+            .map(Effects::from_str)
+            .transpose()
+            .context("Invalid playlist effects")
+    }
+
+    /// Wallpapers currently in the playlist, in playback order
+    ///
+    /// Banned wallpapers are left out. Static entries whose file is gone are skipped.
+    pub fn resolve(&self, library: &mut Library) -> Result<Vec<Wallpaper>> {
+        if let Some(query) = self.parsed_query()? {
+            return library.select(&Filter {
+                skip_banned: true,
+                query: Some(query),
+                ..Filter::default()
+            });
+        }
+
+        let mut by_hash: Option<HashMap<String, Wallpaper>> = None;
+        let mut wallpapers = Vec::new();
+
+        for entry in &self.wallpapers {
+            // Fast path: the file is still where it was when it was added
+            if let Some(wallpaper) = library.find(&entry.name).cloned()
+                && library.hash(&wallpaper)? == entry.hash
+            {
+                wallpapers.push(wallpaper);
+                continue;
+            }
+
+            if by_hash.is_none() {
+                let mut map = HashMap::new();
+                for wallpaper in library.wallpapers.clone() {
+                    map.insert(library.hash(&wallpaper)?, wallpaper);
+                }
+                by_hash = Some(map);
+            }
+
+            if let Some(wallpaper) = by_hash.as_ref().and_then(|m| m.get(&entry.hash)) {
+                wallpapers.push(wallpaper.clone());
+            }
+        }
+
+        library.select_from(
+            wallpapers,
+            &Filter {
+                skip_banned: true,
+                ..Filter::default()
+            },
+        )
+    }
+}
+
+/// Get the directory holding playlist files, creating it if needed
+pub fn get_playlists_dir() -> Result<PathBuf> {
+    let dir = config::locate(config::get_data_dir()?, "playlists")?;
+    fs::create_dir_all(&dir).context("Failed to create playlists directory")?;
+    Ok(dir)
+}
+
+/// Get the path to a playlist file
+pub fn get_playlist_path(name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+    Ok(get_playlists_dir()?.join(format!("{}.toml", name)))
+}
+
+/// Playlist names become file names, so keep them simple
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+    if !valid {
+        anyhow::bail!(
+            "Invalid playlist name '{}' (use letters, digits, '-' and '_')",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Parse a playlist from TOML, validating its query
+pub fn parse_playlist(content: &str) -> Result<Playlist> {
+    let playlist: Playlist = toml::from_str(content).context("Failed to parse playlist")?;
+    playlist.parsed_query()?;
+    playlist.parsed_effects()?;
+    Ok(playlist)
+}
+
+/// Load a playlist by name
+pub fn load_playlist(name: &str) -> Result<Playlist> {
+    let path = get_playlist_path(name)?;
+    if !path.exists() {
+        anyhow::bail!(
+            "Playlist '{}' not found. Use 'playlist create {}' to create it.",
+            name,
+            name
+        );
+    }
+
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_playlist(&content).with_context(|| format!("Invalid playlist {}", path.display()))
+}
+
+/// Save a playlist by name
+pub fn save_playlist(name: &str, playlist: &Playlist) -> Result<()> {
+    let path = get_playlist_path(name)?;
+    let content = toml::to_string(playlist).context("Failed to serialize playlist")?;
+    fs::write(path, content).context("Failed to write playlist file")?;
+    Ok(())
+}
+
+/// Names of all saved playlists, sorted
+pub fn list_playlists() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(get_playlists_dir()?).context("Failed to read playlists directory")? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml")
+            && let Some(stem) = path.file_stem()
+        {
+            names.push(stem.to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
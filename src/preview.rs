@@ -0,0 +1,461 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::{ImageFormat, RgbImage};
+use std::env;
+use std::fmt::Write;
+use std::io::{self, Cursor, IsTerminal};
+use std::path::Path;
+
+use crate::render;
+
+/// Cell size assumed when the terminal does not report its size in pixels
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+/// Kitty accepts image data in chunks of at most this many bytes
+const KITTY_CHUNK_SIZE: usize = 4096;
+/// Size of inline thumbnails, in cells
+const THUMBNAIL_SIZE: (u16, u16) = (32, 8);
+/// Inline thumbnails are indented to line up with list entries
+const THUMBNAIL_INDENT: &str = "    ";
+
+/// How a preview is drawn on the terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// Kitty graphics protocol (kitty, Ghostty, Konsole)
+    Kitty,
+    /// Inline images as introduced by iTerm2 (iTerm2, WezTerm)
+    Iterm2,
+    /// DEC sixel graphics (foot, mlterm, xterm with sixel support)
+    Sixel,
+    /// Coloured "▀" characters, works on any true-colour terminal
+    HalfBlock,
+}
+
+impl Protocol {
+    /// Guess the best protocol from the environment
+    ///
+    /// `WCAPP_PREVIEW` (kitty, iterm2, sixel or halfblock) overrides the guess
+    /// for terminals that are not recognised.
+    pub fn detect() -> Self {
+        let var = |name: &str| env::var(name).unwrap_or_default();
+
+        match var("WCAPP_PREVIEW").to_lowercase().as_str() {
+            "kitty" => return Self::Kitty,
+            "iterm2" => return Self::Iterm2,
+            "sixel" => return Self::Sixel,
+            "halfblock" => return Self::HalfBlock,
+            _ => {}
+        }
+
+        let term = var("TERM");
+        let term_program = var("TERM_PROGRAM");
+
+        if term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || env::var_os("KITTY_WINDOW_ID").is_some()
+            || env::var_os("KONSOLE_VERSION").is_some()
+        {
+            Self::Kitty
+        } else if term_program == "iTerm.app"
+            || term_program == "WezTerm"
+            || var("LC_TERMINAL") == "iTerm2"
+        {
+            Self::Iterm2
+        } else if term.contains("sixel") || term.starts_with("foot") || term == "mlterm" {
+            Self::Sixel
+        } else {
+            Self::HalfBlock
+        }
+    }
+
+    /// Escape sequence that removes previously drawn images, if they are not
+    /// simply overwritten by text
+    pub fn clear_sequence(self) -> &'static str {
+        match self {
+            Self::Kitty => "\x1b_Ga=d,q=2\x1b\\",
+            _ => "",
+        }
+    }
+}
+
+/// An image rendered for the terminal
+pub enum Preview {
+    /// Escape sequence to print with the cursor at the top-left corner,
+    /// and the number of cells it covers
+    Graphic { data: String, cols: u16, rows: u16 },
+    /// Colours of the upper and lower half of each cell, row by row
+    HalfBlocks(Vec<Vec<([u8; 3], [u8; 3])>>),
+}
+
+impl Preview {
+    /// Text that draws the preview at the cursor and ends on a new line
+    fn to_inline(&self, indent: &str) -> String {
+        match self {
+            Self::Graphic { data, .. } => format!("{}{}\n", indent, data),
+            Self::HalfBlocks(cells) => {
+                let mut text = String::new();
+                for row in cells {
+                    text.push_str(indent);
+                    let mut last = None;
+                    for &(top, bottom) in row {
+                        // Only switch colours when they change to keep the output small
+                        if last != Some((top, bottom)) {
+                            let _ = write!(
+                                text,
+                                "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                                top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                            );
+                            last = Some((top, bottom));
+                        }
+                        text.push('▀');
+                    }
+                    text.push_str("\x1b[0m\n");
+                }
+                text
+            }
+        }
+    }
+}
+
+/// Print a small thumbnail of an image below the current line
+///
+/// Graphics protocols are only used on a terminal; when the output is piped,
+/// half blocks are printed so `less -R` can still show them.
+pub fn print_thumbnail(path: &Path) {
+    let protocol = if io::stdout().is_terminal() {
+        Protocol::detect()
+    } else {
+        Protocol::HalfBlock
+    };
+
+    let (cols, rows) = THUMBNAIL_SIZE;
+    match render(path, protocol, cols, rows) {
+        Ok(preview) => print!("{}", preview.to_inline(THUMBNAIL_INDENT)),
+        Err(_) => println!("{}(no preview available)", THUMBNAIL_INDENT),
+    }
+}
+
+/// Size of a terminal cell in pixels
+pub fn cell_size() -> (u32, u32) {
+    crossterm::terminal::window_size()
+        .ok()
+        .filter(|size| size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0)
+        .map(|size| {
+            (
+                (size.width / size.columns) as u32,
+                (size.height / size.rows) as u32,
+            )
+        })
+        .unwrap_or(DEFAULT_CELL_SIZE)
+}
+
+/// Render the image at `path` to fit in `cols` x `rows` cells, keeping its aspect ratio
+pub fn render(path: &Path, protocol: Protocol, cols: u16, rows: u16) -> Result<Preview> {
+    let image = render::open(path)?;
+
+    if protocol == Protocol::HalfBlock {
+        let image = image.thumbnail(cols as u32, rows as u32 * 2).to_rgb8();
+        return Ok(Preview::HalfBlocks(half_blocks(&image)));
+    }
+
+    let (cell_width, cell_height) = cell_size();
+    let image = image
+        .thumbnail(cols as u32 * cell_width, rows as u32 * cell_height)
+        .to_rgb8();
+    let cols = image.width().div_ceil(cell_width).min(cols as u32) as u16;
+    let rows = image.height().div_ceil(cell_height).min(rows as u32) as u16;
+
+    let data = match protocol {
+        Protocol::Kitty => kitty(&image, cols, rows)?,
+        Protocol::Iterm2 => iterm2(&image, cols, rows)?,
+        Protocol::Sixel => sixel(&image),
+        Protocol::HalfBlock => unreachable!("half blocks are handled above"),
+    };
+    Ok(Preview::Graphic { data, cols, rows })
+}
+
+fn encode_png(image: &RgbImage) -> Result<Vec<u8>> {
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .context("Failed to encode preview")?;
+    Ok(png.into_inner())
+}
+
+/// Kitty graphics: base64 PNG sent in chunks, scaled to `cols` x `rows` cells
+fn kitty(image: &RgbImage, cols: u16, rows: u16) -> Result<String> {
+    let encoded = BASE64.encode(encode_png(image)?);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+
+    let mut data = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).expect("base64 is ASCII");
+        if i == 0 {
+            write!(
+                data,
+                "\x1b_Ga=T,f=100,q=2,c={},r={},m={};",
+                cols, rows, more
+            )?;
+        } else {
+            write!(data, "\x1b_Gm={};", more)?;
+        }
+        write!(data, "{}\x1b\\", chunk)?;
+    }
+    Ok(data)
+}
+
+/// iTerm2 inline image: a base64 PNG in a single OSC 1337 sequence
+fn iterm2(image: &RgbImage, cols: u16, rows: u16) -> Result<String> {
+    let png = encode_png(image)?;
+    Ok(format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        cols,
+        rows,
+        BASE64.encode(&png)
+    ))
+}
+
+/// Sixel graphics using a fixed 6x6x6 colour cube
+fn sixel(image: &RgbImage) -> String {
+    let (width, height) = image.dimensions();
+    let level = |c: u8| (c as u32 * 5 + 127) / 255;
+    let colour = |x: u32, y: u32| {
+        let [r, g, b] = image.get_pixel(x, y).0;
+        (level(r) * 36 + level(g) * 6 + level(b)) as usize
+    };
+
+    let mut data = format!("\x1bPq\"1;1;{};{}", width, height);
+    for index in 0..216 {
+        let percent = |l: usize| l * 100 / 5;
+        let _ = write!(
+            data,
+            "#{};2;{};{};{}",
+            index,
+            percent(index / 36),
+            percent(index / 6 % 6),
+            percent(index % 6)
+        );
+    }
+
+    for top in (0..height).step_by(6) {
+        let band = top..(top + 6).min(height);
+
+        let mut used = [false; 216];
+        for y in band.clone() {
+            for x in 0..width {
+                used[colour(x, y)] = true;
+            }
+        }
+
+        for index in (0..216).filter(|&i| used[i]) {
+            let _ = write!(data, "#{}", index);
+
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..width {
+                let bits = band
+                    .clone()
+                    .filter(|&y| colour(x, y) == index)
+                    .fold(0u8, |bits, y| bits | 1 << (y - top));
+                let c = char::from(0x3f + bits);
+
+                run = match run {
+                    Some((last, count)) if last == c => Some((c, count + 1)),
+                    Some((last, count)) => {
+                        push_run(&mut data, last, count);
+                        Some((c, 1))
+                    }
+                    None => Some((c, 1)),
+                };
+            }
+            if let Some((last, count)) = run {
+                push_run(&mut data, last, count);
+            }
+            data.push('$');
+        }
+        data.push('-');
+    }
+
+    data.push_str("\x1b\\");
+    data
+}
+
+fn push_run(data: &mut String, c: char, count: usize) {
+    if count > 3 {
+        let _ = write!(data, "!{}{}", count, c);
+    } else {
+        data.extend(std::iter::repeat_n(c, count));
+    }
+}
+
+/// Pair up pixel rows into the colours of "▀" cells
+fn half_blocks(image: &RgbImage) -> Vec<Vec<([u8; 3], [u8; 3])>> {
+    let (width, height) = image.dimensions();
+    (0..height)
+        .step_by(2)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let top = image.get_pixel(x, y).0;
+                    let bottom = if y + 1 < height {
+                        image.get_pixel(x, y + 1).0
+                    } else {
+                        top
+                    };
+                    (top, bottom)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    /// An image of pseudo-random pixels, which PNG cannot compress much
+    fn noise(width: u32, height: u32) -> RgbImage {
+        let mut state = 1u32;
+        RgbImage::from_fn(width, height, |_, _| {
+            let mut next = || {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            };
+            Rgb([next(), next(), next()])
+        })
+    }
+
+    /// The image in a base64 PNG payload
+    fn decode(payload: &str) -> RgbImage {
+        let png = BASE64.decode(payload).unwrap();
+        image::load_from_memory(&png).unwrap().to_rgb8()
+    }
+
+    #[test]
+    fn kitty_sends_small_images_in_one_chunk() {
+        let image = RgbImage::from_pixel(4, 4, Rgb(RED));
+        let data = kitty(&image, 3, 2).unwrap();
+
+        let payload = data
+            .strip_prefix("\x1b_Ga=T,f=100,q=2,c=3,r=2,m=0;")
+            .and_then(|rest| rest.strip_suffix("\x1b\\"))
+            .unwrap();
+        assert_eq!(decode(payload), image);
+    }
+
+    #[test]
+    fn kitty_splits_large_images_into_chunks() {
+        let image = noise(64, 64);
+        let data = kitty(&image, 10, 5).unwrap();
+
+        let commands: Vec<&str> = data
+            .strip_suffix("\x1b\\")
+            .unwrap()
+            .split("\x1b\\")
+            .collect();
+        assert!(commands.len() > 2, "expected several chunks");
+
+        let mut payload = String::new();
+        for (i, command) in commands.iter().enumerate() {
+            let last = i + 1 == commands.len();
+            let header = match (i, last) {
+                (0, _) => "\x1b_Ga=T,f=100,q=2,c=10,r=5,m=1;",
+                (_, false) => "\x1b_Gm=1;",
+                (_, true) => "\x1b_Gm=0;",
+            };
+            let chunk = command.strip_prefix(header).unwrap();
+            if last {
+                assert!(!chunk.is_empty() && chunk.len() <= KITTY_CHUNK_SIZE);
+            } else {
+                assert_eq!(chunk.len(), KITTY_CHUNK_SIZE);
+            }
+            payload.push_str(chunk);
+        }
+        assert_eq!(decode(&payload), image);
+    }
+
+    #[test]
+    fn iterm2_sends_the_png_in_one_sequence() {
+        let image = noise(5, 3);
+        let data = iterm2(&image, 2, 1).unwrap();
+        let png_size = encode_png(&image).unwrap().len();
+
+        let header = format!(
+            "\x1b]1337;File=inline=1;size={};width=2;height=1;preserveAspectRatio=1:",
+            png_size
+        );
+        let payload = data
+            .strip_prefix(header.as_str())
+            .and_then(|rest| rest.strip_suffix('\x07'))
+            .unwrap();
+        assert_eq!(decode(payload), image);
+    }
+
+    #[test]
+    fn sixel_defines_the_colour_cube() {
+        let data = sixel(&RgbImage::from_pixel(1, 1, Rgb(RED)));
+
+        assert!(data.starts_with("\x1bPq\"1;1;1;1#0;2;0;0;0#1;2;0;0;20#"));
+        assert!(data.contains("#180;2;100;0;0#"));
+        assert!(data.contains("#215;2;100;100;100"));
+        assert!(data.ends_with("\x1b\\"));
+    }
+
+    #[test]
+    fn sixel_draws_bands_of_six_rows() {
+        // Seven rows: a full band of red over blue, then one row of blue
+        let image = RgbImage::from_fn(5, 7, |_, y| Rgb(if y < 3 { RED } else { BLUE }));
+        let data = sixel(&image);
+        let bands = data
+            .strip_suffix("\x1b\\")
+            .and_then(|rest| rest.split_once("#215;2;100;100;100"))
+            .map(|(_, bands)| bands)
+            .unwrap();
+
+        // Blue (5) covers the bottom three bits and red (180) the top three,
+        // five columns wide; the last band only has blue in its top row
+        assert_eq!(bands, "#5!5w$#180!5F$-#5!5@$-");
+    }
+
+    #[test]
+    fn sixel_writes_out_short_runs() {
+        let image = RgbImage::from_pixel(3, 6, Rgb(RED));
+        assert!(sixel(&image).ends_with("#180~~~$-\x1b\\"));
+    }
+
+    #[test]
+    fn half_blocks_pair_rows() {
+        let image = RgbImage::from_fn(2, 4, |x, y| Rgb([x as u8, y as u8, 0]));
+        let cells = half_blocks(&image);
+
+        assert_eq!(
+            cells,
+            [
+                [([0, 0, 0], [0, 1, 0]), ([1, 0, 0], [1, 1, 0])],
+                [([0, 2, 0], [0, 3, 0]), ([1, 2, 0], [1, 3, 0])],
+            ]
+        );
+    }
+
+    #[test]
+    fn half_blocks_repeat_the_last_row_of_odd_images() {
+        let image = RgbImage::from_fn(1, 3, |_, y| Rgb([y as u8, 0, 0]));
+        let cells = half_blocks(&image);
+
+        assert_eq!(cells, [[([0, 0, 0], [1, 0, 0])], [([2, 0, 0], [2, 0, 0])]]);
+    }
+
+    #[test]
+    fn half_blocks_only_switch_colours_when_they_change() {
+        let preview = Preview::HalfBlocks(vec![vec![(RED, BLUE), (RED, BLUE), (BLUE, RED)]]);
+        assert_eq!(
+            preview.to_inline("  "),
+            "  \x1b[38;2;255;0;0;48;2;0;0;255m▀▀\x1b[38;2;0;0;255;48;2;255;0;0m▀\x1b[0m\n"
+        );
+    }
+}
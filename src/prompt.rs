@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+
+/// A way of stopping that scripts can tell apart by the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// The user answered no at a prompt
+    Cancelled,
+    /// A prompt was needed, but stdin is not a terminal
+    NotInteractive,
+    /// Part of what was asked for could not be done
+    Incomplete,
+}
+
+impl Exit {
+    /// Process exit code; 1 is any other error and 2 a usage error
+    pub fn code(self) -> i32 {
+        match self {
+            Exit::Cancelled => 3,
+            Exit::NotInteractive => 4,
+            Exit::Incomplete => 5,
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::Cancelled => write!(f, "Operation cancelled"),
+            Exit::NotInteractive => write!(
+                f,
+                "Refusing to prompt, since stdin is not a terminal. Pass --yes to go ahead without asking"
+            ),
+            Exit::Incomplete => write!(f, "Some of the requested changes were not made"),
+        }
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// Ask a question and read one line of answer
+///
+/// Fails with `Exit::NotInteractive` when stdin is not a terminal, rather
+/// than reading whatever a script happens to pipe in, and with
+/// `Exit::Cancelled` at end of input.
+pub fn ask(question: &str) -> Result<String> {
+    if !io::stdin().is_terminal() {
+        return Err(Exit::NotInteractive.into());
+    }
+
+    print!("{}", question);
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    let read = io::stdin()
+        .read_line(&mut input)
+        .context("Failed to read user input")?;
+    if read == 0 {
+        println!();
+        return Err(Exit::Cancelled.into());
+    }
+    Ok(input.trim().to_string())
+}
+
+/// Ask a yes/no question that defaults to no
+pub fn confirm(question: &str) -> Result<bool> {
+    let answer = ask(&format!("{} (y/N): ", question))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,499 @@
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::library::Wallpaper;
+use crate::state::{self, WallpaperState};
+
+/// A parsed wallpaper query such as `tag:dark and not category:Anime and width>=2560`
+///
+/// Supported terms:
+/// - `tag:<tag>`, `category:<name>`, `name:<text>` (substring)
+/// - `is:favorite`, `is:banned`, `is:rated`
+/// - `width`, `height` and `rating` compared with `=`, `!=`, `<`, `<=`, `>`, `>=`
+///
+/// Terms combine with `and`, `or`, `not` and parentheses. Text matching is
+/// case-insensitive and values containing spaces can be quoted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Tag(String),
+    Category(String),
+    Name(String),
+    Favorite,
+    Banned,
+    Rated,
+    Compare(Field, Op, u32),
+}
+
+/// Numeric attributes that can be compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Width,
+    Height,
+    Rating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Everything a query can look at for one wallpaper
+pub struct Subject<'a> {
+    pub wallpaper: &'a Wallpaper,
+    pub state: Option<&'a WallpaperState>,
+    pub dimensions: Option<(u32, u32)>,
+}
+
+impl Query {
+    /// Whether evaluating the query needs the image dimensions
+    pub fn needs_dimensions(&self) -> bool {
+        match self {
+            Query::And(a, b) | Query::Or(a, b) => a.needs_dimensions() || b.needs_dimensions(),
+            Query::Not(q) => q.needs_dimensions(),
+            Query::Compare(field, _, _) => matches!(field, Field::Width | Field::Height),
+            _ => false,
+        }
+    }
+
+    pub fn matches(&self, subject: &Subject) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(subject) && b.matches(subject),
+            Query::Or(a, b) => a.matches(subject) || b.matches(subject),
+            Query::Not(q) => !q.matches(subject),
+            // The query's tag is normalized when parsed; stored tags may have
+            // been edited by hand
+            Query::Tag(tag) => subject
+                .state
+                .is_some_and(|s| s.tags.iter().any(|t| t.to_lowercase() == *tag)),
+            Query::Category(category) => subject
+                .wallpaper
+                .category
+                .as_ref()
+                .is_some_and(|c| c.eq_ignore_ascii_case(category)),
+            Query::Name(text) => subject
+                .wallpaper
+                .name
+                .to_lowercase()
+                .contains(&text.to_lowercase()),
+            Query::Favorite => subject.state.is_some_and(|s| s.favorite),
+            Query::Banned => subject.state.is_some_and(|s| s.banned),
+            Query::Rated => subject.state.is_some_and(|s| s.rating.is_some()),
+            Query::Compare(field, op, value) => {
+                let actual = match field {
+                    Field::Width => subject.dimensions.map(|(w, _)| w),
+                    Field::Height => subject.dimensions.map(|(_, h)| h),
+                    Field::Rating => subject.state.and_then(|s| s.rating).map(u32::from),
+                };
+                actual.is_some_and(|actual| op.apply(actual, *value))
+            }
+        }
+    }
+}
+
+impl Op {
+    fn apply(self, left: u32, right: u32) -> bool {
+        match self {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        };
+        f.write_str(symbol)
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Field::Width => "width",
+            Field::Height => "height",
+            Field::Rating => "rating",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.parse_or()?;
+
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("Unexpected '{}' in query", token);
+        }
+
+        Ok(query)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+            Token::Word(word) => f.write_str(word),
+        }
+    }
+}
+
+/// Split a query into parentheses and words; quoted sections stay within one word
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+
+                    if c == '"' {
+                        loop {
+                            match chars.next() {
+                                Some('"') => break,
+                                Some(c) => word.push(c),
+                                None => anyhow::bail!("Unterminated quote in query"),
+                            }
+                        }
+                    } else {
+                        word.push(c);
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut query = self.parse_and()?;
+        while self.eat_keyword("or") {
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut query = self.parse_unary()?;
+        while self.eat_keyword("and") {
+            query = Query::And(Box::new(query), Box::new(self.parse_unary()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query> {
+        if self.eat_keyword("not") {
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+
+        match self.next() {
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => anyhow::bail!("Missing ')' in query"),
+                }
+            }
+            Some(Token::Close) => anyhow::bail!("Unexpected ')' in query"),
+            Some(Token::Word(word)) => parse_term(&word),
+            None => anyhow::bail!("Query ended unexpectedly"),
+        }
+    }
+}
+
+/// Fields compared by text, written as `field:value`
+const TEXT_FIELDS: [&str; 4] = ["tag", "category", "name", "is"];
+
+/// Parse a single `field:value` or `field<op>number` term
+///
+/// A known text field before the first `:` wins, so that values such as
+/// `name:a=b` may contain comparison symbols.
+fn parse_term(word: &str) -> Result<Query> {
+    if let Some((field, value)) = word.split_once(':')
+        && TEXT_FIELDS.contains(&field.to_lowercase().as_str())
+    {
+        return parse_text_term(field, value);
+    }
+
+    for (symbol, op) in [
+        (">=", Op::Ge),
+        ("<=", Op::Le),
+        ("!=", Op::Ne),
+        (">", Op::Gt),
+        ("<", Op::Lt),
+        ("=", Op::Eq),
+    ] {
+        if let Some((field, value)) = word.split_once(symbol) {
+            let field = match field.to_lowercase().as_str() {
+                "width" => Field::Width,
+                "height" => Field::Height,
+                "rating" => Field::Rating,
+                other => anyhow::bail!(
+                    "Unknown field '{}' in query (expected width, height or rating)",
+                    other
+                ),
+            };
+            let value = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Expected a number after '{}{}'", field, op))?;
+            return Ok(Query::Compare(field, op, value));
+        }
+    }
+
+    match word.split_once(':') {
+        Some((field, _)) => anyhow::bail!(
+            "Unknown field '{}' in query (expected tag, category, name or is)",
+            field.to_lowercase()
+        ),
+        None => anyhow::bail!(
+            "Invalid query term '{}' (expected e.g. tag:dark, category:Nature or width>=1920)",
+            word
+        ),
+    }
+}
+
+/// Parse the value of a `field:value` term for one of `TEXT_FIELDS`
+fn parse_text_term(field: &str, value: &str) -> Result<Query> {
+    if value.is_empty() {
+        anyhow::bail!("Missing value after '{}:' in query", field);
+    }
+
+    match field.to_lowercase().as_str() {
+        "tag" => Ok(Query::Tag(state::normalize_tag(value)?)),
+        "category" => Ok(Query::Category(value.to_string())),
+        "name" => Ok(Query::Name(value.to_string())),
+        _ => match value.to_lowercase().as_str() {
+            "favorite" | "fav" => Ok(Query::Favorite),
+            "banned" => Ok(Query::Banned),
+            "rated" => Ok(Query::Rated),
+            other => anyhow::bail!(
+                "Unknown 'is:{}' in query (expected favorite, banned or rated)",
+                other
+            ),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Query {
+        input.parse().unwrap()
+    }
+
+    fn error(input: &str) -> String {
+        input.parse::<Query>().unwrap_err().to_string()
+    }
+
+    fn tag(tag: &str) -> Box<Query> {
+        Box::new(Query::Tag(tag.to_string()))
+    }
+
+    #[test]
+    fn terms() {
+        assert_eq!(parse("tag:dark"), Query::Tag("dark".to_string()));
+        assert_eq!(
+            parse("Category:Nature"),
+            Query::Category("Nature".to_string())
+        );
+        assert_eq!(
+            parse("name:\"blue sky\""),
+            Query::Name("blue sky".to_string())
+        );
+        assert_eq!(parse("is:fav"), Query::Favorite);
+        assert_eq!(parse("IS:Banned"), Query::Banned);
+        assert_eq!(parse("is:rated"), Query::Rated);
+        assert_eq!(
+            parse("width>=2560"),
+            Query::Compare(Field::Width, Op::Ge, 2560)
+        );
+        assert_eq!(
+            parse("height<1080"),
+            Query::Compare(Field::Height, Op::Lt, 1080)
+        );
+        assert_eq!(parse("rating!=3"), Query::Compare(Field::Rating, Op::Ne, 3));
+        assert_eq!(parse("rating=5"), Query::Compare(Field::Rating, Op::Eq, 5));
+    }
+
+    #[test]
+    fn text_values_may_contain_comparison_symbols() {
+        assert_eq!(parse("name:a=b"), Query::Name("a=b".to_string()));
+        assert_eq!(parse("tag:X<Y"), Query::Tag("x<y".to_string()));
+        assert_eq!(parse("category:>=1"), Query::Category(">=1".to_string()));
+        assert_eq!(parse("tag:a:b"), Query::Tag("a:b".to_string()));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("tag:a or tag:b and tag:c"),
+            Query::Or(tag("a"), Box::new(Query::And(tag("b"), tag("c"))))
+        );
+        assert_eq!(
+            parse("tag:a and tag:b or tag:c"),
+            Query::Or(Box::new(Query::And(tag("a"), tag("b"))), tag("c"))
+        );
+    }
+
+    #[test]
+    fn operators_associate_to_the_left() {
+        assert_eq!(
+            parse("tag:a and tag:b and tag:c"),
+            Query::And(Box::new(Query::And(tag("a"), tag("b"))), tag("c"))
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        assert_eq!(
+            parse("not tag:a and tag:b"),
+            Query::And(Box::new(Query::Not(tag("a"))), tag("b"))
+        );
+        assert_eq!(
+            parse("NOT not tag:a"),
+            Query::Not(Box::new(Query::Not(tag("a"))))
+        );
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            parse("(tag:a or tag:b) and tag:c"),
+            Query::And(Box::new(Query::Or(tag("a"), tag("b"))), tag("c"))
+        );
+        assert_eq!(
+            parse("not (tag:a or tag:b)"),
+            Query::Not(Box::new(Query::Or(tag("a"), tag("b"))))
+        );
+        assert_eq!(parse("((tag:a))"), *tag("a"));
+    }
+
+    #[test]
+    fn errors_explain_the_problem() {
+        assert_eq!(
+            error("color:red"),
+            "Unknown field 'color' in query (expected tag, category, name or is)"
+        );
+        assert_eq!(
+            error("size>3"),
+            "Unknown field 'size' in query (expected width, height or rating)"
+        );
+        assert_eq!(error("width>=big"), "Expected a number after 'width>='");
+        assert_eq!(error("tag:"), "Missing value after 'tag:' in query");
+        assert_eq!(
+            error("is:new"),
+            "Unknown 'is:new' in query (expected favorite, banned or rated)"
+        );
+        assert_eq!(
+            error("dark"),
+            "Invalid query term 'dark' (expected e.g. tag:dark, category:Nature or width>=1920)"
+        );
+        assert_eq!(error("(tag:a"), "Missing ')' in query");
+        assert_eq!(error(")"), "Unexpected ')' in query");
+        assert_eq!(error("tag:a)"), "Unexpected ')' in query");
+        assert_eq!(error("tag:a and"), "Query ended unexpectedly");
+        assert_eq!(error(""), "Query ended unexpectedly");
+        assert_eq!(error("tag:a tag:b"), "Unexpected 'tag:b' in query");
+        assert_eq!(error("name:\"open"), "Unterminated quote in query");
+        assert_eq!(error("tag:\" \""), "Tags cannot be empty");
+    }
+
+    #[test]
+    fn tags_match_in_any_case() {
+        let wallpaper = Wallpaper {
+            path: "/wallpapers/a.jpg".into(),
+            name: "a.jpg".to_string(),
+            category: None,
+        };
+        let state = WallpaperState {
+            tags: ["été".to_string(), "Legacy".to_string()].into(),
+            ..WallpaperState::default()
+        };
+        let subject = Subject {
+            wallpaper: &wallpaper,
+            state: Some(&state),
+            dimensions: None,
+        };
+
+        assert!(parse("tag:ÉTÉ").matches(&subject));
+        assert!(parse("tag:legacy").matches(&subject));
+        assert!(!parse("tag:ete").matches(&subject));
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use crate::config;
+use crate::history::History;
+use crate::library::{Library, Wallpaper};
+use crate::state::State;
+
+/// Rating unrated wallpapers count as when deciding what to evict
+const NEUTRAL_RATING: u8 = 3;
+
+/// Names of wallpapers evicted to keep the library under `max_library_size`
+///
+/// `fetch` only brings these back when there is room to spare, so that
+/// they do not push out the wallpapers that replaced them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Evicted {
+    #[serde(default)]
+    pub names: BTreeSet<String>,
+}
+
+/// Get the path to the list of evicted wallpapers
+pub fn get_evicted_path() -> Result<PathBuf> {
+    Ok(config::get_state_dir()?.join("evicted.json"))
+}
+
+/// Load the list of evicted wallpapers, starting fresh if it does not exist yet
+pub fn load_evicted() -> Result<Evicted> {
+    let evicted_path = get_evicted_path()?;
+    if !evicted_path.exists() {
+        return Ok(Evicted::default());
+    }
+
+    let content = fs::read_to_string(&evicted_path)
+        .with_context(|| format!("Failed to read {}", evicted_path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", evicted_path.display()))
+}
+
+/// Save the list of evicted wallpapers
+pub fn save_evicted(evicted: &Evicted) -> Result<()> {
+    let evicted_path = get_evicted_path()?;
+    let content = serde_json::to_string(evicted).context("Failed to serialize evicted list")?;
+    fs::write(evicted_path, content).context("Failed to write evicted list")?;
+    Ok(())
+}
+
+/// A wallpaper competing for room in the library
+struct Candidate {
+    size: u64,
+    /// Favorites and files `fetch` cannot bring back always stay
+    pinned: bool,
+    banned: bool,
+    rating: u8,
+    /// Unix time it was last shown, or added to the library if it never was
+    last_used: i64,
+    /// Position in the library, or in the incoming files if not fetched yet
+    source: Source,
+}
+
+#[derive(Clone, Copy)]
+enum Source {
+    Present(usize),
+    Incoming(usize),
+}
+
+impl Candidate {
+    /// Banned first, then lowest rated, then least recently used
+    fn eviction_order(&self, other: &Self) -> Ordering {
+        other
+            .banned
+            .cmp(&self.banned)
+            .then(self.rating.cmp(&other.rating))
+            .then(self.last_used.cmp(&other.last_used))
+    }
+}
+
+/// What `fetch` does to keep the library under its size limit
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Indices into the incoming files of those to fetch
+    pub fetch: Vec<usize>,
+    /// Wallpapers to remove from the library
+    pub evict: Vec<Wallpaper>,
+    /// Size of the library afterwards
+    pub total: u64,
+}
+
+/// A wallpaper in the library, with what eviction needs to know about it
+struct Stored<'a> {
+    wallpaper: &'a Wallpaper,
+    hash: String,
+    size: u64,
+    /// Unix time it was added to the library
+    added: i64,
+}
+
+/// Decide which of the `incoming` files, given as name and size, to fetch
+/// and which wallpapers to evict so that the library fits in `limit` bytes
+///
+/// Wallpapers go banned first, then lowest rated, then least recently
+/// shown according to `history`, counting those never shown from when they
+/// were added. Favorites are never evicted, and neither are files outside
+/// `refetchable`, since nothing could bring them back. Incoming files that
+/// are in `evicted` count as last used when they were last shown.
+pub fn plan(
+    library: &mut Library,
+    incoming: &[(String, u64)],
+    refetchable: &HashSet<String>,
+    limit: u64,
+    history: &History,
+    evicted: &Evicted,
+) -> Result<Plan> {
+    let wallpapers = library.wallpapers.clone();
+    let mut stored = Vec::new();
+    for wallpaper in &wallpapers {
+        let metadata = fs::metadata(&wallpaper.path)
+            .with_context(|| format!("Failed to read {}", wallpaper.path.display()))?;
+        let added = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        stored.push(Stored {
+            wallpaper,
+            hash: library.hash(wallpaper)?,
+            size: metadata.len(),
+            added,
+        });
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let candidates = candidates(
+        &stored,
+        &library.state,
+        incoming,
+        refetchable,
+        history,
+        evicted,
+        now,
+    );
+    Ok(choose(candidates, &stored, limit))
+}
+
+/// Everything competing for room, stored and incoming, with incoming files
+/// never evicted before counting as used at `now`
+fn candidates(
+    stored: &[Stored],
+    state: &State,
+    incoming: &[(String, u64)],
+    refetchable: &HashSet<String>,
+    history: &History,
+    evicted: &Evicted,
+    now: i64,
+) -> Vec<Candidate> {
+    let last_shown = history.last_shown();
+
+    let mut candidates = Vec::new();
+    for (index, stored) in stored.iter().enumerate() {
+        let entry = state.get(&stored.hash);
+        candidates.push(Candidate {
+            size: stored.size,
+            pinned: entry.is_some_and(|e| e.favorite)
+                || !refetchable.contains(&stored.wallpaper.name),
+            banned: entry.is_some_and(|e| e.banned),
+            rating: entry.and_then(|e| e.rating).unwrap_or(NEUTRAL_RATING),
+            last_used: last_shown
+                .get(stored.hash.as_str())
+                .copied()
+                .unwrap_or(stored.added),
+            source: Source::Present(index),
+        });
+    }
+
+    // Files not fetched yet have no hash, so they are looked up by name
+    let state_by_name: HashMap<&str, _> = state
+        .wallpapers
+        .values()
+        .map(|entry| (entry.name.as_str(), entry))
+        .collect();
+    let shown_by_name: HashMap<&str, i64> = history
+        .entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.shown_at))
+        .collect();
+    for (index, (name, size)) in incoming.iter().enumerate() {
+        let entry = state_by_name.get(name.as_str());
+        let last_used = if evicted.names.contains(name) {
+            shown_by_name.get(name.as_str()).copied().unwrap_or(0)
+        } else {
+            now
+        };
+        candidates.push(Candidate {
+            size: *size,
+            pinned: false,
+            banned: entry.is_some_and(|e| e.banned),
+            rating: entry.and_then(|e| e.rating).unwrap_or(NEUTRAL_RATING),
+            last_used,
+            source: Source::Incoming(index),
+        });
+    }
+    candidates
+}
+
+/// Drop candidates in eviction order until the rest fit in `limit`
+fn choose(candidates: Vec<Candidate>, stored: &[Stored], limit: u64) -> Plan {
+    let mut total: u64 = candidates.iter().map(|c| c.size).sum();
+    let mut evictable: Vec<Candidate> = candidates.into_iter().filter(|c| !c.pinned).collect();
+    evictable.sort_by(Candidate::eviction_order);
+
+    let mut plan = Plan::default();
+    for candidate in evictable {
+        let evict = total > limit;
+        if evict {
+            total -= candidate.size;
+        }
+        match (candidate.source, evict) {
+            (Source::Present(index), true) => plan.evict.push(stored[index].wallpaper.clone()),
+            (Source::Incoming(index), false) => plan.fetch.push(index),
+            _ => {}
+        }
+    }
+    plan.fetch.sort_unstable();
+    plan.total = total;
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryEntry;
+    use crate::state::WallpaperState;
+
+    const SIZE: u64 = 10;
+    const ADDED: i64 = 300;
+    const NOW: i64 = 1_000;
+
+    /// A library of wallpapers `SIZE` bytes each, all added at `ADDED`
+    struct Setup {
+        wallpapers: Vec<Wallpaper>,
+        state: State,
+        history: History,
+        evicted: Evicted,
+        refetchable: HashSet<String>,
+    }
+
+    fn hash(name: &str) -> String {
+        format!("hash-{name}")
+    }
+
+    impl Setup {
+        fn new(names: &[&str]) -> Self {
+            Self {
+                wallpapers: names
+                    .iter()
+                    .map(|name| Wallpaper {
+                        path: PathBuf::from("/wallpapers").join(name),
+                        name: name.to_string(),
+                        category: None,
+                    })
+                    .collect(),
+                state: State::default(),
+                history: History::default(),
+                evicted: Evicted::default(),
+                refetchable: names.iter().map(|name| name.to_string()).collect(),
+            }
+        }
+
+        fn mark(&mut self, name: &str, f: impl FnOnce(&mut WallpaperState)) {
+            self.state.update(&hash(name), name, f);
+        }
+
+        fn show(&mut self, name: &str, shown_at: i64) {
+            self.history.entries.push(HistoryEntry {
+                hash: hash(name),
+                name: name.to_string(),
+                shown_at,
+            });
+        }
+
+        fn plan(&self, incoming: &[&str], limit: u64) -> Plan {
+            let stored: Vec<Stored> = self
+                .wallpapers
+                .iter()
+                .map(|wallpaper| Stored {
+                    wallpaper,
+                    hash: hash(&wallpaper.name),
+                    size: SIZE,
+                    added: ADDED,
+                })
+                .collect();
+            let incoming: Vec<(String, u64)> = incoming
+                .iter()
+                .map(|name| (name.to_string(), SIZE))
+                .collect();
+            let candidates = candidates(
+                &stored,
+                &self.state,
+                &incoming,
+                &self.refetchable,
+                &self.history,
+                &self.evicted,
+                NOW,
+            );
+            choose(candidates, &stored, limit)
+        }
+    }
+
+    fn evicted(plan: &Plan) -> Vec<&str> {
+        plan.evict.iter().map(|w| w.name.as_str()).collect()
+    }
+
+    #[test]
+    fn nothing_goes_when_everything_fits() {
+        let setup = Setup::new(&["a.jpg", "b.jpg"]);
+        let plan = setup.plan(&["c.jpg"], 3 * SIZE);
+        assert!(plan.evict.is_empty());
+        assert_eq!(plan.fetch, [0]);
+        assert_eq!(plan.total, 3 * SIZE);
+    }
+
+    #[test]
+    fn favorites_are_never_evicted() {
+        let mut setup = Setup::new(&["a.jpg", "b.jpg", "c.jpg"]);
+        // Banned and lowest rated too, which would otherwise send it first
+        setup.mark("a.jpg", |s| {
+            s.favorite = true;
+            s.banned = true;
+            s.rating = Some(1);
+        });
+
+        let plan = setup.plan(&[], 0);
+        assert_eq!(evicted(&plan), ["b.jpg", "c.jpg"]);
+        assert_eq!(plan.total, SIZE);
+    }
+
+    #[test]
+    fn files_that_cannot_be_fetched_again_stay() {
+        let mut setup = Setup::new(&["a.jpg", "mine.jpg"]);
+        setup.refetchable.remove("mine.jpg");
+
+        let plan = setup.plan(&[], 0);
+        assert_eq!(evicted(&plan), ["a.jpg"]);
+    }
+
+    #[test]
+    fn banned_go_first_then_lowest_rated() {
+        let mut setup = Setup::new(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        setup.mark("b.jpg", |s| {
+            s.banned = true;
+            s.rating = Some(5);
+        });
+        setup.mark("c.jpg", |s| s.rating = Some(1));
+        setup.mark("d.jpg", |s| s.rating = Some(4));
+
+        assert_eq!(evicted(&setup.plan(&[], 3 * SIZE)), ["b.jpg"]);
+        assert_eq!(evicted(&setup.plan(&[], 2 * SIZE)), ["b.jpg", "c.jpg"]);
+        // Unrated counts as 3, below d's 4
+        assert_eq!(evicted(&setup.plan(&[], SIZE)), ["b.jpg", "c.jpg", "a.jpg"]);
+    }
+
+    #[test]
+    fn least_recently_used_breaks_ties() {
+        let mut setup = Setup::new(&["a.jpg", "b.jpg", "c.jpg"]);
+        setup.show("a.jpg", 100);
+        setup.show("b.jpg", 200);
+        setup.show("a.jpg", 500);
+        // c was never shown, so it counts as used when it was added
+
+        assert_eq!(evicted(&setup.plan(&[], SIZE)), ["b.jpg", "c.jpg"]);
+    }
+
+    #[test]
+    fn new_files_push_out_old_ones() {
+        let mut setup = Setup::new(&["a.jpg", "b.jpg"]);
+        setup.show("a.jpg", 500);
+        setup.show("b.jpg", 600);
+
+        let plan = setup.plan(&["new.jpg"], 2 * SIZE);
+        assert_eq!(evicted(&plan), ["a.jpg"]);
+        assert_eq!(plan.fetch, [0]);
+        assert_eq!(plan.total, 2 * SIZE);
+    }
+
+    #[test]
+    fn evicted_files_only_come_back_when_they_rank_higher() {
+        let mut setup = Setup::new(&["a.jpg", "b.jpg"]);
+        setup.show("old.jpg", 50);
+        setup.show("a.jpg", 500);
+        setup.show("b.jpg", 600);
+        setup.evicted.names.insert("old.jpg".to_string());
+        setup.evicted.names.insert("banned.jpg".to_string());
+        setup.mark("banned.jpg", |s| s.banned = true);
+
+        let plan = setup.plan(&["old.jpg", "banned.jpg"], 2 * SIZE);
+        assert!(plan.evict.is_empty());
+        assert!(plan.fetch.is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageReader, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::config;
+use crate::display::{self, Monitor};
+use crate::effects::Effects;
+use crate::setter::Mode;
+
+/// JPEG quality of rendered wallpapers, from 1 to 100
+const QUALITY: u8 = 92;
+/// How many rendered wallpapers to keep in the cache
+const KEEP: usize = 16;
+/// Filter used when scaling wallpapers
+const FILTER: FilterType = FilterType::Lanczos3;
+
+/// An RGB colour, written as `#rrggbb`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "black" => return Ok(Self([0, 0, 0])),
+            "white" => return Ok(Self([255, 255, 255])),
+            _ => {}
+        }
+
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .with_context(|| format!("Invalid colour '{}', expected e.g. #1e1e2e", s))?;
+
+        match digits[..] {
+            [r, g, b] => Ok(Self([r * 17, g * 17, b * 17])),
+            [r1, r2, g1, g2, b1, b2] => Ok(Self([r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2])),
+            _ => anyhow::bail!("Invalid colour '{}', expected e.g. #1e1e2e", s),
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+/// Get the directory caching rendered wallpapers, creating it if needed
+pub fn get_render_dir() -> Result<PathBuf> {
+    let dir = config::locate(config::get_cache_dir()?, "rendered")?;
+    fs::create_dir_all(&dir).context("Failed to create render directory")?;
+    Ok(dir)
+}
+
+/// Decode the image at `path`, whatever its extension says
+pub fn open(path: &Path) -> Result<DynamicImage> {
+    ImageReader::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .with_guessed_format()
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .decode()
+        .with_context(|| format!("Failed to decode {}", path.display()))
+}
+
+/// Write `image` to `dest` as a JPEG
+///
+/// The image goes to a temporary file first so an interrupted run never
+/// leaves a truncated file behind that would be reused next time.
+pub fn save_jpeg(image: &RgbImage, dest: &Path, quality: u8) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let partial = dest.with_extension("part");
+    let file = File::create(&partial)
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+    JpegEncoder::new_with_quality(BufWriter::new(file), quality)
+        .encode_image(image)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    fs::rename(&partial, dest).with_context(|| format!("Failed to write {}", dest.display()))
+}
+
+/// Render a processed copy of `source` for a screen of `size` pixels
+///
+/// The effects run first, then the result is scaled and positioned by
+/// `fit` when the desktop cannot do that itself. Renders are cached by the
+/// content hash of the source and the parameters, and the path to the
+/// rendered copy is returned. For span mode, `size` is the size of the whole
+/// desktop, so each monitor shows the part of the image that lies under it.
+pub fn render(
+    source: &Path,
+    hash: &str,
+    effects: &Effects,
+    fit: Option<(Mode, Color)>,
+    size: (u32, u32),
+) -> Result<PathBuf> {
+    let fit_key = fit.map_or_else(String::new, |(mode, color)| {
+        format!("{}:{}", mode.as_str(), color)
+    });
+    let params = format!("{}|{}|{}x{}", effects, fit_key, size.0, size.1);
+    let key = format!("{:x}", Sha256::digest(params.as_bytes()));
+
+    let dir = get_render_dir()?;
+    let dest = dir.join(format!("{}-{}.jpg", hash, &key[..16]));
+
+    if !dest.exists() {
+        let mut image = effects.apply(open(source)?, size);
+        if let Some((mode, background)) = fit {
+            image = place(&DynamicImage::ImageRgb8(image), mode, size, background);
+        }
+        save_jpeg(&image, &dest, QUALITY)?;
+    }
+
+    prune(&dir, &dest);
+    Ok(dest)
+}
+
+/// Render one image for the whole desktop with its own wallpaper on each monitor
+///
+/// `parts` holds the path and content hash of the wallpaper for each of the
+/// `monitors`; monitors without one are filled with `background`, as are
+/// gaps between monitors. Every part gets the effects and is scaled to its
+/// monitor by `mode`. The desktop should span the result across all monitors.
+pub fn compose(
+    parts: &[Option<(&Path, &str)>],
+    monitors: &[Monitor],
+    effects: &Effects,
+    mode: Mode,
+    background: Color,
+) -> Result<PathBuf> {
+    let mut params = format!("{}|{}:{}", effects, mode.as_str(), background);
+    for (monitor, part) in monitors.iter().zip(parts) {
+        params.push_str(&format!(
+            "|{}x{}+{}+{}:{}",
+            monitor.width,
+            monitor.height,
+            monitor.x,
+            monitor.y,
+            part.map_or("", |(_, hash)| hash)
+        ));
+    }
+    let key = format!("{:x}", Sha256::digest(params.as_bytes()));
+
+    let dir = get_render_dir()?;
+    let dest = dir.join(format!("desktop-{}.jpg", &key[..16]));
+
+    if !dest.exists() {
+        let canvas = paint(monitors, effects, mode, background, |index| {
+            parts
+                .get(index)
+                .copied()
+                .flatten()
+                .map(|(path, _)| open(path))
+                .transpose()
+        })?;
+        save_jpeg(&canvas, &dest, QUALITY)?;
+    }
+
+    prune(&dir, &dest);
+    Ok(dest)
+}
+
+/// Draw the desktop image for `compose`, with `part` loading the wallpaper
+/// for the monitor at an index, if it has one
+///
+/// Parts are loaded one at a time, so that only one full-size image is held
+/// at once.
+fn paint(
+    monitors: &[Monitor],
+    effects: &Effects,
+    mode: Mode,
+    background: Color,
+    mut part: impl FnMut(usize) -> Result<Option<DynamicImage>>,
+) -> Result<RgbImage> {
+    let (left, top, width, height) = display::bounds(monitors);
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb(background.0));
+    for (index, monitor) in monitors.iter().enumerate() {
+        let Some(image) = part(index)? else {
+            continue;
+        };
+        let size = (monitor.width, monitor.height);
+        let image = effects.apply(image, size);
+        let image = place(&DynamicImage::ImageRgb8(image), mode, size, background);
+        imageops::overlay(
+            &mut canvas,
+            &image,
+            i64::from(monitor.x) - i64::from(left),
+            i64::from(monitor.y) - i64::from(top),
+        );
+    }
+    Ok(canvas)
+}
+
+/// Scale and position `image` on a canvas of `size` pixels
+fn place(image: &DynamicImage, mode: Mode, size: (u32, u32), background: Color) -> RgbImage {
+    let (width, height) = size;
+    match mode {
+        Mode::Fill | Mode::Span => image.resize_to_fill(width, height, FILTER).to_rgb8(),
+        Mode::Stretch => image.resize_exact(width, height, FILTER).to_rgb8(),
+        Mode::Fit => centered(
+            &image.resize(width, height, FILTER).to_rgb8(),
+            size,
+            background,
+        ),
+        Mode::Center => centered(&image.to_rgb8(), size, background),
+        Mode::Tile => {
+            let mut canvas = RgbImage::new(width, height);
+            imageops::tile(&mut canvas, &image.to_rgb8());
+            canvas
+        }
+    }
+}
+
+/// Put `image` in the middle of a canvas filled with `background`
+fn centered(image: &RgbImage, size: (u32, u32), background: Color) -> RgbImage {
+    let (width, height) = size;
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb(background.0));
+    let x = (i64::from(width) - i64::from(image.width())) / 2;
+    let y = (i64::from(height) - i64::from(image.height())) / 2;
+    imageops::overlay(&mut canvas, image, x, y);
+    canvas
+}
+
+/// Remove all but the most recent renders, always keeping `current`
+fn prune(dir: &Path, current: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut renders: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path() != current)
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect();
+    renders.sort_by_key(|(modified, _)| Reverse(*modified));
+
+    for (_, path) in renders.into_iter().skip(KEEP - 1) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: Color = Color([0, 0, 0]);
+
+    fn monitor(name: &str, x: i32, y: i32, width: u32, height: u32) -> Monitor {
+        Monitor {
+            name: name.to_string(),
+            x,
+            y,
+            width,
+            height,
+            primary: false,
+        }
+    }
+
+    fn solid(color: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb(color)))
+    }
+
+    /// Paint every monitor in its own colour, or leave it out with `None`
+    fn paint_solid(monitors: &[Monitor], colors: &[Option<[u8; 3]>]) -> RgbImage {
+        paint(
+            monitors,
+            &Effects::default(),
+            Mode::Stretch,
+            BACKGROUND,
+            |index| Ok(colors[index].map(solid)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn monitors_are_painted_at_their_offsets() {
+        // The left monitor sits at a negative offset and lower than the right one
+        let monitors = [monitor("L", -100, 20, 100, 50), monitor("R", 0, 0, 60, 40)];
+        let canvas = paint_solid(&monitors, &[Some([255, 0, 0]), Some([0, 0, 255])]);
+
+        assert_eq!(canvas.dimensions(), (160, 70));
+        assert_eq!(canvas.get_pixel(0, 20).0, [255, 0, 0]);
+        assert_eq!(canvas.get_pixel(99, 69).0, [255, 0, 0]);
+        assert_eq!(canvas.get_pixel(100, 0).0, [0, 0, 255]);
+        assert_eq!(canvas.get_pixel(159, 39).0, [0, 0, 255]);
+        // Above the left monitor and below the right one nothing is shown
+        assert_eq!(canvas.get_pixel(0, 19).0, BACKGROUND.0);
+        assert_eq!(canvas.get_pixel(100, 40).0, BACKGROUND.0);
+    }
+
+    #[test]
+    fn gaps_and_monitors_without_a_part_get_the_background() {
+        let monitors = [
+            monitor("A", 0, 0, 40, 30),
+            monitor("B", 50, 0, 40, 30),
+            monitor("C", 100, 0, 40, 30),
+        ];
+        let canvas = paint_solid(&monitors, &[Some([0, 255, 0]), None, Some([0, 255, 0])]);
+
+        assert_eq!(canvas.dimensions(), (140, 30));
+        assert_eq!(canvas.get_pixel(39, 0).0, [0, 255, 0]);
+        assert_eq!(canvas.get_pixel(45, 15).0, BACKGROUND.0);
+        assert_eq!(canvas.get_pixel(70, 15).0, BACKGROUND.0);
+        assert_eq!(canvas.get_pixel(100, 29).0, [0, 255, 0]);
+    }
+
+    #[test]
+    fn stacked_monitors_are_painted_one_above_the_other() {
+        let monitors = [
+            monitor("top", 0, -30, 40, 30),
+            monitor("bottom", 0, 0, 40, 30),
+        ];
+        let canvas = paint_solid(&monitors, &[Some([255, 255, 0]), Some([0, 255, 255])]);
+
+        assert_eq!(canvas.dimensions(), (40, 60));
+        assert_eq!(canvas.get_pixel(20, 29).0, [255, 255, 0]);
+        assert_eq!(canvas.get_pixel(20, 30).0, [0, 255, 255]);
+    }
+
+    #[test]
+    fn parts_are_loaded_in_monitor_order() {
+        let monitors = [monitor("A", 0, 0, 10, 10), monitor("B", 10, 0, 10, 10)];
+        let mut loaded = Vec::new();
+        paint(
+            &monitors,
+            &Effects::default(),
+            Mode::Fill,
+            BACKGROUND,
+            |index| {
+                loaded.push(index);
+                Ok(None)
+            },
+        )
+        .unwrap();
+        assert_eq!(loaded, [0, 1]);
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::Result;
+use rand::SeedableRng;
+use rand::distributions::{Distribution, WeightedError, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::Config;
+use crate::history::History;
+use crate::library::{Filter, Library, Wallpaper};
+use crate::playlist::Playlist;
+use crate::query::Query;
+
+/// Wallpapers shown longer ago than this are no longer penalised
+const RECENCY_WINDOW_SECS: f64 = 24.0 * 60.0 * 60.0;
+/// Weight multiplier for a wallpaper that was just shown
+const MIN_RECENCY_FACTOR: f64 = 0.05;
+/// Weight multiplier for favorites
+const FAVORITE_BOOST: f64 = 2.0;
+/// Rating assumed for wallpapers that have not been rated
+const NEUTRAL_RATING: f64 = 3.0;
+
+/// Which wallpapers `set --random` and `cycle` pick from, and how
+#[derive(Debug, Default)]
+pub struct PickOptions {
+    /// Only pick favorites
+    pub favorites: bool,
+    /// Only pick wallpapers matching this query
+    pub query: Option<Query>,
+    /// Only pick from this playlist
+    pub playlist: Option<String>,
+    /// Weight picks instead of picking uniformly
+    pub weighted: bool,
+    /// Seed for reproducible picks
+    pub seed: Option<u64>,
+}
+
+impl PickOptions {
+    /// Whether anything narrows down the library
+    pub fn is_filtered(&self) -> bool {
+        self.favorites || self.query.is_some() || self.playlist.is_some()
+    }
+
+    /// Wallpapers eligible for picking: the playlist, or the whole library,
+    /// without banned wallpapers and anything not matching the filters or
+    /// the query in the config
+    pub fn candidates(
+        &self,
+        library: &mut Library,
+        playlist: Option<&Playlist>,
+        config: Option<&Config>,
+    ) -> Result<Vec<Wallpaper>> {
+        let wallpapers = match playlist {
+            Some(playlist) => playlist.resolve(library)?,
+            None => library.wallpapers.clone(),
+        };
+
+        let configured = config.map(Config::parsed_query).transpose()?.flatten();
+        let query = match (self.query.clone(), configured) {
+            (Some(query), Some(configured)) => {
+                Some(Query::And(Box::new(configured), Box::new(query)))
+            }
+            (query, configured) => query.or(configured),
+        };
+
+        library.select_from(
+            wallpapers,
+            &Filter {
+                favorites: self.favorites,
+                skip_banned: true,
+                query,
+            },
+        )
+    }
+}
+
+/// Picks random wallpapers, either uniformly or weighted by the user's
+/// ratings, favorites, category weights and how recently each was shown
+pub struct Selector {
+    rng: StdRng,
+    /// Whether picks must be reproducible
+    seeded: bool,
+    weighted: bool,
+    category_weights: BTreeMap<String, f64>,
+}
+
+impl Selector {
+    /// Create a selector; the same `seed` always yields the same picks for the same state
+    pub fn new(seed: Option<u64>, weighted: bool, category_weights: BTreeMap<String, f64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Self {
+            rng,
+            seeded: seed.is_some(),
+            weighted,
+            category_weights,
+        }
+    }
+
+    /// Create a selector from the config, with `weighted` forcing weighted mode on
+    pub fn from_config(config: Option<&Config>, seed: Option<u64>, weighted: bool) -> Self {
+        Self::new(
+            seed,
+            weighted || config.is_some_and(|c| c.weighted),
+            config
+                .map(|c| c.category_weights.clone())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Pick one of `wallpapers`, or `None` if there is nothing with a non-zero weight
+    pub fn pick<'a>(
+        &mut self,
+        wallpapers: &'a [Wallpaper],
+        library: &mut Library,
+        history: &History,
+    ) -> Result<Option<&'a Wallpaper>> {
+        if !self.weighted {
+            return Ok(wallpapers.choose(&mut self.rng));
+        }
+
+        let last_shown = history.last_shown();
+        let now = self.reference_time(&last_shown);
+
+        let mut weights = Vec::with_capacity(wallpapers.len());
+        for wallpaper in wallpapers {
+            let hash = library.hash(wallpaper)?;
+            let entry = library.state.get(&hash);
+            let category_weight = wallpaper
+                .category
+                .as_ref()
+                .and_then(|c| self.category_weights.get(c))
+                .copied()
+                .unwrap_or(1.0);
+
+            weights.push(weight(
+                entry.and_then(|e| e.rating),
+                entry.is_some_and(|e| e.favorite),
+                entry.is_some_and(|e| e.banned),
+                category_weight,
+                last_shown.get(hash.as_str()).map(|shown_at| now - shown_at),
+            ));
+        }
+
+        self.sample(wallpapers, &weights)
+    }
+
+    /// Time recency is measured from
+    ///
+    /// With a seed this is the last time anything was shown rather than the
+    /// clock, so that the same seed and history always give the same picks.
+    fn reference_time(&self, last_shown: &HashMap<&str, i64>) -> i64 {
+        if self.seeded {
+            last_shown.values().max().copied().unwrap_or(0)
+        } else {
+            chrono::Utc::now().timestamp()
+        }
+    }
+
+    /// Pick one of `wallpapers` with the chance given by `weights`, or `None`
+    /// if all weights are zero
+    fn sample<'a>(
+        &mut self,
+        wallpapers: &'a [Wallpaper],
+        weights: &[f64],
+    ) -> Result<Option<&'a Wallpaper>> {
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => Ok(Some(&wallpapers[distribution.sample(&mut self.rng)])),
+            Err(WeightedError::NoItem | WeightedError::AllWeightsZero) => Ok(None),
+            Err(e) => Err(anyhow::anyhow!("Failed to weight wallpapers: {}", e)),
+        }
+    }
+
+    /// Pick `count` wallpapers, all different while there are enough of them
+    pub fn pick_many(
+        &mut self,
+        wallpapers: &[Wallpaper],
+        count: usize,
+        library: &mut Library,
+        history: &History,
+    ) -> Result<Vec<Wallpaper>> {
+        let mut chosen = Vec::with_capacity(count);
+        let mut remaining = wallpapers.to_vec();
+
+        while chosen.len() < count {
+            if remaining.is_empty() {
+                remaining = wallpapers.to_vec();
+            }
+            let Some(wallpaper) = self.pick(&remaining, library, history)?.cloned() else {
+                break;
+            };
+            remaining.retain(|w| w.path != wallpaper.path);
+            chosen.push(wallpaper);
+        }
+
+        Ok(chosen)
+    }
+}
+
+/// Selection weight of a single wallpaper
+///
+/// Unrated wallpapers count as rated 3/5 and wallpapers shown within the last
+/// day are penalised proportionally to how recently they were shown. Banned
+/// wallpapers are never picked, even if they slip past the filters.
+pub fn weight(
+    rating: Option<u8>,
+    favorite: bool,
+    banned: bool,
+    category_weight: f64,
+    seconds_since_shown: Option<i64>,
+) -> f64 {
+    if banned {
+        return 0.0;
+    }
+
+    let rating_factor = rating.map(f64::from).unwrap_or(NEUTRAL_RATING) / NEUTRAL_RATING;
+    let favorite_factor = if favorite { FAVORITE_BOOST } else { 1.0 };
+    let recency_factor = seconds_since_shown
+        .map(|secs| (secs as f64 / RECENCY_WINDOW_SECS).clamp(MIN_RECENCY_FACTOR, 1.0))
+        .unwrap_or(1.0);
+
+    rating_factor * favorite_factor * category_weight.max(0.0) * recency_factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::HistoryEntry;
+    use std::path::PathBuf;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn wallpapers(count: usize) -> Vec<Wallpaper> {
+        (0..count)
+            .map(|i| Wallpaper {
+                path: PathBuf::from(format!("/wallpapers/{}.jpg", i)),
+                name: format!("{}.jpg", i),
+                category: None,
+            })
+            .collect()
+    }
+
+    /// How often each wallpaper is picked in `rounds` seeded picks
+    fn frequencies(weights: &[f64], rounds: usize) -> Vec<f64> {
+        let wallpapers = wallpapers(weights.len());
+        let mut selector = Selector::new(Some(42), true, BTreeMap::new());
+        let mut counts = vec![0; weights.len()];
+        for _ in 0..rounds {
+            let picked = selector.sample(&wallpapers, weights).unwrap().unwrap();
+            let index = wallpapers.iter().position(|w| w.path == picked.path);
+            counts[index.unwrap()] += 1;
+        }
+        counts
+            .into_iter()
+            .map(|count| count as f64 / rounds as f64)
+            .collect()
+    }
+
+    #[test]
+    fn picks_follow_the_weights() {
+        let weights = [
+            weight(None, false, false, 1.0, None),
+            weight(Some(5), true, false, 1.0, None),
+            weight(Some(1), false, false, 1.0, None),
+        ];
+        let total: f64 = weights.iter().sum();
+        for (frequency, weight) in frequencies(&weights, 20_000).iter().zip(weights) {
+            assert!(
+                (frequency - weight / total).abs() < 0.02,
+                "picked {:.3} of the time, expected {:.3}",
+                frequency,
+                weight / total
+            );
+        }
+    }
+
+    #[test]
+    fn zero_weight_and_banned_are_never_picked() {
+        let weights = [
+            weight(None, false, false, 1.0, None),
+            weight(None, false, false, 0.0, None),
+            weight(Some(5), true, true, 1.0, None),
+            weight(None, false, false, 1.0, None),
+        ];
+        let frequencies = frequencies(&weights, 5_000);
+        assert_eq!(frequencies[1], 0.0);
+        assert_eq!(frequencies[2], 0.0);
+        assert!(frequencies[0] > 0.4 && frequencies[3] > 0.4);
+    }
+
+    #[test]
+    fn nothing_is_picked_when_all_weights_are_zero() {
+        let wallpapers = wallpapers(2);
+        let mut selector = Selector::new(Some(1), true, BTreeMap::new());
+        assert!(selector.sample(&wallpapers, &[0.0, 0.0]).unwrap().is_none());
+        assert!(selector.sample(&[], &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn same_seed_gives_same_picks() {
+        let wallpapers = wallpapers(10);
+        let weights = [1.0, 2.0, 3.0, 4.0, 5.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let picks = |seed| {
+            let mut selector = Selector::new(Some(seed), true, BTreeMap::new());
+            (0..50)
+                .map(|_| {
+                    selector
+                        .sample(&wallpapers, &weights)
+                        .unwrap()
+                        .unwrap()
+                        .name
+                        .clone()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+    }
+
+    #[test]
+    fn seeded_recency_does_not_depend_on_the_clock() {
+        let history = History {
+            entries: vec![
+                HistoryEntry {
+                    hash: "a".to_string(),
+                    name: "a.jpg".to_string(),
+                    shown_at: 1_000,
+                },
+                HistoryEntry {
+                    hash: "b".to_string(),
+                    name: "b.jpg".to_string(),
+                    shown_at: 5_000,
+                },
+            ],
+        };
+        let selector = Selector::new(Some(1), true, BTreeMap::new());
+        assert_eq!(selector.reference_time(&history.last_shown()), 5_000);
+
+        let unseeded = Selector::new(None, true, BTreeMap::new());
+        assert!(unseeded.reference_time(&history.last_shown()) > 5_000);
+    }
+
+    #[test]
+    fn recently_shown_wallpapers_are_penalised() {
+        let fresh = weight(None, false, false, 1.0, None);
+        assert_eq!(weight(None, false, false, 1.0, Some(2 * DAY)), fresh);
+        assert_eq!(weight(None, false, false, 1.0, Some(DAY / 2)), fresh / 2.0);
+        assert_eq!(
+            weight(None, false, false, 1.0, Some(0)),
+            fresh * MIN_RECENCY_FACTOR
+        );
+    }
+
+    #[test]
+    fn ratings_favorites_and_categories_scale_the_weight() {
+        assert_eq!(weight(None, false, false, 1.0, None), 1.0);
+        assert_eq!(weight(Some(3), false, false, 1.0, None), 1.0);
+        assert_eq!(weight(Some(5), false, false, 1.0, None), 5.0 / 3.0);
+        assert_eq!(weight(None, true, false, 1.0, None), FAVORITE_BOOST);
+        assert_eq!(weight(None, false, false, 3.0, None), 3.0);
+        assert_eq!(weight(None, false, false, -1.0, None), 0.0);
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::config::{self, Config};
+use crate::display::{self, Screens, Shown};
+use crate::effects::Effects;
+use crate::history;
+use crate::hooks::{self, Event};
+use crate::library::{Library, Wallpaper};
+use crate::palette;
+use crate::render::{self, Color};
+
+/// How a wallpaper is scaled to the screen
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Scale to cover the screen, cropping the edges
+    Fill,
+    /// Scale to fit inside the screen, letterboxing the rest
+    Fit,
+    /// Show at its own size in the middle of the screen
+    Center,
+    /// Repeat from the top-left corner
+    Tile,
+    /// Scale to the screen size, ignoring the aspect ratio
+    Stretch,
+    /// Cover the whole desktop across all screens
+    Span,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Fill => "fill",
+            Mode::Fit => "fit",
+            Mode::Center => "center",
+            Mode::Tile => "tile",
+            Mode::Stretch => "stretch",
+            Mode::Span => "span",
+        }
+    }
+
+    /// The same mode for the desktop backend
+    fn backend(self) -> wallpaper::Mode {
+        match self {
+            Mode::Fill => wallpaper::Mode::Crop,
+            Mode::Fit => wallpaper::Mode::Fit,
+            Mode::Center => wallpaper::Mode::Center,
+            Mode::Tile => wallpaper::Mode::Tile,
+            Mode::Stretch => wallpaper::Mode::Stretch,
+            Mode::Span => wallpaper::Mode::Span,
+        }
+    }
+
+    /// Whether the mode can leave parts of the screen uncovered
+    fn letterboxes(self) -> bool {
+        matches!(self, Mode::Fit | Mode::Center)
+    }
+}
+
+/// How wallpapers look on the screen
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Appearance {
+    /// Scaling mode, or whatever the desktop currently uses
+    pub mode: Option<Mode>,
+    /// Colour around wallpapers that do not cover the screen
+    pub letterbox: Option<Color>,
+    /// Adjustments made to a rendered copy before it is set
+    pub effects: Effects,
+}
+
+impl Appearance {
+    /// Use `mode`, `letterbox` and `effects` when given, and the config otherwise
+    pub fn resolve(
+        mode: Option<Mode>,
+        letterbox: Option<Color>,
+        effects: Option<Effects>,
+        config: Option<&Config>,
+    ) -> Self {
+        Self {
+            mode: mode.or_else(|| config.and_then(|c| c.mode)),
+            letterbox: letterbox.or_else(|| config.and_then(|c| c.letterbox)),
+            effects: effects
+                .or_else(|| config.and_then(|c| c.effects))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Set the desktop wallpaper to `wallpaper`, whose content hash is `hash`
+///
+/// The scaling mode is handed to the desktop when it supports it. Otherwise,
+/// and for letterboxing in a custom colour, a copy sized for the screen is
+/// rendered and set instead. Effects are always applied to a rendered copy.
+/// A custom `setter` command cannot be told the mode, so it always gets a
+/// rendered copy when a mode is given.
+pub fn set_wallpaper(wallpaper: &Wallpaper, hash: &str, appearance: Appearance) -> Result<()> {
+    let config = config::load_config()?;
+    let setter = config.as_ref().and_then(|c| c.setter.as_deref());
+    let effects = appearance.effects;
+    let fit = appearance.mode.filter(|&mode| {
+        let custom_letterbox = mode.letterboxes() && appearance.letterbox.is_some();
+        custom_letterbox || setter.is_some() || wallpaper::set_mode(mode.backend()).is_err()
+    });
+    let previous = display::load_screens()
+        .ok()
+        .and_then(|screens| screens.all)
+        .map(|shown| shown.path);
+
+    if fit.is_none() && effects.is_empty() {
+        set_path(&wallpaper.path, setter)?;
+    } else {
+        let monitors = display::layout(config.as_ref());
+        let size = if appearance.mode == Some(Mode::Span) {
+            let (_, _, width, height) = display::bounds(&monitors);
+            (width, height)
+        } else {
+            display::screen_size(&monitors)
+        };
+        let background = appearance.letterbox.unwrap_or_default();
+        let fit = fit.map(|mode| (mode, background));
+        let rendered = render::render(&wallpaper.path, hash, &effects, fit, size)?;
+
+        if let Some((mode, _)) = fit
+            && setter.is_none()
+        {
+            // The rendered copy matches the screen, so it only needs placing
+            let native = if mode == Mode::Span {
+                Mode::Span
+            } else {
+                Mode::Fill
+            };
+            let _ = wallpaper::set_mode(native.backend());
+        }
+        set_path(&rendered, setter)?;
+    }
+
+    display::save_screens(&Screens {
+        all: Some(shown(wallpaper, hash)),
+        monitors: BTreeMap::new(),
+    })?;
+
+    palette::write_themes(config.as_ref(), wallpaper);
+    hooks::run(
+        config.as_ref(),
+        Event::Set,
+        &hooks::wallpaper_env(wallpaper, previous.as_deref()),
+    );
+    Ok(())
+}
+
+/// Set wallpapers on single monitors, keeping what the others show
+///
+/// `assignments` holds the monitor index, wallpaper and content hash of
+/// each change. All monitors are rendered into one image that the desktop
+/// spans across them, which needs a desktop with a span mode or a custom
+/// `setter` command that can span it.
+pub fn set_on_monitors(
+    assignments: &[(usize, &Wallpaper, &str)],
+    appearance: Appearance,
+) -> Result<()> {
+    let config = config::load_config()?;
+    let setter = config.as_ref().and_then(|c| c.setter.as_deref());
+    let monitors = display::layout(config.as_ref());
+    if let [(_, wallpaper, hash)] = assignments
+        && monitors.len() == 1
+    {
+        return set_wallpaper(wallpaper, hash, appearance);
+    }
+
+    let mut screens = display::load_screens()?;
+    let mut previous = Vec::with_capacity(assignments.len());
+    for &(index, wallpaper, hash) in assignments {
+        let monitor = monitors
+            .get(index)
+            .with_context(|| format!("Monitor {} not found", index))?;
+        previous.push(screens.on(monitor).map(|shown| shown.path.clone()));
+        screens
+            .monitors
+            .insert(monitor.name.clone(), shown(wallpaper, hash));
+    }
+
+    let parts: Vec<Option<(&Path, &str)>> = monitors
+        .iter()
+        .map(|monitor| {
+            screens
+                .on(monitor)
+                .filter(|shown| shown.path.exists())
+                .map(|shown| (shown.path.as_path(), shown.hash.as_str()))
+        })
+        .collect();
+    let mode = appearance
+        .mode
+        .filter(|&mode| mode != Mode::Span)
+        .unwrap_or(Mode::Fill);
+    let background = appearance.letterbox.unwrap_or_default();
+    let composed = render::compose(&parts, &monitors, &appearance.effects, mode, background)?;
+
+    if setter.is_none() {
+        wallpaper::set_mode(Mode::Span.backend())
+            .map_err(|e| anyhow::anyhow!("Failed to span wallpapers across monitors: {}", e))?;
+    }
+    set_path(&composed, setter)?;
+    display::save_screens(&screens)?;
+
+    // Themes follow the first wallpaper that changed
+    if let Some((_, wallpaper, _)) = assignments.first() {
+        palette::write_themes(config.as_ref(), wallpaper);
+    }
+    for (&(index, wallpaper, _), previous) in assignments.iter().zip(&previous) {
+        let mut env = hooks::wallpaper_env(wallpaper, previous.as_deref());
+        env.push(("WCAPP_MONITOR", monitors[index].name.clone()));
+        hooks::run(config.as_ref(), Event::Set, &env);
+    }
+    Ok(())
+}
+
+fn shown(wallpaper: &Wallpaper, hash: &str) -> Shown {
+    Shown {
+        name: wallpaper.name.clone(),
+        path: wallpaper.path.clone(),
+        hash: hash.to_string(),
+    }
+}
+
+/// Hand the image at `path` to the desktop, or to the configured `setter`
+/// command with the path in `WCAPP_PATH`
+fn set_path(path: &Path, setter: Option<&str>) -> Result<()> {
+    let absolute_path = fs::canonicalize(path).context("Failed to get absolute path")?;
+
+    if let Some(command) = setter {
+        let status = hooks::shell(command)
+            .env("WCAPP_PATH", &absolute_path)
+            .stdin(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to run setter `{}`", command))?;
+        if !status.success() {
+            anyhow::bail!("Setter `{}` failed: {}", command, status);
+        }
+        return Ok(());
+    }
+
+    wallpaper::set_from_path(absolute_path.to_str().unwrap())
+        .map_err(|e| anyhow::anyhow!("Failed to set wallpaper: {}", e))?;
+
+    Ok(())
+}
+
+/// Set a wallpaper from the library and add it to the history
+pub fn apply(library: &mut Library, wallpaper: &Wallpaper, appearance: Appearance) -> Result<()> {
+    let hash = library.hash(wallpaper)?;
+    set_wallpaper(wallpaper, &hash, appearance)?;
+
+    history::record(&hash, &wallpaper.name)?;
+    library.save_index()
+}
+
+/// Set wallpapers from the library on single monitors and add them to the history
+pub fn apply_to(
+    library: &mut Library,
+    assignments: &[(usize, Wallpaper)],
+    appearance: Appearance,
+) -> Result<()> {
+    let mut hashes = Vec::with_capacity(assignments.len());
+    for (_, wallpaper) in assignments {
+        hashes.push(library.hash(wallpaper)?);
+    }
+
+    let changes: Vec<(usize, &Wallpaper, &str)> = assignments
+        .iter()
+        .zip(&hashes)
+        .map(|((index, wallpaper), hash)| (*index, wallpaper, hash.as_str()))
+        .collect();
+    set_on_monitors(&changes, appearance)?;
+
+    let mut history = history::load_history()?;
+    for (_, wallpaper, hash) in &changes {
+        history.record(hash, &wallpaper.name);
+    }
+    history::save_history(&history)?;
+    library.save_index()
+}
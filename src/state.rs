@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+
+/// User data attached to wallpapers, keyed by content hash so it survives
+/// renames and re-fetches
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct State {
+    #[serde(default)]
+    pub wallpapers: BTreeMap<String, WallpaperState>,
+}
+
+/// Favorite, rating, ban and tag markers for a single wallpaper
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct WallpaperState {
+    /// Last known name, only used for display
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub banned: bool,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+}
+
+/// The form tags are stored and compared in: trimmed and lowercase
+pub fn normalize_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        anyhow::bail!("Tags cannot be empty");
+    }
+    Ok(tag.to_lowercase())
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl WallpaperState {
+    fn is_empty(&self) -> bool {
+        !self.favorite && self.rating.is_none() && !self.banned && self.tags.is_empty()
+    }
+}
+
+impl State {
+    pub fn is_empty(&self) -> bool {
+        self.wallpapers.is_empty()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&WallpaperState> {
+        self.wallpapers.get(hash)
+    }
+
+    /// Update the entry for `hash`, dropping it again if nothing is left set
+    pub fn update(&mut self, hash: &str, name: &str, f: impl FnOnce(&mut WallpaperState)) {
+        let entry = self.wallpapers.entry(hash.to_string()).or_default();
+        entry.name = name.to_string();
+        f(entry);
+
+        if entry.is_empty() {
+            self.wallpapers.remove(hash);
+        }
+    }
+}
+
+/// Get the path to the state file
+pub fn get_state_path() -> Result<PathBuf> {
+    config::locate(config::get_state_dir()?, "state.json")
+}
+
+/// Load the state file, starting fresh if it does not exist yet
+pub fn load_state() -> Result<State> {
+    let state_path = get_state_path()?;
+    if !state_path.exists() {
+        return Ok(State::default());
+    }
+
+    let content = fs::read_to_string(&state_path)
+        .with_context(|| format!("Failed to read {}", state_path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", state_path.display()))
+}
+
+/// Save the state file
+pub fn save_state(state: &State) -> Result<()> {
+    let state_path = get_state_path()?;
+    let content = serde_json::to_string_pretty(state).context("Failed to serialize state")?;
+    fs::write(state_path, content).context("Failed to write state file")?;
+    Ok(())
+}
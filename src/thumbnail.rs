@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::render;
+
+/// Longest side of a thumbnail, in pixels
+const MAX_SIZE: u32 = 480;
+/// JPEG quality of thumbnails, from 1 to 100
+const QUALITY: u8 = 80;
+
+/// Get the directory caching thumbnails served by the API, creating it if needed
+pub fn get_thumbnails_dir() -> Result<PathBuf> {
+    let dir = config::locate(config::get_cache_dir()?, "thumbnails")?;
+    fs::create_dir_all(&dir).context("Failed to create thumbnails directory")?;
+    Ok(dir)
+}
+
+/// Write a JPEG thumbnail of `source` to `dest`
+///
+/// Thumbnails are named after the content hash of their source by callers,
+/// so an existing `dest` is up to date and is left alone. Returns whether a
+/// new thumbnail was written.
+pub fn ensure(source: &Path, dest: &Path) -> Result<bool> {
+    if dest.exists() {
+        return Ok(false);
+    }
+
+    let image = render::open(source)?
+        .thumbnail(MAX_SIZE, MAX_SIZE)
+        .to_rgb8();
+    render::save_jpeg(&image, dest, QUALITY)?;
+
+    Ok(true)
+}